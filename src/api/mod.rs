@@ -1,37 +1,230 @@
+use std::str::FromStr;
 use std::sync::Arc;
 
+use ::actix::prelude::*;
+use parking_lot::RwLock;
+
+use futures03::compat::Future01CompatExt;
+
+use crate::consensus::events::ConsensusStatusEvent;
+use crate::consensus::pbft::core::core::Core;
 use crate::core::chain::Chain;
-use crate::types::block::Blocks;
+use crate::core::ledger::Ledger;
+use crate::core::tx_pool::SafeTxPool;
+use crate::types::{Height, block::Block};
 
 use http::StatusCode;
 use tide::{body, head, configuration::{Configuration, Environment}, App, AppData};
 
-async fn blocks(mut chain: AppData<Arc<Chain>>) -> String {
-    let state: &Arc<Chain> = &chain.0;
-    let last_height = state.get_last_height();
-    let mut blocks: Blocks = Blocks(vec![]);
-    (0..last_height + 1).for_each(|height| {
-        let block_hash = state.get_block_hash_by_height(height).unwrap();
-        let block = state.get_block_by_hash(&block_hash).unwrap();
-        blocks.0.push(block);
-    });
-    serde_json::to_string(&blocks).unwrap()
+pub mod rpc;
+pub mod stream;
+
+pub const DEFAULT_PAGE_LIMIT: u64 = 20;
+pub const MAX_PAGE_LIMIT: u64 = 100;
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub chain: Arc<Chain>,
+    pub tx_pool: Arc<RwLock<SafeTxPool>>,
+    pub events: Option<Addr<stream::EventStream>>,
+    pub core: Addr<Core>,
 }
 
-async fn transactions(mut chain: AppData<Arc<Chain>>) -> String {
-    let state: &Arc<Chain> = &chain.0;
+/// Query of `/blocks?from=&limit=`, the unknown keys are ignored
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlocksQuery {
+    pub from: Height,
+    pub limit: u64,
+}
+
+impl Default for BlocksQuery {
+    fn default() -> Self {
+        BlocksQuery {
+            from: 0,
+            limit: DEFAULT_PAGE_LIMIT,
+        }
+    }
+}
+
+impl FromStr for BlocksQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut query = BlocksQuery::default();
+        for pair in s.split('&').filter(|pair| !pair.is_empty()) {
+            let mut kv = pair.splitn(2, '=');
+            let (key, value) = (kv.next().unwrap(), kv.next().unwrap_or(""));
+            if value.is_empty() {
+                continue;
+            }
+            match key {
+                "from" => query.from = value.parse().map_err(|_| format!("invalid from: {}", value))?,
+                "limit" => query.limit = value.parse().map_err(|_| format!("invalid limit: {}", value))?,
+                _ => {}
+            }
+        }
+        if query.limit == 0 {
+            return Err("limit should be greater than 0".to_string());
+        }
+        query.limit = query.limit.min(MAX_PAGE_LIMIT);
+        Ok(query)
+    }
+}
+
+/// A page of blocks, `next` is the cursor of the next page, it is none if the page reaches the tip
+#[derive(Debug, Serialize)]
+pub struct BlockPage {
+    pub blocks: Vec<Block>,
+    pub next: Option<Height>,
+}
+
+fn block_page(ledger: &Ledger, query: &BlocksQuery) -> BlockPage {
+    let last_height = *ledger.get_last_block_height();
+    let mut blocks = vec![];
+    let mut height = query.from;
+    while height <= last_height && (blocks.len() as u64) < query.limit {
+        match ledger.get_block_by_height(height) {
+            Some(block) => blocks.push(block),
+            None => match ledger.get_pruned_end(height) {
+                // the blocks below the checkpoint are not downloaded, the cursor moves past them
+                Some(checkpoint) => {
+                    height = checkpoint;
+                    continue;
+                }
+                None => break,
+            },
+        }
+        height += 1;
+    }
+    let next = if height <= last_height { Some(height) } else { None };
+    BlockPage { blocks, next }
+}
+
+async fn blocks(mut state: AppData<ApiState>, query: head::UrlQuery<BlocksQuery>) -> String {
+    let chain: &Arc<Chain> = &state.0.chain;
+    serde_json::to_string(&block_page(&chain.get_ledger().read(), &query.0)).unwrap()
+}
+
+async fn block_by_height(mut state: AppData<ApiState>, height: head::Path<Height>) -> Result<String, StatusCode> {
+    let chain: &Arc<Chain> = &state.0.chain;
+    let block = chain.get_block_by_height(height.0).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(serde_json::to_string(&block).unwrap())
+}
+
+async fn block_by_hash(mut state: AppData<ApiState>, block_hash: head::Path<String>) -> Result<String, StatusCode> {
+    let chain: &Arc<Chain> = &state.0.chain;
+    let block_hash = rpc::hex_to_hash(&block_hash.0).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let block = chain.get_block_by_hash(&block_hash).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(serde_json::to_string(&block).unwrap())
+}
+
+async fn block_transactions(mut state: AppData<ApiState>, height: head::Path<Height>) -> Result<String, StatusCode> {
+    let chain: &Arc<Chain> = &state.0.chain;
+    let block = chain.get_block_by_height(height.0).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(serde_json::to_string(block.transactions()).unwrap())
+}
+
+async fn transactions(mut state: AppData<ApiState>) -> String {
+    let state: &Arc<Chain> = &state.0.chain;
     let mut transactions = state.get_transactions();
     serde_json::to_string(&transactions).unwrap()
 }
 
-pub fn start_api(chain: Arc<Chain>, ip: String, port: u16) {
-    let mut app = App::new(chain);
+async fn transaction_by_hash(mut state: AppData<ApiState>, tx_hash: head::Path<String>) -> Result<String, StatusCode> {
+    let chain: &Arc<Chain> = &state.0.chain;
+    let tx_hash = rpc::hex_to_hash(&tx_hash.0).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let transaction = chain.get_transaction(&tx_hash).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(serde_json::to_string(&transaction).unwrap())
+}
+
+async fn transaction_proof(mut state: AppData<ApiState>, tx_hash: head::Path<String>) -> Result<String, StatusCode> {
+    let tx_hash = rpc::hex_to_hash(&tx_hash.0).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let proof = rpc::transaction_proof(&state.0, &tx_hash).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(proof.to_string())
+}
+
+async fn consensus_status(mut state: AppData<ApiState>) -> Result<String, StatusCode> {
+    let status = await!(state.0.core.send(ConsensusStatusEvent {}).compat()).map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(serde_json::to_string(&status).unwrap())
+}
+
+async fn metrics() -> String {
+    crate::metrics::gather()
+}
+
+async fn json_rpc(state: AppData<ApiState>, body: body::Str) -> String {
+    let response = await!(rpc::handle(state.0.clone(), body.0));
+    serde_json::to_string(&response).unwrap()
+}
+
+pub fn start_api(state: ApiState, ip: String, port: u16) {
+    let mut app = App::new(state);
     app.at("/blocks").get(blocks);
+    app.at("/blocks/{}").get(block_by_height);
+    app.at("/blocks/hash/{}").get(block_by_hash);
+    app.at("/blocks/{}/transactions").get(block_transactions);
     app.at("/transactions").get(transactions);
+    app.at("/transactions/{}").get(transaction_by_hash);
+    app.at("/transactions/{}/proof").get(transaction_proof);
+    app.at("/consensus/status").get(consensus_status);
+    app.at("/metrics").get(metrics);
+    app.at("/rpc").post(json_rpc);
     app.config(Configuration {
         env: Environment::Production,
         address: ip,
         port: port,
     });
     app.serve();
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::Address;
+    use lru_time_cache::LruCache;
+    use crate::common::random_dir;
+    use crate::config::StoreConfig;
+    use crate::core::ledger::LastMeta;
+    use crate::store::schema::{open_database, Schema};
+    use crate::types::block::Header;
+
+    fn new_block(height: Height) -> Block {
+        Block::new(Header::new_mock(EMPTY_HASH, Address::from(10), EMPTY_HASH, height, 100, None), vec![])
+    }
+
+    #[test]
+    fn t_blocks_query() {
+        assert_eq!(BlocksQuery::from_str("").unwrap(), BlocksQuery::default());
+        assert_eq!(BlocksQuery::from_str("from=10&limit=5").unwrap(), BlocksQuery { from: 10, limit: 5 });
+        assert_eq!(BlocksQuery::from_str("limit=1000&other=1").unwrap(), BlocksQuery { from: 0, limit: MAX_PAGE_LIMIT });
+        assert_eq!(BlocksQuery::from_str("from=&limit=").unwrap(), BlocksQuery::default());
+        assert!(BlocksQuery::from_str("from=abc").is_err());
+        assert!(BlocksQuery::from_str("limit=0").is_err());
+    }
+
+    #[test]
+    fn t_block_page_checkpoint() {
+        let database = open_database(&random_dir(), &StoreConfig::default()).unwrap();
+        let mut ledger = Ledger::new(
+            LastMeta::new_zero(),
+            LruCache::with_capacity(1 << 10),
+            LruCache::with_capacity(1 << 10),
+            vec![],
+            Schema::new(Arc::new(database)),
+        );
+        ledger.add_genesis_block(&new_block(0));
+        (1..3).for_each(|height| ledger.add_block(&new_block(height)));
+        // the heights 3..10 are skipped by the checkpoint sync
+        ledger.import_checkpoint(&new_block(10), vec![], vec![]);
+        (11..13).for_each(|height| ledger.add_block(&new_block(height)));
+
+        let heights = |page: &BlockPage| page.blocks.iter().map(|block| block.height()).collect::<Vec<_>>();
+        let page = block_page(&ledger, &BlocksQuery { from: 0, limit: 4 });
+        assert_eq!(heights(&page), vec![0, 1, 2, 10]);
+        assert_eq!(page.next, Some(11));
+        let page = block_page(&ledger, &BlocksQuery { from: 5, limit: 4 });
+        assert_eq!(heights(&page), vec![10, 11, 12]);
+        assert_eq!(page.next, None);
+    }
+}
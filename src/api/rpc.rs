@@ -0,0 +1,324 @@
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::storage::values::StorageValue;
+use futures03::compat::Future01CompatExt;
+use serde_json::Value;
+
+use crate::{
+    common::{decode, merkle::MerkleProof, string_to_address},
+    consensus::events::ConsensusStatusEvent,
+    store::schema::TxLocation,
+    types::{Height, transaction::Transaction},
+};
+use super::{ApiState, stream::PendingTransaction};
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+// the method is known but can't be served by the node
+pub const NOT_SUPPORTED: i64 = -32000;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new<S: Into<String>>(code: i64, message: S) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn ok(id: Value, result: Value) -> Self {
+        Response {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn err(id: Value, error: RpcError) -> Self {
+        Response {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
+
+pub type RpcResult = Result<Value, RpcError>;
+
+/// handle parses a JSON-RPC 2.0 request and dispatches it to the method
+pub async fn handle(state: ApiState, body: String) -> Response {
+    let state = &state;
+    let request: Request = match serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(err) => return Response::err(Value::Null, RpcError::new(PARSE_ERROR, err.to_string())),
+    };
+    if request.jsonrpc != JSONRPC_VERSION {
+        return Response::err(request.id, RpcError::new(INVALID_REQUEST, "jsonrpc version should be 2.0"));
+    }
+    debug!("Receive a rpc request, method: {}", request.method);
+    let result = match request.method.as_str() {
+        "sendRawTransaction" => send_raw_transaction(state, &request.params),
+        "getTransactionByHash" => get_transaction_by_hash(state, &request.params),
+        "getBlockByHeight" => get_block_by_height(state, &request.params),
+        "getBlockByHash" => get_block_by_hash(state, &request.params),
+        "getLatestHeight" => Ok(json!(state.chain.get_last_height())),
+        "getValidators" => get_validators(state, &request.params),
+        "getAccount" => get_account(state, &request.params),
+        "getReceipt" => get_receipt(state, &request.params),
+        "getTransactionProof" => get_transaction_proof(state, &request.params),
+        "getConsensusStatus" => await!(state.core.send(ConsensusStatusEvent {}).compat())
+            .map(|status| json!(status))
+            .map_err(|err| RpcError::new(INTERNAL_ERROR, err.to_string())),
+        other => Err(RpcError::new(METHOD_NOT_FOUND, format!("method {} not found", other))),
+    };
+    match result {
+        Ok(value) => Response::ok(request.id, value),
+        Err(err) => Response::err(request.id, err),
+    }
+}
+
+fn send_raw_transaction(state: &ApiState, params: &[Value]) -> RpcResult {
+    let raw = param_bytes(params, 0)?;
+    let mut transaction: Transaction = decode(&raw).map_err(|_| RpcError::new(INVALID_PARAMS, "invalid raw transaction"))?;
+    if !transaction.verify_sign(state.chain.config.chain_id) {
+        return Err(RpcError::new(INVALID_PARAMS, "invalid transaction signature"));
+    }
+    let tx_hash = transaction.hash();
+    transaction.set_hash(tx_hash);
+    if state.chain.get_transaction(&tx_hash).is_some() {
+        return Err(RpcError::new(INVALID_PARAMS, "transaction has been committed"));
+    }
+    state.tx_pool
+        .write()
+        .add_local_tx(transaction.clone())
+        .map_err(|err| RpcError::new(INTERNAL_ERROR, err.to_string()))?;
+    if let Some(ref events) = state.events {
+        events.do_send(PendingTransaction(transaction));
+    }
+    Ok(json!(hash_to_hex(&tx_hash)))
+}
+
+fn get_transaction_by_hash(state: &ApiState, params: &[Value]) -> RpcResult {
+    let tx_hash = param_hash(params, 0)?;
+    let transaction = match state.chain.get_transaction(&tx_hash) {
+        Some(transaction) => transaction,
+        None => return Ok(Value::Null),
+    };
+    let location = state.chain.get_transaction_location(&tx_hash);
+    Ok(json!({
+        "hash": hash_to_hex(&tx_hash),
+        "transaction": transaction,
+        "block_hash": location.as_ref().map(|location| hash_to_hex(&location.block_hash)),
+        "height": location.as_ref().map(|location| location.block_height),
+        "index": location.as_ref().map(|location| location.position_in_block),
+    }))
+}
+
+fn get_block_by_height(state: &ApiState, params: &[Value]) -> RpcResult {
+    let height = param_height(params, 0)?;
+    Ok(json!(state.chain.get_block_by_height(height)))
+}
+
+fn get_block_by_hash(state: &ApiState, params: &[Value]) -> RpcResult {
+    let block_hash = param_hash(params, 0)?;
+    Ok(json!(state.chain.get_block_by_hash(&block_hash)))
+}
+
+fn get_validators(state: &ApiState, params: &[Value]) -> RpcResult {
+    let height = if params.is_empty() {
+        state.chain.get_last_height()
+    } else {
+        param_height(params, 0)?
+    };
+    let validators: Vec<String> = state.chain
+        .get_validators(height)
+        .iter()
+        .map(|validator| format!("{:?}", validator.address()))
+        .collect();
+    Ok(json!(validators))
+}
+
+/// The ledger doesn't execute transactions, so there is no account state to serve
+fn get_account(_state: &ApiState, params: &[Value]) -> RpcResult {
+    let address = param_str(params, 0)?;
+    string_to_address(&address.to_string()).map_err(|err| RpcError::new(INVALID_PARAMS, err))?;
+    Err(RpcError::new(NOT_SUPPORTED, "getAccount is not supported: the ledger doesn't track the account state"))
+}
+
+/// Transactions are not executed, so the receipt only records where the transaction was committed
+fn get_receipt(state: &ApiState, params: &[Value]) -> RpcResult {
+    let tx_hash = param_hash(params, 0)?;
+    Ok(state.chain.get_transaction_location(&tx_hash).map_or(Value::Null, |location| {
+        json!({
+            "transaction_hash": hash_to_hex(&tx_hash),
+            "block_hash": hash_to_hex(&location.block_hash),
+            "height": location.block_height,
+            "index": location.position_in_block,
+            "status": "committed",
+        })
+    }))
+}
+
+/// The proof is verified by `light::verify_transaction` against the `tx_hash` of the block header
+fn get_transaction_proof(state: &ApiState, params: &[Value]) -> RpcResult {
+    let tx_hash = param_hash(params, 0)?;
+    Ok(transaction_proof(state, &tx_hash).unwrap_or(Value::Null))
+}
+
+/// transaction_proof returns everything to verify the inclusion without trusting the node:
+/// the encoded transaction (the merkle leaf), the transaction root of the block and the sibling path
+pub fn transaction_proof(state: &ApiState, tx_hash: &Hash) -> Option<Value> {
+    let transaction = state.chain.get_transaction(tx_hash)?;
+    let (location, proof) = state.chain.get_transaction_proof(tx_hash)?;
+    let header = state.chain.get_header_by_height(location.block_height)?;
+    Some(proof_to_json(tx_hash, transaction, &location, &header.tx_hash, &proof))
+}
+
+fn proof_to_json(tx_hash: &Hash, transaction: Transaction, location: &TxLocation, tx_root: &Hash, proof: &MerkleProof) -> Value {
+    let path: Vec<String> = proof.path.iter().map(hash_to_hex).collect();
+    json!({
+        "transaction_hash": hash_to_hex(tx_hash),
+        "raw": format!("0x{}", hex::encode(transaction.into_bytes())),
+        "block_hash": hash_to_hex(&location.block_hash),
+        "height": location.block_height,
+        "tx_root": hash_to_hex(tx_root),
+        "proof": {
+            "index": proof.index,
+            "leaves": proof.leaves,
+            "path": path,
+        },
+    })
+}
+
+pub fn hash_to_hex(hash: &Hash) -> String {
+    format!("0x{}", hex::encode(hash.as_ref()))
+}
+
+pub fn hex_to_bytes(s: &str) -> Result<Vec<u8>, String> {
+    let s = if s.starts_with("0x") { &s[2..] } else { s };
+    hex::decode(s).map_err(|err| err.to_string())
+}
+
+pub fn hex_to_hash(s: &str) -> Result<Hash, String> {
+    let bytes = hex_to_bytes(s)?;
+    Hash::from_slice(&bytes).ok_or_else(|| "invalid hash length".to_string())
+}
+
+fn param_str(params: &[Value], idx: usize) -> Result<&str, RpcError> {
+    params.get(idx)
+        .and_then(|value| value.as_str())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("params[{}] should be a string", idx)))
+}
+
+fn param_bytes(params: &[Value], idx: usize) -> Result<Vec<u8>, RpcError> {
+    hex_to_bytes(param_str(params, idx)?).map_err(|err| RpcError::new(INVALID_PARAMS, err))
+}
+
+fn param_hash(params: &[Value], idx: usize) -> Result<Hash, RpcError> {
+    hex_to_hash(param_str(params, idx)?).map_err(|err| RpcError::new(INVALID_PARAMS, err))
+}
+
+fn param_height(params: &[Value], idx: usize) -> Result<Height, RpcError> {
+    params.get(idx)
+        .and_then(|value| value.as_u64())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("params[{}] should be a height", idx)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_request_serde() {
+        let request: Request = serde_json::from_str(r#"{"jsonrpc":"2.0","id":1,"method":"getBlockByHeight","params":[10]}"#).unwrap();
+        assert_eq!(request.method, "getBlockByHeight");
+        assert_eq!(param_height(&request.params, 0).unwrap(), 10);
+        assert!(param_hash(&request.params, 0).is_err());
+
+        let request: Request = serde_json::from_str(r#"{"jsonrpc":"2.0","id":"a","method":"getLatestHeight"}"#).unwrap();
+        assert!(request.params.is_empty());
+    }
+
+    #[test]
+    fn t_response_serde() {
+        let response = Response::ok(json!(1), json!(100));
+        assert_eq!(serde_json::to_string(&response).unwrap(), r#"{"jsonrpc":"2.0","id":1,"result":100}"#);
+        let response = Response::err(Value::Null, RpcError::new(PARSE_ERROR, "parse error"));
+        assert_eq!(serde_json::to_string(&response).unwrap(), r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"parse error"}}"#);
+    }
+
+    #[test]
+    fn t_proof_to_json() {
+        use cryptocurrency_kit::ethkey::{Address, Generator, Random};
+        use crate::common::{merkle_tree, merkle_tree_proof};
+        use crate::light::verify_inclusion;
+
+        let key_pair = Random.generate().unwrap();
+        let transactions: Vec<Transaction> = (0..3).map(|nonce| {
+            let mut transaction = Transaction::new(nonce, Address::from(100), 10, 10, 1, vec![1, 2, 3]);
+            transaction.sign(10, key_pair.secret());
+            transaction
+        }).collect();
+        let tx_root = merkle_tree(transactions.clone()).root();
+        let proof = merkle_tree_proof(transactions.clone(), 2).unwrap();
+        let tx_hash = transactions[2].hash();
+        let location = TxLocation { block_hash: tx_root, block_height: 10, position_in_block: 2 };
+        let value = proof_to_json(&tx_hash, transactions[2].clone(), &location, &tx_root, &proof);
+
+        assert_eq!(value["transaction_hash"], json!(hash_to_hex(&tx_hash)));
+        assert_eq!(value["height"], json!(10));
+        assert_eq!(value["proof"]["index"], json!(2));
+        assert_eq!(value["proof"]["leaves"], json!(3));
+
+        // the client verifies the inclusion by the json only
+        let raw = hex_to_bytes(value["raw"].as_str().unwrap()).unwrap();
+        let root = hex_to_hash(value["tx_root"].as_str().unwrap()).unwrap();
+        let path = value["proof"]["path"].as_array().unwrap()
+            .iter()
+            .map(|sibling| hex_to_hash(sibling.as_str().unwrap()).unwrap())
+            .collect();
+        let proof = MerkleProof { index: 2, leaves: 3, path };
+        assert!(verify_inclusion(&root, &raw, &proof).is_ok());
+    }
+
+    #[test]
+    fn t_hex_hash() {
+        let hash = CryptoHash::hash(&vec![1_u8, 2, 3]);
+        assert_eq!(hex_to_hash(&hash_to_hex(&hash)).unwrap(), hash);
+        assert!(hex_to_hash("0x0102").is_err());
+        assert!(hex_to_hash("zz").is_err());
+    }
+}
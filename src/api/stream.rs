@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::actix::prelude::*;
+use cryptocurrency_kit::crypto::CryptoHash;
+
+use crate::{
+    core::chain::Chain,
+    subscriber::events::ChainEvent,
+    types::transaction::Transaction,
+};
+use super::rpc::hash_to_hex;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_HEAD_SIZE: u64 = 8 << 10;
+const EVENTS_PATH: &str = "/events";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    NewHeads,
+    NewBlocks,
+    PendingTransactions,
+    Finalized,
+}
+
+impl Topic {
+    pub fn all() -> HashSet<Topic> {
+        vec![Topic::NewHeads, Topic::NewBlocks, Topic::PendingTransactions, Topic::Finalized].into_iter().collect()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Topic::NewHeads => "newHeads",
+            Topic::NewBlocks => "newBlocks",
+            Topic::PendingTransactions => "pendingTransactions",
+            Topic::Finalized => "finalized",
+        }
+    }
+}
+
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newHeads" => Ok(Topic::NewHeads),
+            "newBlocks" => Ok(Topic::NewBlocks),
+            "pendingTransactions" => Ok(Topic::PendingTransactions),
+            "finalized" => Ok(Topic::Finalized),
+            other => Err(format!("unknown topic {}", other)),
+        }
+    }
+}
+
+/// parse_topics parses the query of `/events?topics=newHeads,finalized`, no topics means all of them
+pub fn parse_topics(query: &str) -> Result<HashSet<Topic>, String> {
+    let mut topics = HashSet::new();
+    for pair in query.split('&') {
+        let mut kv = pair.splitn(2, '=');
+        if kv.next() != Some("topics") {
+            continue;
+        }
+        for topic in kv.next().unwrap_or("").split(',').filter(|topic| !topic.is_empty()) {
+            topics.insert(Topic::from_str(topic)?);
+        }
+    }
+    if topics.is_empty() {
+        return Ok(Topic::all());
+    }
+    Ok(topics)
+}
+
+#[derive(Message)]
+pub struct NewClient {
+    pub stream: TcpStream,
+    pub topics: HashSet<Topic>,
+}
+
+#[derive(Message, Clone, Debug)]
+pub struct PendingTransaction(pub Transaction);
+
+struct Client {
+    stream: TcpStream,
+    topics: HashSet<Topic>,
+}
+
+/// EventStream pushes the chain events to the clients by server-sent events,
+/// every event is a frame of `event: <topic>\ndata: <json>\n\n`
+pub struct EventStream {
+    chain: Arc<Chain>,
+    clients: Vec<Client>,
+}
+
+impl Actor for EventStream {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.chain.subscriber_event(ctx.address().recipient());
+        ctx.run_interval(KEEP_ALIVE_INTERVAL, |act, _| {
+            act.write_frame(None, ": keep-alive\n\n");
+        });
+        info!("Event stream has started");
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!("Event stream has stopped");
+    }
+}
+
+impl Handler<ChainEvent> for EventStream {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChainEvent, _ctx: &mut Self::Context) {
+        match msg {
+            ChainEvent::NewHeader(header) => {
+                self.publish(Topic::NewHeads, &header);
+            }
+            ChainEvent::NewBlock(block) => {
+                self.publish(Topic::NewBlocks, &block);
+                // a committed block of pbft is final
+                let finalized = json!({
+                    "height": block.height(),
+                    "hash": hash_to_hex(&block.hash()),
+                });
+                self.publish(Topic::Finalized, &finalized);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Handler<PendingTransaction> for EventStream {
+    type Result = ();
+
+    fn handle(&mut self, msg: PendingTransaction, _ctx: &mut Self::Context) {
+        let transaction = json!({
+            "hash": hash_to_hex(&msg.0.hash()),
+            "transaction": msg.0,
+        });
+        self.publish(Topic::PendingTransactions, &transaction);
+    }
+}
+
+impl Handler<NewClient> for EventStream {
+    type Result = ();
+
+    fn handle(&mut self, msg: NewClient, _ctx: &mut Self::Context) {
+        let mut stream = msg.stream;
+        let header = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\nAccess-Control-Allow-Origin: *\r\n\r\n";
+        if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() || stream.write_all(header.as_bytes()).is_err() {
+            return;
+        }
+        debug!("New event stream client, peer: {:?}", stream.peer_addr());
+        self.clients.push(Client { stream, topics: msg.topics });
+    }
+}
+
+impl EventStream {
+    pub fn new(chain: Arc<Chain>) -> Self {
+        EventStream { chain, clients: vec![] }
+    }
+
+    fn publish<T: ::serde::Serialize>(&mut self, topic: Topic, data: &T) {
+        let frame = format!("event: {}\ndata: {}\n\n", topic.as_str(), serde_json::to_string(data).unwrap());
+        self.write_frame(Some(topic), &frame);
+    }
+
+    /// write_frame sends the frame to the clients which subscribe the topic, the broken clients are removed
+    fn write_frame(&mut self, topic: Option<Topic>, frame: &str) {
+        self.clients.retain(|client| {
+            if topic.map_or(false, |topic| !client.topics.contains(&topic)) {
+                return true;
+            }
+            (&client.stream).write_all(frame.as_bytes()).is_ok()
+        });
+    }
+}
+
+/// start_event_stream listens on the address and hands the `GET /events` connections to the stream actor,
+/// every connection reads its request head on its own thread, so a slow client can't stall the others
+pub fn start_event_stream(chain: Arc<Chain>, ip: String, port: u16) -> io::Result<Addr<EventStream>> {
+    let listener = TcpListener::bind((ip.as_str(), port))?;
+    info!("Event stream listen on {}:{}", ip, port);
+    let addr = EventStream::new(chain).start();
+    let event_stream = addr.clone();
+    ::std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let event_stream = event_stream.clone();
+                    ::std::thread::spawn(move || accept(stream, &event_stream));
+                }
+                Err(err) => debug!("Failed to accept event stream client, err: {}", err),
+            }
+        }
+    });
+    Ok(addr)
+}
+
+fn accept(mut stream: TcpStream, event_stream: &Addr<EventStream>) {
+    if let Err(err) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+        debug!("Failed to set the read timeout of event stream client, err: {}", err);
+        return;
+    }
+    let target = match read_request_target(&stream) {
+        Some(target) => target,
+        None => return,
+    };
+    let (path, query) = match target.find('?') {
+        Some(idx) => (&target[..idx], &target[idx + 1..]),
+        None => (target.as_str(), ""),
+    };
+    if path != EVENTS_PATH {
+        let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        return;
+    }
+    match parse_topics(query) {
+        Ok(topics) => event_stream.do_send(NewClient { stream, topics }),
+        Err(err) => {
+            let _ = stream.write_all(format!("HTTP/1.1 400 Bad Request\r\nContent-Length: {}\r\n\r\n{}", err.len(), err).as_bytes());
+        }
+    }
+}
+
+/// read_request_target reads the request head (at most `MAX_REQUEST_HEAD_SIZE`) and returns the target of `GET <target> HTTP/1.1`
+fn read_request_target<R: Read>(stream: R) -> Option<String> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_HEAD_SIZE));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    // drain the headers
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(n) if n > 0 && line != "\r\n" && line != "\n" => continue,
+            _ => break,
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Some(target.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_parse_topics() {
+        assert_eq!(parse_topics("").unwrap(), Topic::all());
+        assert_eq!(parse_topics("topics=").unwrap(), Topic::all());
+        let topics = parse_topics("topics=newHeads,finalized").unwrap();
+        assert_eq!(topics.len(), 2);
+        assert!(topics.contains(&Topic::NewHeads));
+        assert!(topics.contains(&Topic::Finalized));
+        assert!(parse_topics("topics=newHeads,unknown").is_err());
+    }
+
+    #[test]
+    fn t_read_request_target() {
+        let request = b"GET /events?topics=newHeads HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert_eq!(read_request_target(&request[..]).unwrap(), "/events?topics=newHeads");
+        assert!(read_request_target(&b"POST /events HTTP/1.1\r\n\r\n"[..]).is_none());
+        let endless = io::repeat(b'a');
+        assert!(read_request_target(endless).is_none());
+    }
+}
@@ -1,5 +1,6 @@
 use std::fs::File;
 use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
@@ -10,7 +11,6 @@ use ::actix::prelude::*;
 use cryptocurrency_kit::crypto::Hash;
 use cryptocurrency_kit::ethkey::{Generator, KeyPair, Secret, Random};
 use futures::Future;
-use kvdb_rocksdb::Database;
 use libp2p::{Multiaddr, PeerId};
 use lru_time_cache::LruCache;
 use parking_lot::RwLock;
@@ -19,27 +19,39 @@ use crate::{
     common,
     config::Config,
     consensus::pbft::core::core::{Core, handle_msg_middle},
+    consensus::backend::new_impl_backend,
     consensus::consensus::{create_bft_engine, SafeEngine},
     core::chain::Chain,
     core::ledger::{LastMeta, Ledger},
     core::tx_pool::{BaseTxPool, TxPool, SafeTxPool},
+    core::tx_journal::TxJournal,
     error::ChainResult,
     logger::init_log,
     minner::Minner,
     p2p::{
         protocol::Payload,
         discover_service::DiscoverService,
-        server::{author_handshake, TcpServer},
+        config::NodeMode,
+        kad::{parse_bootnode, KadDiscovery},
+        reputation::Reputation,
+        secure::NodeKey,
+        server::{author_handshake, handshake_packet},
+        snapshot::Checkpoint,
+        sync::Synchronizer,
+        transport::TransportContext,
         spawn_sync_subscriber,
     },
     pprof::spawn_signal_handler,
-    store::schema::Schema,
+    store::schema::{open_database, Schema},
     subscriber::events::{BroadcastEventSubscriber, ChainEventSubscriber, SubscriberType},
     subscriber::*,
     types::Validator,
-    api::start_api,
+    api::{ApiState, start_api, stream::{EventStream, start_event_stream}},
 };
 
+// the file of the generated node key in the store directory
+const NODE_KEY_FILE: &str = "node_key";
+
 pub fn start_node(config: &str, sender: Sender<()>) -> Result<(), String> {
     print_art();
     init_log();
@@ -47,9 +59,10 @@ pub fn start_node(config: &str, sender: Sender<()>) -> Result<(), String> {
     if result.is_err() {
         return Err(result.err().unwrap());
     }
-    let config = result.unwrap();
+    let mut config = result.unwrap();
     let secret = Secret::from_str(&config.secret).expect("Secret is uncorrect");
     let key_pair = KeyPair::from_secret(secret).unwrap();
+    let node_key = init_node_key(&mut config)?;
     let ledger = init_store(&config)?;
     let ledger: Arc<RwLock<Ledger>> = Arc::new(RwLock::new(ledger));
 
@@ -61,11 +74,12 @@ pub fn start_node(config: &str, sender: Sender<()>) -> Result<(), String> {
     info!("Genesis hash: {:?}", chain.get_genesis().hash());
 
     // init transaction pool
-    let _tx_pool = Arc::new(RwLock::new(init_transaction_pool(&config)));
+    let _tx_pool = Arc::new(RwLock::new(init_transaction_pool(&config, &chain)?));
+    init_tx_journal_rotate(&config, _tx_pool.clone());
 
     let chain = Arc::new(chain);
 
-    init_api(&config, chain.clone());
+    let events = init_event_stream(&config, chain.clone());
 
     let broadcast_subscriber = BroadcastEventSubscriber::new(SubscriberType::Async).start();
 
@@ -76,11 +90,15 @@ pub fn start_node(config: &str, sender: Sender<()>) -> Result<(), String> {
         broadcast_subscriber.clone(),
     );
 
+    init_api(&config, chain.clone(), _tx_pool.clone(), events, core_pid.clone());
+
     let config_clone = config.clone();
     {
         let p2p_event_notify = init_p2p_event_notify();
-        let _discover_pid = init_p2p_service(p2p_event_notify.clone(), &config_clone);
-        init_tcp_server(chain.clone(), p2p_event_notify.clone(), genesis.hash(), core_pid.clone(), &config_clone);
+        let discovery = init_p2p_service(p2p_event_notify.clone(), &config_clone)?;
+        let verifier = Box::new(new_impl_backend(key_pair.clone(), chain.clone(), broadcast_subscriber.clone())) as SafeEngine;
+        let synchronizer = init_synchronizer(chain.clone(), p2p_event_notify.clone(), verifier)?;
+        init_transport(chain.clone(), p2p_event_notify.clone(), genesis.hash(), core_pid.clone(), synchronizer, discovery, node_key, key_pair.secret(), &config_clone)?;
     }
 
     // spawn new thread to handle mine
@@ -95,6 +113,44 @@ pub fn start_node(config: &str, sender: Sender<()>) -> Result<(), String> {
     Ok(())
 }
 
+/// init_node_key loads the key of the p2p identity, the remote peers authenticate the peer id by it,
+/// so the configured peer id must be derived from the key, it's filled in if not set
+fn init_node_key(config: &mut Config) -> Result<NodeKey, String> {
+    let secret = match config.node_key {
+        Some(ref node_key) => Secret::from_str(node_key).map_err(|err| format!("Node key is uncorrect, err: {}", err))?,
+        None => load_node_key(&config.store)?,
+    };
+    let node_key = NodeKey::from_secret(secret)?;
+    let peer_id = node_key.peer_id().to_base58();
+    if config.peer_id.is_empty() {
+        config.peer_id = peer_id;
+    } else if config.peer_id != peer_id {
+        return Err(format!("The configured peer id {} doesn't match the node key, expect {}", config.peer_id, peer_id));
+    }
+    info!("Init node key successfully, peer id: {}", config.peer_id);
+    Ok(node_key)
+}
+
+/// load_node_key reads the node key persisted in the store directory, a new one is generated at the
+/// first start, it's never the consensus secret
+fn load_node_key(store: &str) -> Result<Secret, String> {
+    let path = Path::new(store).join(NODE_KEY_FILE);
+    if path.exists() {
+        let mut input = String::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut input))
+            .map_err(|err| format!("Failed to read node key, err: {}", err))?;
+        return Secret::from_str(input.trim()).map_err(|err| format!("Node key is uncorrect, err: {}", err));
+    }
+    let secret = Random.generate().map_err(|err| err.to_string())?.secret().clone();
+    ::std::fs::create_dir_all(store)
+        .and_then(|_| File::create(&path))
+        .and_then(|mut f| f.write_all(format!("{:x}", *secret).as_bytes()))
+        .map_err(|err| format!("Failed to persist node key, err: {}", err))?;
+    info!("Generate a new node key, path: {:?}", path);
+    Ok(secret)
+}
+
 fn init_p2p_event_notify() -> Addr<ProcessSignals> {
     info!("Init p2p event nofity");
     spawn_sync_subscriber()
@@ -103,25 +159,96 @@ fn init_p2p_event_notify() -> Addr<ProcessSignals> {
 fn init_p2p_service(
     p2p_subscriber: Addr<ProcessSignals>,
     config: &Config,
-) -> Addr<DiscoverService> {
+) -> Result<Addr<KadDiscovery>, String> {
     let peer_id = PeerId::from_str(&config.peer_id).unwrap();
     let mul_addr = Multiaddr::from_str(&format!("/ip4/{}/tcp/{}", config.ip, config.port)).unwrap();
-    let discover_service =
-        DiscoverService::spawn_discover_service(p2p_subscriber, peer_id, mul_addr, config.ttl);
+    let hidden = config.p2p.mode == NodeMode::Validator;
+    let bootnodes = init_bootnodes(config)?;
+    // mDNS advertises the node on LAN, the hidden validator never does it
+    if config.discovery.mdns && !hidden {
+        DiscoverService::spawn_discover_service(p2p_subscriber.clone(), peer_id.clone(), mul_addr.clone(), config.ttl);
+    }
+    let mut discovery = KadDiscovery::new(
+        p2p_subscriber,
+        peer_id,
+        mul_addr,
+        bootnodes,
+        config.discovery.max_peers,
+        config.discovery.interval,
+    ).with_private_peers(config.p2p.private_peer_ids()?);
+    if hidden {
+        discovery = discovery.hidden();
+    }
+    let discovery = discovery.start();
+    info!("P2P node mode: {:?}", config.p2p.mode);
     info!("Init p2p service successfully");
-    discover_service
+    Ok(discovery)
 }
 
-fn init_tcp_server(chain: Arc<Chain>, p2p_subscriber: Addr<ProcessSignals>, genesis: Hash, core_pid: Addr<Core>, config: &Config) {
-    let peer_id = PeerId::from_str(&config.peer_id).unwrap();
+fn init_bootnodes(config: &Config) -> Result<Vec<(PeerId, Multiaddr)>, String> {
+    if config.p2p.mode == NodeMode::Validator {
+        // the validator behind sentries only knows the sentries
+        config.p2p.allowed_peers()?;
+        return config.p2p.sentry_nodes();
+    }
+    config.bootnodes.iter().map(|bootnode| parse_bootnode(bootnode)).collect()
+}
+
+fn init_synchronizer(chain: Arc<Chain>, p2p_subscriber: Addr<ProcessSignals>, verifier: SafeEngine) -> Result<Addr<Synchronizer>, String> {
+    let checkpoint = match chain.config.checkpoint {
+        Some(ref config) => {
+            let hash = config.block_hash()?;
+            info!("Sync from checkpoint, height: {}, hash: {:?}", config.height, hash);
+            Some(Checkpoint { height: config.height, hash })
+        }
+        None => None,
+    };
+    let synchronizer = Synchronizer::new(chain.clone(), verifier, p2p_subscriber, checkpoint).start();
+    chain.subscriber_event(synchronizer.clone().recipient());
+    info!("Init synchronizer successfully");
+    Ok(synchronizer)
+}
+
+fn init_transport(chain: Arc<Chain>, p2p_subscriber: Addr<ProcessSignals>, genesis: Hash, core_pid: Addr<Core>, synchronizer: Addr<Synchronizer>, discovery: Addr<KadDiscovery>, node_key: NodeKey, validator_key: &Secret, config: &Config) -> Result<(), String> {
     let mul_addr = Multiaddr::from_str(&format!("/ip4/{}/tcp/{}", config.ip, config.port)).unwrap();
-    let author = author_handshake(genesis.clone());
-    let h1 = Box::new(handle_msg_middle(core_pid, chain.clone()));
-    let server = TcpServer::new(peer_id, mul_addr, None, genesis.clone(), Box::new(author), h1);
+    let author = author_handshake(genesis.clone(), config.chain_id);
+    let handshake_fn = {
+        let chain = chain.clone();
+        handshake_packet(node_key.peer_id(), config.chain_id, genesis, validator_key.clone(), move || {
+            (chain.get_last_height(), chain.get_last_hash())
+        })
+    };
+    let h1 = Box::new(handle_msg_middle(core_pid, chain.clone(), synchronizer, discovery, p2p_subscriber.clone()));
+    let validators = {
+        let chain = chain.clone();
+        move || {
+            chain.get_validators(chain.get_last_height()).iter().map(|validator| *validator.address()).collect()
+        }
+    };
+    let ban_file = config.p2p.ban_file.clone().map(PathBuf::from).unwrap_or_else(|| Path::new(&config.store).join("p2p_bans.json"));
+    let reputation = Reputation::load(&ban_file, config.p2p.ban_duration, chrono::Utc::now().timestamp() as u64)
+        .map_err(|err| format!("Failed to load banned peers, err: {}", err))?;
+    // the bootnodes or sentries are always redialed after disconnection
+    let mut persistent_peers = init_bootnodes(config)?;
+    persistent_peers.extend(config.p2p.persistent_nodes()?);
+    let context = TransportContext {
+        node_key,
+        mul_addr,
+        genesis,
+        chain_id: config.chain_id,
+        config: config.p2p.clone(),
+        reputation,
+        handshake_fn: Box::new(handshake_fn),
+        author: Box::new(author),
+        handles: h1,
+        validators: Box::new(validators),
+        persistent_peers,
+    };
+    let transport = config.p2p.transport.transport().start(context)?;
 
     // subscriber p2p event, sync operation
     {
-        let recipient = server.clone().recipient();
+        let recipient = transport.p2p_event;
         // register
         let message = SubscribeMessage::SubScribe(recipient);
         let request_fut = p2p_subscriber.send(message);
@@ -137,9 +264,10 @@ fn init_tcp_server(chain: Arc<Chain>, p2p_subscriber: Addr<ProcessSignals>, gene
 
     // subscriber chain event, async operation
     {
-        chain.subscriber_event(server.clone().recipient());
+        chain.subscriber_event(transport.chain_event);
     }
-    info!("Init tcp server successfully");
+    info!("Init {:?} transport successfully", config.p2p.transport);
+    Ok(())
 }
 
 fn init_config(config: &str) -> Result<Config, String> {
@@ -149,11 +277,37 @@ fn init_config(config: &str) -> Result<Config, String> {
         .and_then(|mut f| f.read_to_string(&mut input))
         .map(|_| toml::from_str::<Config>(&input).unwrap())
         .map_err(|err| err.to_string())
+        .and_then(|config| config.p2p.validate().map(|_| config))
 }
 
-fn init_transaction_pool(_: &Config) -> SafeTxPool {
+fn init_transaction_pool(config: &Config, chain: &Chain) -> Result<SafeTxPool, String> {
+    let tx_pool = match config.tx_journal {
+        Some(ref journal_config) => {
+            let journal = TxJournal::new(&journal_config.path, journal_config.lifetime);
+            let ledger = chain.get_ledger().clone();
+            let chain_id = config.chain_id;
+            // revalidate the journaled transactions, drop the committed transactions
+            BaseTxPool::with_journal(journal, move |transaction| {
+                transaction.verify_sign(chain_id)
+                    && ledger.read().get_transaction(transaction.get_hash().unwrap()).is_none()
+            }).map_err(|err| err.to_string())?
+        }
+        None => BaseTxPool::new(),
+    };
     info!("Init transaction pool successfully");
-    Box::new(BaseTxPool::new()) as SafeTxPool
+    Ok(Box::new(tx_pool) as SafeTxPool)
+}
+
+fn init_tx_journal_rotate(config: &Config, tx_pool: Arc<RwLock<SafeTxPool>>) {
+    if let Some(ref journal_config) = config.tx_journal {
+        let interval = journal_config.rejournal;
+        spawn(move || loop {
+            ::std::thread::sleep(interval);
+            if let Err(err) = tx_pool.write().rejournal() {
+                error!("Failed to rotate transaction journal, err: {}", err);
+            }
+        });
+    }
 }
 
 fn init_store(config: &Config) -> Result<Ledger, String> {
@@ -165,7 +319,7 @@ fn init_store(config: &Config) -> Result<Ledger, String> {
         validators.push(Validator::new(common::string_to_address(validator)?));
     }
 
-    let database = Database::open_default(&config.store).map_err(|err| err.to_string())?;
+    let database = open_database(&config.store, &config.store_config)?;
     let schema = Schema::new(Arc::new(database));
     Ok(Ledger::new(
         LastMeta::new_zero(),
@@ -209,12 +363,21 @@ fn start_mint(
     })
 }
 
-fn init_api(config: &Config, chain: Arc<Chain>) {
+fn init_event_stream(config: &Config, chain: Arc<Chain>) -> Option<Addr<EventStream>> {
+    config.stream_port.and_then(|port| {
+        info!("Start event stream");
+        start_event_stream(chain, config.api_ip.clone(), port)
+            .map_err(|err| error!("Failed to start event stream, err: {}", err))
+            .ok()
+    })
+}
+
+fn init_api(config: &Config, chain: Arc<Chain>, tx_pool: Arc<RwLock<SafeTxPool>>, events: Option<Addr<EventStream>>, core: Addr<Core>) {
     let config = config.clone();
     let chain = chain.clone();
     spawn(move || {
         info!("Start service api");
-        start_api(chain, config.api_ip, config.api_port);
+        start_api(ApiState { chain, tx_pool, events, core }, config.api_ip, config.api_port);
     });
 }
 
@@ -0,0 +1,160 @@
+use cryptocurrency_kit::crypto::{hash, Hash};
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Binary merkle tree.
+///
+/// leaf = hash(0x00 || item), node = hash(0x01 || left || right),
+/// the last node of an odd level is promoted to the next level unchanged, it's never paired with
+/// itself, so [a, b, c] and [a, b, c, c] have different roots. The root of empty tree is hash(0x00).
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    // levels[0] are the leaves, the last level is the root
+    levels: Vec<Vec<Hash>>,
+}
+
+/// The sibling path from the leaf to the root, the promoted levels have no sibling,
+/// they are located by the number of leaves
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: u64,
+    pub leaves: u64,
+    pub path: Vec<Hash>,
+}
+
+impl MerkleTree {
+    pub fn new(items: Vec<Vec<u8>>) -> Self {
+        let leaves: Vec<Hash> = items.iter().map(|item| leaf_hash(item)).collect();
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let level = levels.last().unwrap()
+                .chunks(2)
+                .map(|pair| pair.get(1).map_or(pair[0], |right| node_hash(&pair[0], right)))
+                .collect();
+            levels.push(level);
+        }
+        MerkleTree { levels }
+    }
+
+    pub fn root(&self) -> Hash {
+        self.levels.last().unwrap().first().cloned().unwrap_or_else(|| leaf_hash(&[]))
+    }
+
+    /// proof returns the sibling path of the leaf at the index
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.levels[0].len() {
+            return None;
+        }
+        let mut path = vec![];
+        let mut idx = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = if idx % 2 == 0 { level.get(idx + 1) } else { level.get(idx - 1) };
+            path.extend(sibling.cloned());
+            idx /= 2;
+        }
+        Some(MerkleProof { index: index as u64, leaves: self.levels[0].len() as u64, path })
+    }
+}
+
+impl MerkleProof {
+    /// verify checks the item is included in the tree of the root
+    pub fn verify(&self, item: &[u8], root: &Hash) -> bool {
+        if self.index >= self.leaves {
+            return false;
+        }
+        let (mut idx, mut width) = (self.index, self.leaves);
+        let mut path = self.path.iter();
+        let mut node = leaf_hash(item);
+        while width > 1 {
+            // the last node of an odd level is promoted
+            if !(idx % 2 == 0 && idx + 1 == width) {
+                let sibling = match path.next() {
+                    Some(sibling) => sibling,
+                    None => return false,
+                };
+                node = if idx % 2 == 0 { node_hash(&node, sibling) } else { node_hash(sibling, &node) };
+            }
+            idx /= 2;
+            width = (width + 1) / 2;
+        }
+        path.next().is_none() && node == *root
+    }
+}
+
+fn leaf_hash(item: &[u8]) -> Hash {
+    let mut buffer = Vec::with_capacity(1 + item.len());
+    buffer.push(LEAF_PREFIX);
+    buffer.extend_from_slice(item);
+    hash(&buffer)
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut buffer = Vec::with_capacity(1 + 2 * left.as_ref().len());
+    buffer.push(NODE_PREFIX);
+    buffer.extend_from_slice(left.as_ref());
+    buffer.extend_from_slice(right.as_ref());
+    hash(&buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(n: u8) -> Vec<Vec<u8>> {
+        (0..n).map(|i| vec![i, i + 1, i + 2]).collect()
+    }
+
+    #[test]
+    fn t_merkle_proof() {
+        for n in 1..10 {
+            let tree = MerkleTree::new(items(n));
+            let root = tree.root();
+            for (index, item) in items(n).iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert!(proof.verify(item, &root), "n: {}, index: {}", n, index);
+                assert!(!proof.verify(&[100], &root));
+            }
+            assert!(tree.proof(n as usize).is_none());
+        }
+    }
+
+    #[test]
+    fn t_merkle_proof_tampered() {
+        let tree = MerkleTree::new(items(5));
+        let root = tree.root();
+        let mut proof = tree.proof(2).unwrap();
+        assert!(proof.verify(&items(5)[2], &root));
+
+        proof.index = 3;
+        assert!(!proof.verify(&items(5)[2], &root));
+        proof.index = 5;
+        assert!(!proof.verify(&items(5)[2], &root));
+        proof.index = 2;
+        proof.leaves = 4;
+        assert!(!proof.verify(&items(5)[2], &root));
+
+        // the promoted leaf has a shorter path
+        let proof = tree.proof(4).unwrap();
+        assert_eq!(proof.path.len(), 1);
+        assert!(proof.verify(&items(5)[4], &root));
+    }
+
+    #[test]
+    fn t_merkle_root() {
+        assert_eq!(MerkleTree::new(vec![]).root(), leaf_hash(&[]));
+        assert_eq!(MerkleTree::new(items(1)).root(), leaf_hash(&items(1)[0]));
+        assert_ne!(MerkleTree::new(items(2)).root(), MerkleTree::new(items(3)).root());
+    }
+
+    #[test]
+    fn t_merkle_duplicated_tail() {
+        // CVE-2012-2459, duplicating the odd tail must not keep the root
+        let mut duplicated = items(3);
+        duplicated.push(items(3)[2].clone());
+        assert_ne!(MerkleTree::new(items(3)).root(), MerkleTree::new(duplicated).root());
+        let mut duplicated = items(5);
+        duplicated.push(items(5)[4].clone());
+        assert_ne!(MerkleTree::new(items(5)).root(), MerkleTree::new(duplicated).root());
+    }
+}
@@ -8,24 +8,46 @@ use std::fmt::{self, Display};
 use std::net::{SocketAddr, AddrParseError};
 
 use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
-use cryptocurrency_kit::merkle_tree::MerkleTree;
+use cryptocurrency_kit::merkle_tree::MerkleTree as KitMerkleTree;
 use cryptocurrency_kit::storage::values::StorageValue;
+use serde::de::DeserializeOwned;
 use libp2p::{
     multiaddr::Protocol,
     Multiaddr,
 };
 
+pub mod merkle;
+
+use self::merkle::{MerkleProof, MerkleTree};
+
+/// merkle_tree_root is the root of cryptocurrency-kit merkle tree, the transaction root of the blocks
+/// before `merkle_upgrade_height`
 pub fn merkle_tree_root<T: StorageValue>(input: Vec<T>) -> Hash {
     let mut v: Vec<Vec<_>> = vec![];
     for item in input {
         let bytes = item.into_bytes();
         v.push(bytes);
     }
-    let root = MerkleTree::new_merkle_tree(v).root.unwrap();
-//    info!("{:?}, len: {}", &root.data);
+    let root = KitMerkleTree::new_merkle_tree(v).root.unwrap();
     Hash::from_slice(&root.data).unwrap()
 }
 
+/// merkle_tree builds the binary merkle tree which serves the inclusion proofs
+pub fn merkle_tree<T: StorageValue>(input: Vec<T>) -> MerkleTree {
+    MerkleTree::new(input.into_iter().map(|item| item.into_bytes()).collect())
+}
+
+/// merkle_tree_proof returns the inclusion proof of the item at the index
+pub fn merkle_tree_proof<T: StorageValue>(input: Vec<T>, index: usize) -> Option<MerkleProof> {
+    merkle_tree(input).proof(index)
+}
+
+/// decode is the fallible `StorageValue::from_bytes` for the untrusted input, the values are
+/// encoded by bincode as `implement_storagevalue_traits!` does
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+    bincode::deserialize(bytes).map_err(|err| err.to_string())
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct HexBytes {
     inner: [u8; 32],
@@ -5,7 +5,11 @@ use toml::Value as Toml;
 use toml::value::Table;
 use toml::value::Datetime;
 
+use cryptocurrency_kit::crypto::Hash;
+
 use crate::common::random_dir;
+use crate::types::Height;
+use crate::p2p::config::Config as P2PConfig;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
@@ -14,16 +18,122 @@ pub struct Config {
     pub port: u16,
     pub api_ip: String,
     pub api_port: u16,
+    // port of the server-sent events stream, it is disabled if not set
+    #[serde(default)]
+    pub stream_port: Option<u16>,
     #[serde(with = "serde_millis")]
     pub block_period: Duration,
     #[serde(with = "serde_millis")]
     pub request_time: Duration,
+    // derived from the node key if not set
+    #[serde(default)]
     pub peer_id: String,
     #[serde(with = "serde_millis")]
     pub ttl: Duration,
+    // static peers "<peer_id>@<multiaddr>", they are dialed at startup and redialed once disconnected
+    #[serde(default)]
+    pub bootnodes: Vec<String>,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    // connection limits and peer banning
+    #[serde(default)]
+    pub p2p: P2PConfig,
     pub store: String,
+    // options of the column families of store
+    #[serde(default)]
+    pub store_config: StoreConfig,
     pub secret: String,
+    // hex secret of the p2p identity, the peer id is derived from it, a separate key is generated
+    // and persisted in the store directory if not set
+    #[serde(default)]
+    pub node_key: Option<String>,
     pub genesis: Option<GenesisConfig>,
+    #[serde(default)]
+    pub tx_journal: Option<TxJournalConfig>,
+    // sync from the trusted checkpoint instead of genesis
+    #[serde(default)]
+    pub checkpoint: Option<CheckpointConfig>,
+    // the blocks from the height commit the transaction root which serves inclusion proofs,
+    // all of the validators must set the same height, it is disabled if not set
+    #[serde(default)]
+    pub merkle_upgrade_height: Option<Height>,
+}
+
+/// Peer discovery, mDNS only works on LAN, kademlia finds the peers through the connected peers
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    pub mdns: bool,
+    // max number of the closest nodes dialed by kademlia
+    pub max_peers: usize,
+    // interval of kademlia lookup
+    #[serde(with = "serde_millis")]
+    pub interval: Duration,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            mdns: true,
+            max_peers: 25,
+            interval: Duration::from_millis(10 * 1000),
+        }
+    }
+}
+
+/// Options of RocksDB, every index of schema has its own column family.
+/// Only the options exposed by kvdb-rocksdb are tunable, the compression and bloom filters
+/// are kept at their defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StoreConfig {
+    pub max_open_files: i32,
+    // compaction profile: "auto", "ssd" or "hdd"
+    pub compaction: String,
+    // memory budget (MiB) of each column, it is used for the block cache and write buffer
+    pub memory_budget: usize,
+    // memory budget (MiB) of the specified columns, the key is the index name without "core.",
+    // e.g. headers = 128
+    pub columns: HashMap<String, usize>,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig {
+            max_open_files: 512,
+            compaction: "ssd".to_string(),
+            memory_budget: 32,
+            columns: HashMap::new(),
+        }
+    }
+}
+
+/// Journal of the local transactions, they are reloaded into the tx pool after restart
+#[derive(Debug, Clone, Deserialize)]
+pub struct TxJournalConfig {
+    pub path: String,
+    // how long the local transaction can be kept in the journal
+    #[serde(with = "serde_millis")]
+    pub lifetime: Duration,
+    // interval of regenerating the journal
+    #[serde(with = "serde_millis")]
+    pub rejournal: Duration,
+}
+
+/// A trusted block, the new node downloads the state snapshot at it and follows the chain from it
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckpointConfig {
+    pub height: u64,
+    // hex of the block hash
+    pub hash: String,
+}
+
+impl CheckpointConfig {
+    pub fn block_hash(&self) -> Result<Hash, String> {
+        let hash = if self.hash.starts_with("0x") { &self.hash[2..] } else { &self.hash };
+        let bytes = hex::decode(hash).map_err(|err| err.to_string())?;
+        Hash::from_slice(&bytes).ok_or_else(|| "invalid checkpoint hash length".to_string())
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -44,13 +154,22 @@ impl Default for Config {
             port: 7960,
             api_ip: "0.0.0.0".to_owned(),
             api_port: 8960,
+            stream_port: None,
             block_period: Duration::from_millis(3 * 1000),
             request_time: Duration::from_millis(3 * 1000),
             peer_id: "QmbBr2fHwLFKvHkAq1BpbEr4dvR8P6orQxHkVaxeJsJiW8".to_string(),
             ttl: Duration::from_millis(5 * 1000),
+            bootnodes: vec![],
+            discovery: DiscoveryConfig::default(),
+            p2p: P2PConfig::default(),
             store: *random_dir(),
+            store_config: StoreConfig::default(),
             secret: "".into(),
+            node_key: None,
             genesis: None,
+            tx_journal: None,
+            checkpoint: None,
+            merkle_upgrade_height: None,
         }
     }
 }
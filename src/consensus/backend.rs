@@ -1,4 +1,5 @@
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -33,12 +34,13 @@ use super::{
     validator::{fn_selector, ImplValidatorSet, ValidatorSet},
 };
 use crate::{
-    common::merkle_tree_root,
     core::chain::Chain,
     error::{ChainError, ChainResult},
     protocol::GossipMessage,
     subscriber::events::{BroadcastEvent, BroadcastEventSubscriber},
     types::block::{Block, Header},
+    types::transaction::merkle_root_transactions,
+    types::votes::decrypt_commit_bytes,
     types::{Height, Validator, EMPTY_ADDRESS},
 };
 use ethereum_types::H256;
@@ -68,6 +70,21 @@ pub trait Backend {
     fn get_header_by_height(&self, height: Height) -> Option<Header>;
 }
 
+/// verify_transactions checks the signature of every transaction and the transaction root of the block
+pub fn verify_transactions(block: &Block, chain_id: u64, merkle_upgrade_height: Option<Height>) -> Result<(), EngineError> {
+    let transactions = block.transactions().to_vec();
+    for transaction in &transactions {
+        if !transaction.verify_sign(chain_id) {
+            return Err(EngineError::InvalidSignature);
+        }
+    }
+    let transaction_hash = merkle_root_transactions(transactions, block.height(), merkle_upgrade_height);
+    if transaction_hash != block.header().tx_hash {
+        return Err(EngineError::InvalidTransactionHash(block.header().tx_hash.clone(), transaction_hash));
+    }
+    Ok(())
+}
+
 pub fn new_impl_backend(
     keypair: KeyPair,
     chain: Arc<Chain>,
@@ -210,20 +227,8 @@ impl Backend for ImplBackend {
         }
 
         // check transaction
-        {
-            let transactions = block.transactions().to_vec();
-            for transaction in &transactions {
-                if !transaction.verify_sign(self.config.chain_id) {
-                    return (Duration::from_nanos(0), Err(EngineError::InvalidSignature));
-                }
-            }
-            let transaction_hash = merkle_tree_root(transactions);
-            if transaction_hash != header.tx_hash {
-                return (
-                    Duration::from_nanos(0),
-                    Err(EngineError::InvalidTransactionHash(header.tx_hash.clone(), transaction_hash)),
-                );
-            }
+        if let Err(err) = verify_transactions(block, self.config.chain_id, self.chain.config.merkle_upgrade_height) {
+            return (Duration::from_nanos(0), Err(err));
         }
         let result = self.verify_header(&header, false);
         if let Err(ref err) = result {
@@ -342,21 +347,21 @@ impl Engine for ImplBackend {
     }
 
     fn verify_seal(&self, header: &Header) -> EngineResult {
-        // check votes
+        // check votes, the commit seals sign the block hash
         {
-            let votes = header.votes.as_ref().ok_or(EngineError::LackVotes(
-                self.validator_set.two_thirds_majority() + 1,
-                header.votes.as_ref().unwrap().len(),
-            ))?;
-            if votes.verify_signs(CryptoHash::hash(header), |validator| {
-                self.validator_set.get_by_address(validator).is_some()
-            }) == false
-            {
-                return Err(EngineError::InvalidSignature);
-            }
             let maj32 = self.validator_set.two_thirds_majority();
-            if maj32 + 1 > votes.len() {
-                return Err(EngineError::LackVotes(maj32 + 1, votes.len()));
+            let votes = header.votes.as_ref().ok_or_else(|| EngineError::LackVotes(maj32 + 1, 0))?;
+            let digest = header.block_hash();
+            let mut signers = HashSet::new();
+            for vote in votes.votes() {
+                let signer = decrypt_commit_bytes(&digest, vote).map_err(|_| EngineError::InvalidSignature)?;
+                if self.validator_set.get_by_address(signer).is_none() {
+                    return Err(EngineError::InvalidSignature);
+                }
+                signers.insert(signer);
+            }
+            if maj32 + 1 > signers.len() {
+                return Err(EngineError::LackVotes(maj32 + 1, signers.len()));
             }
         }
 
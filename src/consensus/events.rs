@@ -1,4 +1,5 @@
 use std::any::{Any, TypeId};
+use std::collections::BTreeMap;
 
 use ::actix::prelude::*;
 
@@ -64,6 +65,28 @@ impl Message for BackLogEvent {
     type Result = ConsensusResult;
 }
 
+/// Query the consensus state of the core, it is used by the api
+#[derive(Debug)]
+pub struct ConsensusStatusEvent {}
+
+impl Message for ConsensusStatusEvent {
+    type Result = ConsensusStatus;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsensusStatus {
+    pub view: View,
+    pub state: String,
+    pub lock_hash: Option<String>,
+    pub proposer: Option<String>,
+    pub is_proposer: bool,
+    pub prepares: usize,
+    pub commits: usize,
+    // round -> the number of round change messages
+    pub round_changes: BTreeMap<u64, usize>,
+    pub wait_round_change: bool,
+}
+
 #[derive(Debug, Message)]
 pub enum ConsensusEvent {
     NetWork(MessageEvent),
@@ -17,8 +17,6 @@ use crate::{
 };
 
 use std::borrow::Cow;
-use ethereum_types::H256;
-use cryptocurrency_kit::common::to_fixed_array_32;
 
 pub trait HandleCommit {
     fn send_commit(&mut self);
@@ -99,13 +97,11 @@ impl HandleCommit for Core {
             return Err(ConsensusError::Unknown("commit seal is nil".to_string()));
         }
         let commit_seal = commit_seal.unwrap();
-        let digest = H256::from(to_fixed_array_32(commit_subject.digest.as_ref()));
-        let sign_message = SignMessage::from(digest);
-        verify_address(&sender, commit_seal, &sign_message)
-            .map(|_| ())
-            .map_err(|_| {
-                ConsensusError::Unknown("message's sender should be commit seal".to_string())
-            })?;
+        let signer = decrypt_commit_bytes(&commit_subject.digest, commit_seal)
+            .map_err(|err| ConsensusError::Unknown(err))?;
+        if signer != sender {
+            return Err(ConsensusError::Unknown("message's sender should be commit seal".to_string()));
+        }
         let current_state = &self.current_state;
         let current_subject = current_state.subject().unwrap();
         if current_subject.digest != commit_subject.digest
@@ -35,19 +35,42 @@ use crate::{
     consensus::backend::{Backend, ImplBackend},
     consensus::config::Config,
     consensus::error::{ConsensusError, ConsensusResult},
-    consensus::events::{OpCMD, MessageEvent, NewHeaderEvent, FinalCommittedEvent, BackLogEvent, TimerEvent},
+    consensus::events::{OpCMD, MessageEvent, NewHeaderEvent, FinalCommittedEvent, BackLogEvent, TimerEvent, ConsensusStatusEvent, ConsensusStatus},
     consensus::types::{Proposal, Request as CSRequest, Round, Subject, View},
     consensus::validator::{ImplValidatorSet, ValidatorSet, Validators},
+    metrics::{self, duration_to_secs},
     p2p::server::HandleMsgFn,
     p2p::protocol::{RawMessage, P2PMsgCode, Payload},
+    p2p::kad::{DiscoveryMessage, KadDiscovery},
+    p2p::sync::{Synchronizer, SyncMessage},
+    p2p::reputation::Misbehavior,
     protocol::{GossipMessage, MessageType, State},
     types::Validator,
-    types::block::{Block, Blocks},
+    types::block::Block,
     types::Height,
+    subscriber::{P2PEvent, ProcessSignals},
     subscriber::events::ChainEvent,
 };
 
-pub fn handle_msg_middle(core_pid: Addr<Core>, chain: Arc<Chain>) -> impl Fn(PeerId, RawMessage) -> Result<(), String> {
+/// misbehavior_of returns the misbehavior of the peer which sent the rejected consensus message,
+/// the stale or early messages are expected while gossiping and aren't misbehaviors
+fn misbehavior_of(err: &ConsensusError) -> Option<Misbehavior> {
+    match err {
+        ConsensusError::InvalidMessage | ConsensusError::InconsistentSubject | ConsensusError::UnauthorizedAddress => {
+            Some(Misbehavior::InvalidMessage)
+        }
+        ConsensusError::Engine(_) => Some(Misbehavior::BadBlock),
+        _ => None,
+    }
+}
+
+pub fn handle_msg_middle(
+    core_pid: Addr<Core>,
+    chain: Arc<Chain>,
+    synchronizer: Addr<Synchronizer>,
+    discovery: Addr<KadDiscovery>,
+    p2p_subscriber: Addr<ProcessSignals>,
+) -> impl Fn(PeerId, RawMessage) -> Result<(), String> {
     move |peer_id: PeerId, msg: RawMessage| {
         let header = msg.header();
         let payload = msg.payload().to_vec();
@@ -55,8 +78,13 @@ pub fn handle_msg_middle(core_pid: Addr<Core>, chain: Arc<Chain>) -> impl Fn(Pee
             P2PMsgCode::Consensus => {
                 let request = core_pid.send(MessageEvent { payload: payload });
                 let chain = chain.clone();
+                let p2p_subscriber = p2p_subscriber.clone();
+                let peer_id = peer_id.clone();
                 Arbiter::spawn(request.and_then(move |result| {
                     if let Err(err) = result {
+                        if let Some(misbehavior) = misbehavior_of(&err) {
+                            p2p_subscriber.do_send(P2PEvent::Misbehave(peer_id, misbehavior));
+                        }
                         if let ConsensusError::FutureBlockMessage(height) = err {
                             let chain = chain.clone();
                             actix::spawn(Delay::new(Instant::now() + Duration::from_secs(1)).and_then(move |_| {
@@ -72,43 +100,20 @@ pub fn handle_msg_middle(core_pid: Addr<Core>, chain: Arc<Chain>) -> impl Fn(Pee
                     futures::future::ok(())
                 }).map_err(|err| panic!(err)));
             }
-            P2PMsgCode::Block => {
-                let blocks: Blocks = Blocks::from_bytes(Cow::from(&payload));
-                debug!("Receive a batch block from network, size:{:?}", blocks.0.len());
-                // TODO FIXME
-                blocks.0.iter().for_each(|block| {
-                    chain.insert_block(&block);
-                });
+            // the blocks are verified by synchronizer before inserting
+            P2PMsgCode::Block | P2PMsgCode::Status | P2PMsgCode::GetHeaders | P2PMsgCode::Headers | P2PMsgCode::GetBodies
+            | P2PMsgCode::Bodies | P2PMsgCode::GetSnapshot | P2PMsgCode::Snapshot => {
+                synchronizer.do_send(SyncMessage(peer_id.clone(), msg.clone()));
             }
-            P2PMsgCode::Sync => {
-                let height = Height::from_bytes(Cow::from(&payload));
-                debug!("Receive a new sync event from network, height: {:?}", height);
-
-                let last_height = chain.get_last_height();
-                let mut total = 0;
-                let mut batch = 0;
-                let mut blocks = Blocks(vec![]);
-                for height in (height..last_height + 1) {
-                    if let Some(block) = chain.get_block_by_height(height) {
-                        blocks.0.push(block);
-                    }
-                    if batch > 20 {
-                        chain.post_event(ChainEvent::PostBlock(Some(peer_id.clone()), blocks.clone()));
-                        batch = 0;
-                        // FIXME
-                        blocks.0.clear();
-                    }
-                    if total > 100 {
-                        break;
-                    }
-                    batch += 1;
-                    total += 1;
-                }
-                if blocks.0.len() > 0 {
-                    chain.post_event(ChainEvent::PostBlock(Some(peer_id.clone()), blocks));
-                }
+            P2PMsgCode::FindNode | P2PMsgCode::Neighbors => {
+                discovery.do_send(DiscoveryMessage(peer_id.clone(), msg.clone()));
+            }
+            // the remote transactions are not accepted by the tx pool yet, they are only relayed
+            P2PMsgCode::Transaction => {}
+            // answered by the transport
+            P2PMsgCode::Ping | P2PMsgCode::Handshake | P2PMsgCode::Sync | P2PMsgCode::Pong => {
+                debug!("Skip message from {}, code: {:?}", peer_id.to_base58(), header.code);
             }
-            _ => unimplemented!()
         }
 
         Ok(())
@@ -133,6 +138,8 @@ pub struct Core {
     future_prepprepare_timer: Addr<Timer>,
     round_change_timer: Addr<Timer>,
     pub consensus_timestamp: Duration,
+    // when the current height is started, it is used to measure the commit latency
+    height_start: Instant,
 
     backlog_store: Addr<BackLogActor>,
     pub backend: Box<Backend<ValidatorsType=ImplValidatorSet>>,
@@ -242,6 +249,14 @@ impl Handler<OpCMD> for Core {
     }
 }
 
+impl Handler<ConsensusStatusEvent> for Core {
+    type Result = MessageResult<ConsensusStatusEvent>;
+
+    fn handle(&mut self, _msg: ConsensusStatusEvent, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.status())
+    }
+}
+
 impl Core {
     pub fn new(chain: Arc<Chain>, backend: ImplBackend, key_pair: KeyPair) -> Addr<Core> {
         //    let core_backend: Box<Backend<ValidatorsType=ImplValidatorSet> + Send + Sync> = Box::new(backend.clone()) as Box<Backend<ValidatorsType=ImplValidatorSet> + Send + Sync>;
@@ -298,6 +313,7 @@ impl Core {
                 }),
 
                 consensus_timestamp: Duration::from_secs(0),
+                height_start: Instant::now(),
 
                 backend: core_backend,
 
@@ -335,6 +351,8 @@ impl Core {
 
     pub fn handle_check_message(&mut self, msg: &GossipMessage, src: &Validator) -> ConsensusResult {
         debug!("Handle check message, {}", msg.trace());
+        let code = format!("{:?}", msg.code);
+        metrics::CONSENSUS_MESSAGES_RECEIVED.with_label_values(&[&code]).inc();
         let result = match msg.code {
             MessageType::Preprepare => {
                 <Core as HandlePreprepare>::handle(self, msg, src)
@@ -355,7 +373,9 @@ impl Core {
                 ConsensusError::FutureMessage | ConsensusError::FutureRoundMessage => {
                     self.backlog_store.do_send(msg.clone());
                 }
-                _ => {}
+                _ => {
+                    metrics::CONSENSUS_MESSAGES_DROPPED.with_label_values(&[&code]).inc();
+                }
             }
         }
         result
@@ -402,7 +422,7 @@ impl Core {
         self.set_state(State::Committed);
         let mut committed_seals = Vec::with_capacity(self.current_state.commits.len());
         self.current_state.commits.values().iter().for_each(|v| {
-            committed_seals.push(v.signature.as_ref().unwrap().clone());
+            committed_seals.push(v.commit_seal.as_ref().unwrap().clone());
         });
         let has_more_than_maj23 =
             self.validators.two_thirds_majority() + 1 <= committed_seals.len();
@@ -411,6 +431,9 @@ impl Core {
         let mut proposal = self.current_state.proposal().unwrap().clone();
         if let Err(err) = self.backend.commit(&mut proposal, committed_seals) {
             error!("Failed to commit block");
+        } else {
+            metrics::CONSENSUS_COMMIT_LATENCY.observe(duration_to_secs(self.height_start.elapsed()));
+            metrics::CONSENSUS_ROUNDS.observe((self.current_state.round() + 1) as f64);
         }
 
         debug!(
@@ -460,6 +483,7 @@ impl Core {
 
         // reset state
         self.wait_round_change = false;
+        self.height_start = Instant::now();
         // set state into State::AcceptRequest
         // NOTIC: the next step should set request atomic
         self.set_state(State::AcceptRequest);
@@ -507,6 +531,7 @@ impl Core {
         //        let round = self.round_change_set
         //        .max_round(self.validators.two_thirds_majority() + 1).unwrap();
         trace!("ready to update round, because round change");
+        metrics::CONSENSUS_ROUND_CHANGES.inc();
         let new_view = View::new(self.current_state.height(), round);
 
         // TODO 继承上一次的Round change prove
@@ -616,6 +641,20 @@ impl Core {
         View::new(self.current_state.height(), self.current_state.round())
     }
 
+    pub fn status(&self) -> ConsensusStatus {
+        ConsensusStatus {
+            view: self.current_view(),
+            state: format!("{:?}", self.state),
+            lock_hash: self.current_state.get_lock_hash().map(|lock_hash| format!("{:?}", lock_hash)),
+            proposer: self.validators.get_proposer().map(|proposer| format!("{:?}", proposer.address())),
+            is_proposer: self.is_proposer(),
+            prepares: self.current_state.prepares.len(),
+            commits: self.current_state.commits.len(),
+            round_changes: self.round_change_set.votes(),
+            wait_round_change: self.wait_round_change,
+        }
+    }
+
     pub fn mut_current_state(&mut self) -> &mut RoundState {
         &mut self.current_state
     }
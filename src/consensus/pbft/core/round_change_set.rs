@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::{
     protocol::{MessageManage, GossipMessage},
@@ -73,6 +73,11 @@ impl RoundChangeSet<ImplValidatorSet> {
         max
     }
 
+    // return the number of round change messages of every round
+    pub fn votes(&self) -> BTreeMap<Round, usize> {
+        self.round_changes.iter().map(|(round, mm)| (*round, mm.len())).collect()
+    }
+
     pub fn print_info(&self) {
         for round_change in &self.round_changes {
             debug!("round:{:?}, size:{:?}", round_change.0, round_change.1.len());
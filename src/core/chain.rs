@@ -9,9 +9,12 @@ use futures::Future;
 use std::time::Instant;
 
 use crate::{
+    common::{merkle_tree_proof, merkle::MerkleProof},
     config::Config,
     error::{ChainError, ChainResult},
-    types::{Height, Validators, ValidatorArray, Validator, transaction::Transaction, block::Block, block::Header},
+    metrics,
+    store::schema::TxLocation,
+    types::{Height, Validators, ValidatorArray, Validator, transaction::{Transaction, merkle_upgraded}, block::Block, block::Header},
     subscriber::events::{ChainEvent, ChainEventCT::ProcessSignals, ChainEventCT::SubscribeMessage},
 };
 use super::genesis::store_genesis_block;
@@ -58,6 +61,7 @@ impl Chain {
 
             ledger.add_block(block);
         }
+        metrics::CHAIN_HEIGHT.set(block.height() as i64);
         self.subscriber.do_send(ChainEvent::NewBlock(block.clone()));
         self.subscriber.do_send(ChainEvent::NewHeader(block.header().clone()));
 //        Arbiter::spawn(self.subscriber.send(ChainEvent::NewBlock(block.clone())).then(|result| {
@@ -70,6 +74,29 @@ impl Chain {
         Ok(())
     }
 
+    /// import_checkpoint jumps the chain to the verified checkpoint block, the transitions are the
+    /// validator sets changed before it
+    pub fn import_checkpoint(&self, block: &Block, validators: Vec<Address>, transitions: Vec<(Height, Vec<Address>)>) -> ChainResult {
+        let _guard = self.lock.write();
+        {
+            let mut ledger = self.ledger.write();
+            if *ledger.get_last_block_height() >= block.height() {
+                return Err(ChainError::Exists(block.hash()));
+            }
+            let validators = validators.iter().map(|address| Validator::new(*address)).collect();
+            let transitions = transitions
+                .into_iter()
+                .map(|(height, validators)| (height, validators.iter().map(|address| Validator::new(*address)).collect()))
+                .collect();
+            ledger.import_checkpoint(block, validators, transitions);
+        }
+        info!("Import checkpoint, hash: {}, height: {}", block.hash().short(), block.height());
+        metrics::CHAIN_HEIGHT.set(block.height() as i64);
+        self.subscriber.do_send(ChainEvent::NewBlock(block.clone()));
+        self.subscriber.do_send(ChainEvent::NewHeader(block.header().clone()));
+        Ok(())
+    }
+
     pub fn insert_block_mock(block: &Block, ledger: Arc<RwLock<Ledger>>) -> ChainResult {
         info!("Ready insert a new block, hash: {}, height: {}", block.hash().short(), block.height());
         {
@@ -111,6 +138,26 @@ impl Chain {
         self.ledger.read().get_transactions()
     }
 
+    pub fn get_transaction(&self, tx_hash: &Hash) -> Option<Transaction> {
+        self.ledger.read().get_transaction(tx_hash)
+    }
+
+    pub fn get_transaction_location(&self, tx_hash: &Hash) -> Option<TxLocation> {
+        self.ledger.read().get_transaction_location(tx_hash)
+    }
+
+    /// get_transaction_proof returns the location of the committed transaction and its merkle proof
+    /// against the transaction root of the block, none for the blocks before `merkle_upgrade_height`
+    pub fn get_transaction_proof(&self, tx_hash: &Hash) -> Option<(TxLocation, MerkleProof)> {
+        let location = self.get_transaction_location(tx_hash)?;
+        if !merkle_upgraded(location.block_height, self.config.merkle_upgrade_height) {
+            return None;
+        }
+        let block = self.get_block_by_hash(&location.block_hash)?;
+        let proof = merkle_tree_proof(block.transactions().clone(), location.position_in_block as usize)?;
+        Some((location, proof))
+    }
+
     pub fn get_block_hash_by_height(&self, height: Height) -> Option<Hash> {
         self.ledger.read().get_block_hash_by_height(height)
     }
@@ -123,9 +170,9 @@ impl Chain {
         self.ledger.read().get_last_block_hash().clone()
     }
 
-    pub fn add_validators(&self, _height: Height, validators: Vec<Address>) -> ChainResult {
+    pub fn add_validators(&self, height: Height, validators: Vec<Address>) -> ChainResult {
         let validators = validators.iter().map(|address| Validator::new(*address)).collect();
-        self.ledger.write().add_validators(validators);
+        self.ledger.write().add_validators(height, validators);
         Ok(())
     }
 
@@ -180,8 +227,8 @@ mod test {
     use super::*;
     use crate::common::random_dir;
     use cryptocurrency_kit::ethkey::{Generator, Random};
-    use kvdb_rocksdb::Database;
-    use crate::store::schema::Schema;
+    use crate::config::StoreConfig;
+    use crate::store::schema::{open_database, Schema};
     use crate::core::ledger::{Ledger, LastMeta};
     use lru_time_cache::LruCache;
     use std::sync::Arc;
@@ -193,7 +240,7 @@ mod test {
     fn t_batch() {
         let secret = Random.generate().unwrap();
 
-        let database = Database::open_default(&random_dir()).map_err(|err| err.to_string()).unwrap();
+        let database = open_database(&random_dir(), &StoreConfig::default()).unwrap();
         let schema = Schema::new(Arc::new(database));
         let mut ledger = Ledger::new(
             LastMeta::new_zero(),
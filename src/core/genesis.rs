@@ -36,7 +36,7 @@ pub(crate) fn store_genesis_block(genesis_config: &GenesisConfig, ledger: Arc<Rw
         }).map(|address| {
             Validator::new(address)
         }).collect();
-        ledger.add_validators(validators);
+        ledger.add_validators(0, validators);
     }
 
     // TODO Add more xin
@@ -63,8 +63,8 @@ mod test {
     use super::*;
     use crate::common::random_dir;
     use cryptocurrency_kit::ethkey::{Generator, Random};
-    use kvdb_rocksdb::Database;
-    use crate::store::schema::Schema;
+    use crate::config::StoreConfig;
+    use crate::store::schema::{open_database, Schema};
     use crate::core::ledger::{Ledger, LastMeta};
     use lru_time_cache::LruCache;
 
@@ -72,7 +72,7 @@ mod test {
     fn t_genesis_block() {
         let secret = Random.generate().unwrap();
 
-        let database = Database::open_default(&random_dir()).map_err(|err| err.to_string()).unwrap();
+        let database = open_database(&random_dir(), &StoreConfig::default()).unwrap();
         let schema = Schema::new(Arc::new(database));
         let mut ledger = Ledger::new(
             LastMeta::new_zero(),
@@ -97,7 +97,7 @@ mod test {
     fn t_back_block() {
         let secret = Random.generate().unwrap();
 
-        let database = Database::open_default(&random_dir()).map_err(|err| err.to_string()).unwrap();
+        let database = open_database(&random_dir(), &StoreConfig::default()).unwrap();
         let schema = Schema::new(Arc::new(database));
         let mut ledger = Ledger::new(
             LastMeta::new_zero(),
@@ -1,11 +1,12 @@
 use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
 use kvdb_rocksdb::{Database, DatabaseConfig, DatabaseIterator};
 use lru_time_cache::LruCache;
 use parking_lot::RwLock;
 use chrono::{DateTime, TimeZone, NaiveDateTime, Utc};
 
 use crate::{
-    store::schema::Schema,
+    store::schema::{Schema, TxLocation},
     types::block::{Block, Header},
     types::transaction::Transaction,
     types::{Height, Validator, ValidatorArray, HashesEntry},
@@ -70,6 +71,10 @@ impl Ledger {
         self.schema.transaction().get(tx_hash)
     }
 
+    pub fn get_transaction_location(&self, tx_hash: &Hash) -> Option<TxLocation> {
+        self.schema.transaction_locations().get(tx_hash)
+    }
+
     pub fn get_genesis_block(&mut self) -> Option<&Block> {
         if self.genesis.is_some() {
             return self.genesis.as_ref();
@@ -98,6 +103,8 @@ impl Ledger {
         &self.meta.block_hash
     }
 
+    /// get_block_hash_by_height returns none for the heights below the imported checkpoint,
+    /// their blocks are never downloaded
     pub fn get_block_hash_by_height(&self, height: Height) -> Option<Hash> {
         self.schema.block_hashes_by_height().get(height)
     }
@@ -197,37 +204,54 @@ impl Ledger {
             return;
         }
 
+        // all of the writes are committed by one batch, a crash can't leave a half-written block
+        let fork = self.schema.fork();
+        Self::write_block(&Schema::from_view(fork.clone().into()), block);
+        fork.merge().expect("Failed to write block");
+        self.commit_block(block);
+    }
+
+    fn write_block(schema: &Schema, block: &Block) {
+        let header = block.header();
+        let hash = header.block_hash();
         // persists
         {
 //            debug!("Write header");
-            let mut header_db = self.schema.headers();
+            let mut header_db = schema.headers();
             header_db.put(&hash, header.clone());
         }
 
         // transactions
         {
             let mut tx_hashes = HashesEntry(vec![]);
-            let mut tx_db = self.schema.transaction();
+            let mut tx_db = schema.transaction();
+            let mut location_db = schema.transaction_locations();
 //            debug!("Write transaction");
-            for transaction in block.transactions() {
+            for (position, transaction) in block.transactions().iter().enumerate() {
                 let tx_hash = transaction.hash();
                 tx_db.put(&tx_hash, transaction.clone());
+                location_db.put(&tx_hash, TxLocation::new(hash, block.height(), position as u64));
                 tx_hashes.0.push(tx_hash);
             }
 
-            let mut tx_hashes_db = self.schema.transaction_hashes();
+            let mut tx_hashes_db = schema.transaction_hashes();
             tx_hashes_db.put(&hash, tx_hashes);
         }
 
         // height
         {
-            let mut height_db = self.schema.block_hashes_by_height();
+            let mut height_db = schema.block_hashes_by_height();
 //            debug!("Write height, hash:{:?}, height:{:?}", hash.short(), block.height());
             height_db.push(hash.clone());
             assert_eq!(height_db.last().unwrap(), hash);
             assert_eq!(height_db.len(), block.height() + 1);
         }
+    }
 
+    /// commit_block caches the written block and moves the head to it
+    fn commit_block(&mut self, block: &Block) {
+        let header = block.header();
+        let hash = header.block_hash();
         // cache it
         {
             self.header_cache
@@ -244,14 +268,70 @@ impl Ledger {
         info!("📝 Insert new block, hash:{:?}, height:{}, utime:{}, proposer:{:?}", hash.short(), header.height, dt.to_rfc3339(), header.proposer);
     }
 
-    pub fn add_validators(&mut self, validators: Vec<Validator>) {
+    /// import_checkpoint stores the checkpoint block as the new head, the blocks before it are skipped,
+    /// their heights are kept in the height index without hashes, so the lookups by these heights
+    /// return none while `get_last_block_height` still counts them
+    pub fn import_checkpoint(&mut self, block: &Block, validators: Vec<Validator>, transitions: Vec<(Height, Vec<Validator>)>) {
+        let fork = self.schema.fork();
+        {
+            let schema = Schema::from_view(fork.clone().into());
+            {
+                let mut height_db = schema.block_hashes_by_height();
+                assert!(height_db.len() <= block.height());
+                if height_db.len() < block.height() {
+                    schema.pruned_heights().put(&height_db.len(), block.height());
+                }
+                height_db.set_len(block.height());
+            }
+            Self::write_block(&schema, block);
+            // the transitions before the checkpoint are kept, their headers are not
+            let changed = transitions.last().map_or(validators != self.validators, |(_, last)| *last != validators);
+            let mut transitions_db = schema.validator_transitions();
+            for (height, validators) in transitions {
+                transitions_db.put(&height, ValidatorArray::from(validators));
+            }
+            if changed {
+                transitions_db.put(&block.height(), ValidatorArray::from(validators.clone()));
+            }
+            schema.validators().set(ValidatorArray::from(validators.clone()));
+        }
+        fork.merge().expect("Failed to write checkpoint");
+        self.commit_block(block);
+        self.validators = validators;
+    }
+
+    /// add_validators changes the validator set from the height
+    pub fn add_validators(&mut self, height: Height, validators: Vec<Validator>) {
         let val_array = ValidatorArray::from(validators.clone());
+        self.schema.validator_transitions().put(&height, val_array.clone());
         let mut validators_entry = self.schema.validators();
         validators_entry.set(val_array);
         // cache it
         self.validators = validators;
     }
 
+    /// get_pruned_end returns the checkpoint height if the height is skipped by the checkpoint import
+    pub fn get_pruned_end(&self, height: Height) -> Option<Height> {
+        self.schema
+            .pruned_heights()
+            .iter()
+            .find(|(first, checkpoint)| *first <= height && height < *checkpoint)
+            .map(|(_, checkpoint)| checkpoint)
+    }
+
+    /// get_validator_transitions returns the validator sets which take effect at or below the height,
+    /// in ascending order of heights
+    pub fn get_validator_transitions(&self, height: Height) -> Vec<(Height, Vec<Address>)> {
+        let mut transitions: Vec<(Height, Vec<Address>)> = self.schema
+            .validator_transitions()
+            .iter()
+            .filter(|(transition_height, _)| *transition_height <= height)
+            .map(|(transition_height, validators)| (transition_height, validators.addresses().clone()))
+            .collect();
+        transitions.sort_by_key(|(transition_height, _)| *transition_height);
+        transitions
+    }
+
     pub fn reload_meta(&mut self) {
         let hashes = self.schema.block_hashes_by_height();
         let last_hash = hashes.last().unwrap();
@@ -263,6 +343,11 @@ impl Ledger {
         &self.schema
     }
 
+    /// snapshot returns the point-in-time view of the committed chain
+    pub fn snapshot(&self) -> Schema {
+        self.schema.snapshot()
+    }
+
     fn update_meta(&mut self, block: &Block) {
         let header = block.header();
         self.meta.header = header.clone();
@@ -2,5 +2,6 @@ pub mod ledger;
 pub mod genesis;
 pub mod transaction_pool;
 pub mod tx_pool;
+pub mod tx_journal;
 pub mod chain;
 pub mod actor;
@@ -0,0 +1,211 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use cryptocurrency_kit::crypto::CryptoHash;
+use cryptocurrency_kit::storage::values::StorageValue;
+
+use crate::common::decode;
+use crate::types::{Timestamp, transaction::Transaction};
+
+const RECORD_HEAD_SIZE: usize = 12; // |size: 4bytes|timestamp: 8bytes|
+
+/// TxJournal is an append-only file of locally submitted transactions,
+/// it is used to restore the pending transactions of the tx pool after a restart.
+///
+/// record format
+/// |size: 4bytes|timestamp(secs): 8bytes|transaction encode|
+pub struct TxJournal {
+    path: PathBuf,
+    lifetime: Duration,
+    writer: Option<BufWriter<File>>,
+}
+
+impl TxJournal {
+    pub fn new<P: AsRef<Path>>(path: P, lifetime: Duration) -> Self {
+        TxJournal {
+            path: path.as_ref().to_path_buf(),
+            lifetime,
+            writer: None,
+        }
+    }
+
+    /// load parses the journal from disk, the expired and broken records are skipped.
+    /// The callback receives the transaction (hash has been cached) and its journal time.
+    pub fn load<F>(&mut self, now: Timestamp, mut add: F) -> io::Result<usize>
+        where
+            F: FnMut(Transaction, Timestamp),
+    {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+        let mut buf = vec![];
+        BufReader::new(File::open(&self.path)?).read_to_end(&mut buf)?;
+
+        let (mut offset, mut total) = (0, 0);
+        while offset + RECORD_HEAD_SIZE <= buf.len() {
+            let size = BigEndian::read_u32(&buf[offset..]) as usize;
+            let timestamp = BigEndian::read_u64(&buf[offset + 4..]);
+            offset += RECORD_HEAD_SIZE;
+            if offset + size > buf.len() {
+                // the tail record is half written, cause of crash
+                warn!("Transaction journal has a truncated record, drop it");
+                break;
+            }
+            let record = &buf[offset..offset + size];
+            offset += size;
+            if self.is_expired(timestamp, now) {
+                continue;
+            }
+            let mut transaction: Transaction = match decode(record) {
+                Ok(transaction) => transaction,
+                Err(err) => {
+                    warn!("Transaction journal has a broken record, skip it, err: {}", err);
+                    continue;
+                }
+            };
+            let tx_hash = transaction.hash();
+            transaction.set_hash(tx_hash);
+            add(transaction, timestamp);
+            total += 1;
+        }
+        Ok(total)
+    }
+
+    /// insert appends a transaction into the journal
+    pub fn insert(&mut self, transaction: &Transaction, timestamp: Timestamp) -> io::Result<()> {
+        if self.writer.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.writer = Some(BufWriter::new(file));
+        }
+        let writer = self.writer.as_mut().unwrap();
+        write_record(writer, transaction, timestamp)?;
+        writer.flush()
+    }
+
+    /// rotate regenerates the journal with the alive transactions of the pool
+    pub fn rotate(&mut self, transactions: Vec<(&Transaction, Timestamp)>, now: Timestamp) -> io::Result<usize> {
+        // close the current journal
+        self.writer.take();
+
+        let tmp_path = self.path.with_extension("new");
+        let mut total = 0;
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            for (transaction, timestamp) in transactions {
+                if self.is_expired(timestamp, now) {
+                    continue;
+                }
+                write_record(&mut writer, transaction, timestamp)?;
+                total += 1;
+            }
+            writer.flush()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.writer = Some(BufWriter::new(file));
+        debug!("Regenerated transaction journal, transactions: {}", total);
+        Ok(total)
+    }
+
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    pub fn is_expired(&self, timestamp: Timestamp, now: Timestamp) -> bool {
+        timestamp + self.lifetime.as_secs() < now
+    }
+}
+
+fn write_record<W: Write>(writer: &mut W, transaction: &Transaction, timestamp: Timestamp) -> io::Result<()> {
+    let payload = transaction.clone().into_bytes();
+    writer.write_u32::<BigEndian>(payload.len() as u32)?;
+    writer.write_u64::<BigEndian>(timestamp)?;
+    writer.write_all(&payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::random_dir;
+    use cryptocurrency_kit::ethkey::{Address, Generator, Random};
+
+    fn new_transaction(nonce: u64) -> Transaction {
+        let key_pair = Random.generate().unwrap();
+        let mut transaction = Transaction::new(nonce, Address::from(100), 10, 10, 1, vec![1, 2, 3]);
+        transaction.sign(10, key_pair.secret());
+        transaction
+    }
+
+    fn journal_path() -> PathBuf {
+        let dir = *random_dir();
+        fs::create_dir_all(&dir).unwrap();
+        Path::new(&dir).join("transactions.journal")
+    }
+
+    #[test]
+    fn t_journal_load() {
+        let path = journal_path();
+        let mut journal = TxJournal::new(&path, Duration::from_secs(60));
+        let transactions: Vec<Transaction> = (0..10).map(new_transaction).collect();
+        transactions.iter().for_each(|transaction| {
+            journal.insert(transaction, 100).unwrap();
+        });
+
+        let mut journal = TxJournal::new(&path, Duration::from_secs(60));
+        let mut loaded = vec![];
+        assert_eq!(journal.load(120, |transaction, _| loaded.push(transaction)).unwrap(), 10);
+        for (a, b) in transactions.iter().zip(loaded.iter()) {
+            assert_eq!(a.hash(), *b.get_hash().unwrap());
+        }
+
+        // all of them are expired
+        assert_eq!(journal.load(161, |_, _| {}).unwrap(), 0);
+    }
+
+    #[test]
+    fn t_journal_rotate() {
+        let path = journal_path();
+        let mut journal = TxJournal::new(&path, Duration::from_secs(60));
+        let (tx1, tx2) = (new_transaction(1), new_transaction(2));
+        journal.insert(&tx1, 10).unwrap();
+        journal.insert(&tx2, 100).unwrap();
+
+        assert_eq!(journal.rotate(vec![(&tx1, 10), (&tx2, 100)], 100).unwrap(), 1);
+        let mut loaded = vec![];
+        assert_eq!(journal.load(100, |transaction, _| loaded.push(transaction)).unwrap(), 1);
+        assert_eq!(*loaded[0].get_hash().unwrap(), tx2.hash());
+    }
+
+    #[test]
+    fn t_journal_truncated() {
+        let path = journal_path();
+        let mut journal = TxJournal::new(&path, Duration::from_secs(60));
+        journal.insert(&new_transaction(1), 10).unwrap();
+        journal.insert(&new_transaction(2), 10).unwrap();
+        {
+            let len = fs::metadata(&path).unwrap().len();
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.set_len(len - 3).unwrap();
+        }
+        assert_eq!(journal.load(10, |_, _| {}).unwrap(), 1);
+    }
+
+    #[test]
+    fn t_journal_broken() {
+        let path = journal_path();
+        let mut journal = TxJournal::new(&path, Duration::from_secs(60));
+        journal.insert(&new_transaction(1), 10).unwrap();
+        {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_u32::<BigEndian>(3).unwrap();
+            file.write_u64::<BigEndian>(10).unwrap();
+            file.write_all(&[1, 2, 3]).unwrap();
+        }
+        journal.insert(&new_transaction(2), 10).unwrap();
+        assert_eq!(journal.load(10, |_, _| {}).unwrap(), 2);
+    }
+}
@@ -1,5 +1,5 @@
 use std::sync::Arc;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
 use ::actix::prelude::*;
 use priority_queue::PriorityQueue;
@@ -7,9 +7,11 @@ use cryptocurrency_kit::crypto::{Hash, hash, EMPTY_HASH};
 use evmap::{self, WriteHandle, ReadHandle};
 
 use crate::{
-    types::transaction::Transaction,
+    types::{Timestamp, transaction::Transaction},
     error::TxPoolError,
+    metrics,
 };
+use super::tx_journal::TxJournal;
 
 pub const MAX_TXPOOL_SIZE: u64 = 10_000_000;
 pub const MAX_SLOT_SIZE: u32 = 1_000;
@@ -21,6 +23,14 @@ pub trait TxPool {
     fn add_tx(&mut self, transaction: Transaction) -> Result<u64, TxPoolError>;
     fn add_txs(&mut self, transactions: &Vec<Transaction>) -> Result<u64, TxPoolError>;
     fn remove_txs(&mut self, tx_hashes: Vec<&Hash>);
+    /// add a transaction submitted by local client, it will be journaled if the journal is enabled
+    fn add_local_tx(&mut self, transaction: Transaction) -> Result<u64, TxPoolError> {
+        self.add_tx(transaction)
+    }
+    /// regenerate the journal with the pending local transactions
+    fn rejournal(&mut self) -> Result<(), TxPoolError> {
+        Ok(())
+    }
 }
 
 pub type SafeTxPool = Box<TxPool + Send + Sync>;
@@ -28,6 +38,9 @@ pub type SafeTxPool = Box<TxPool + Send + Sync>;
 pub struct BaseTxPool {
     pq: PriorityQueue<Hash, u64>,
     txs: Vec<BTreeMap<Hash, Transaction>>,
+    // local transactions and their journal time
+    locals: HashMap<Hash, Timestamp>,
+    journal: Option<TxJournal>,
 }
 
 impl Actor for BaseTxPool {
@@ -66,6 +79,7 @@ impl TxPool for BaseTxPool {
         }
         v.insert(tx.get_hash().unwrap().clone(), tx.clone());
         self.pq.push(tx.get_hash().unwrap().clone(), tx.amount());
+        metrics::TX_POOL_SIZE.set(self.pq.len() as i64);
         Ok(self.pq.len() as u64)
     }
 
@@ -83,7 +97,53 @@ impl TxPool for BaseTxPool {
             let idx = self.get_idx(tx_hash);
             let m: &mut BTreeMap<_, _> = self.txs.get_mut(idx).unwrap();
             m.remove(tx_hash);
+            self.pq.remove(tx_hash);
+            self.locals.remove(tx_hash);
         });
+        metrics::TX_POOL_SIZE.set(self.pq.len() as i64);
+    }
+
+    fn add_local_tx(&mut self, tx: Transaction) -> Result<u64, TxPoolError> {
+        let tx_hash = tx.get_hash().unwrap().clone();
+        if self.locals.contains_key(&tx_hash) {
+            return Ok(self.pq.len() as u64);
+        }
+        let now = chrono::Local::now().timestamp() as Timestamp;
+        if let Some(ref mut journal) = self.journal {
+            journal.insert(&tx, now).map_err(|err| TxPoolError::Journal(err.to_string()))?;
+        }
+        self.locals.insert(tx_hash, now);
+        self.add_tx(tx)
+    }
+
+    fn rejournal(&mut self) -> Result<(), TxPoolError> {
+        if self.journal.is_none() {
+            return Ok(());
+        }
+        let now = chrono::Local::now().timestamp() as Timestamp;
+        let expired: Vec<Hash> = {
+            let journal = self.journal.as_ref().unwrap();
+            self.locals.iter()
+                .filter(|(_, timestamp)| journal.is_expired(**timestamp, now))
+                .map(|(tx_hash, _)| tx_hash.clone())
+                .collect()
+        };
+        if !expired.is_empty() {
+            debug!("Drop expired local transactions, size: {}", expired.len());
+            self.remove_txs(expired.iter().collect());
+        }
+
+        let mut locals = vec![];
+        for (tx_hash, timestamp) in &self.locals {
+            let idx = self.get_idx(tx_hash);
+            if let Some(tx) = self.txs[idx].get(tx_hash) {
+                locals.push((tx, *timestamp));
+            }
+        }
+        self.journal.as_mut().unwrap()
+            .rotate(locals, now)
+            .map(|_| ())
+            .map_err(|err| TxPoolError::Journal(err.to_string()))
     }
 }
 
@@ -93,12 +153,46 @@ impl BaseTxPool {
         let mut tx_pool = BaseTxPool {
             pq: PriorityQueue::new(),
             txs: Vec::with_capacity(n),
+            locals: HashMap::new(),
+            journal: None,
         };
         (0..n).for_each(|_| {
             tx_pool.txs.push(BTreeMap::new());
         });
         tx_pool
     }
+
+    /// Create a tx pool with a journal, the journaled transactions are reloaded and
+    /// revalidated by `validate`, the invalid or expired transactions are dropped.
+    pub fn with_journal<F>(mut journal: TxJournal, validate: F) -> Result<Self, TxPoolError>
+        where
+            F: Fn(&Transaction) -> bool,
+    {
+        let mut tx_pool = BaseTxPool::new();
+        let now = chrono::Local::now().timestamp() as Timestamp;
+        let (mut total, mut dropped) = (0, 0);
+        journal.load(now, |tx, timestamp| {
+            total += 1;
+            if !validate(&tx) {
+                dropped += 1;
+                return;
+            }
+            let tx_hash = tx.get_hash().unwrap().clone();
+            if let Err(err) = tx_pool.add_tx(tx) {
+                warn!("Skip journaled transaction {:?}, err: {}", tx_hash, err);
+                dropped += 1;
+                return;
+            }
+            tx_pool.locals.insert(tx_hash, timestamp);
+        }).map_err(|err| TxPoolError::Journal(err.to_string()))?;
+        info!("Loaded local transaction journal, transactions: {}, dropped: {}", total, dropped);
+
+        tx_pool.journal = Some(journal);
+        // drop the stale records
+        tx_pool.rejournal()?;
+        Ok(tx_pool)
+    }
+
     fn get_idx(&self, tx_hash: &Hash) -> usize {
         use ethereum_types::U256;
         let u = U256::from(tx_hash.as_ref());
@@ -125,4 +219,39 @@ mod tests {
 //        let mut v = vec![];
         (0..10_0000).for_each(|_idx| {})
     }
+
+    #[test]
+    fn t_txpool_journal() {
+        use std::time::Duration;
+        use cryptocurrency_kit::ethkey::{Address, Generator, Random};
+        use cryptocurrency_kit::crypto::CryptoHash;
+        use crate::common::random_dir;
+
+        let dir = *random_dir();
+        ::std::fs::create_dir_all(&dir).unwrap();
+        let path = ::std::path::Path::new(&dir).join("transactions.journal");
+        let key_pair = Random.generate().unwrap();
+        let mut tx_hashes = vec![];
+        {
+            let journal = TxJournal::new(&path, Duration::from_secs(60));
+            let mut tx_pool = BaseTxPool::with_journal(journal, |_| true).unwrap();
+            (0..10).for_each(|nonce| {
+                let mut tx = Transaction::new(nonce, Address::from(100), 10, 10, 1, vec![]);
+                tx.sign(10, key_pair.secret());
+                tx.set_hash(tx.hash());
+                tx_hashes.push(tx.hash());
+                tx_pool.add_local_tx(tx).unwrap();
+            });
+            tx_pool.remove_txs(vec![&tx_hashes[0]]);
+            tx_pool.rejournal().unwrap();
+        }
+
+        let journal = TxJournal::new(&path, Duration::from_secs(60));
+        let tx_pool = BaseTxPool::with_journal(journal, |tx| *tx.get_hash().unwrap() != tx_hashes[1]).unwrap();
+        assert!(tx_pool.get_tx(&tx_hashes[0]).is_none());
+        assert!(tx_pool.get_tx(&tx_hashes[1]).is_none());
+        tx_hashes[2..].iter().for_each(|tx_hash| {
+            assert!(tx_pool.get_tx(tx_hash).is_some());
+        });
+    }
 }
\ No newline at end of file
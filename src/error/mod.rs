@@ -6,6 +6,8 @@ use cryptocurrency_kit::crypto::Hash;
 pub enum TxPoolError {
     #[fail(display = "More than max txpool limit, max:{}", _0)]
     MoreThanMaxSIZE(u64),
+    #[fail(display = "Transaction journal error, ({})", _0)]
+    Journal(String),
 }
 
 #[derive(Debug, Fail)]
@@ -20,6 +22,12 @@ pub enum P2PError {
     InvalidMessage,
     #[fail(display = "Timeout")]
     Timeout,
+    #[fail(display = "Too many peers")]
+    TooManyPeers,
+    #[fail(display = "Banned peer")]
+    Banned,
+    #[fail(display = "Incompatible protocol version")]
+    IncompatibleVersion,
 }
 
 pub type ChainResult = Result<(), ChainError>;
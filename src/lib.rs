@@ -29,6 +29,8 @@ extern crate log;
 extern crate env_logger;
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate prometheus;
 
 pub mod common;
 pub mod util;
@@ -47,4 +49,6 @@ pub mod cmd;
 pub mod config;
 pub mod logger;
 pub mod mocks;
-pub mod api;
\ No newline at end of file
+pub mod api;
+pub mod light;
+pub mod metrics;
\ No newline at end of file
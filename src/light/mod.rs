@@ -0,0 +1,292 @@
+use std::collections::HashSet;
+
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+
+use crate::{
+    common::merkle::MerkleProof,
+    types::{Height, block::Header, transaction::Transaction, votes::decrypt_commit_bytes},
+};
+
+pub type LightResult = Result<(), LightError>;
+
+#[derive(Debug, Fail)]
+pub enum LightError {
+    #[fail(display = "Discontinuous header, expect: {}, got: {}", _0, _1)]
+    DiscontinuousHeader(Height, Height),
+    #[fail(display = "Header is not linked to parent, height: {}", _0)]
+    InvalidParent(Height),
+    #[fail(display = "Invalid commit seal, height: {}", _0)]
+    InvalidSeal(Height),
+    #[fail(display = "Lack seals, height: {}, expect: {}, got: {}", _0, _1, _2)]
+    LackSeals(Height, usize, usize),
+    #[fail(display = "Invalid proposer, height: {}", _0)]
+    InvalidProposer(Height),
+    #[fail(display = "Invalid validator set change, height: {}", _0)]
+    InvalidValidatorChange(Height),
+    #[fail(display = "Invalid inclusion proof")]
+    InvalidProof,
+}
+
+/// The validator set which takes effect from the height
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidatorChange {
+    pub height: Height,
+    pub validators: Vec<Address>,
+}
+
+/// The sealed header at which the validator set changes, the new set is used from the next header
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidatorTransition {
+    pub header: Header,
+    pub validators: Vec<Address>,
+}
+
+/// LightClient follows the chain by headers only.
+///
+/// It starts from a trusted header and its validator set, every following header must be linked
+/// to the parent and sealed by more than 2/3 of the validators. The validator set is changed by
+/// `ValidatorChange`, it is accepted if the header of the change height is sealed by both sets.
+pub struct LightClient {
+    header: Header,
+    validators: Vec<Address>,
+}
+
+impl LightClient {
+    pub fn new(trusted: Header, validators: Vec<Address>) -> Self {
+        LightClient { header: trusted, validators }
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn height(&self) -> Height {
+        self.header.height
+    }
+
+    pub fn validators(&self) -> &Vec<Address> {
+        &self.validators
+    }
+
+    /// verify_header checks the header is the child of the current header
+    pub fn verify_header(&self, header: &Header) -> LightResult {
+        self.verify_parent(header)?;
+        verify_seals(header, &self.validators)
+    }
+
+    fn verify_parent(&self, header: &Header) -> LightResult {
+        if header.height != self.header.height + 1 {
+            return Err(LightError::DiscontinuousHeader(self.header.height + 1, header.height));
+        }
+        if header.prev_hash != self.header.block_hash() {
+            return Err(LightError::InvalidParent(header.height));
+        }
+        if !self.validators.contains(&header.proposer) {
+            return Err(LightError::InvalidProposer(header.height));
+        }
+        Ok(())
+    }
+
+    /// update verifies the header and moves to it
+    pub fn update(&mut self, header: Header) -> LightResult {
+        self.verify_header(&header)?;
+        self.header = header;
+        Ok(())
+    }
+
+    /// update_validators verifies the header with the validator change and moves to it,
+    /// the new validator set is used from the next header
+    pub fn update_validators(&mut self, header: Header, change: ValidatorChange) -> LightResult {
+        if change.height != header.height {
+            return Err(LightError::InvalidValidatorChange(change.height));
+        }
+        // the header is sealed by both of the old and new validators
+        self.verify_parent(&header)?;
+        verify_validator_transition(&header, &self.validators)?;
+        verify_validator_transition(&header, &change.validators)?;
+        self.header = header;
+        self.validators = change.validators;
+        Ok(())
+    }
+
+    /// verify_headers follows the continuous headers, the changes are applied at their heights
+    pub fn verify_headers(&mut self, headers: Vec<Header>, changes: Vec<ValidatorChange>) -> LightResult {
+        let mut changes = changes.into_iter().peekable();
+        for header in headers {
+            let change = match changes.peek() {
+                Some(change) if change.height == header.height => changes.next(),
+                Some(change) if change.height < header.height => return Err(LightError::InvalidValidatorChange(change.height)),
+                _ => None,
+            };
+            match change {
+                Some(change) => self.update_validators(header, change)?,
+                None => self.update(header)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// verify_seals checks all of the commit seals are signed by the validators,
+/// and more than 2/3 of the validators have signed
+pub fn verify_seals(header: &Header, validators: &[Address]) -> LightResult {
+    let need = validators.len() * 2 / 3 + 1;
+    let votes = header.votes.as_ref().ok_or_else(|| LightError::LackSeals(header.height, need, 0))?;
+    let digest = header.block_hash();
+    if !votes.verify_signs(digest, |address| validators.contains(&address)) {
+        return Err(LightError::InvalidSeal(header.height));
+    }
+    let signers: HashSet<Address> = votes.votes().iter().filter_map(|vote| decrypt_commit_bytes(&digest, vote).ok()).collect();
+    if signers.len() < need {
+        return Err(LightError::LackSeals(header.height, need, signers.len()));
+    }
+    Ok(())
+}
+
+/// verify_validator_transition checks more than 2/3 of the validators have sealed the header,
+/// the seals of the other signers are ignored
+pub fn verify_validator_transition(header: &Header, validators: &[Address]) -> LightResult {
+    let validators: HashSet<Address> = validators.iter().cloned().collect();
+    if validators.is_empty() {
+        return Err(LightError::InvalidValidatorChange(header.height));
+    }
+    let digest = header.block_hash();
+    let signers: HashSet<Address> = header.votes
+        .as_ref()
+        .map(|votes| votes.votes().iter().filter_map(|vote| decrypt_commit_bytes(&digest, vote).ok()).collect())
+        .unwrap_or_default();
+    let sealed = signers.iter().filter(|signer| validators.contains(signer)).count();
+    let need = validators.len() * 2 / 3 + 1;
+    if sealed < need {
+        return Err(LightError::LackSeals(header.height, need, sealed));
+    }
+    Ok(())
+}
+
+/// verify_validator_transitions follows the validator set from the trusted one through the transitions
+/// of ascending heights, every transition header is sealed by both of the old and new sets.
+/// It returns the validator set after the last transition.
+pub fn verify_validator_transitions(trusted: &[Address], transitions: &[ValidatorTransition]) -> Result<Vec<Address>, LightError> {
+    let mut validators = trusted.to_vec();
+    let mut last_height = None;
+    for transition in transitions {
+        let height = transition.header.height;
+        if last_height.map_or(false, |last_height| height <= last_height) {
+            return Err(LightError::InvalidValidatorChange(height));
+        }
+        verify_validator_transition(&transition.header, &validators)?;
+        verify_validator_transition(&transition.header, &transition.validators)?;
+        validators = transition.validators.clone();
+        last_height = Some(height);
+    }
+    Ok(validators)
+}
+
+/// verify_transaction checks the transaction is included in the block of the verified header
+pub fn verify_transaction(header: &Header, transaction: &Transaction, proof: &MerkleProof) -> LightResult {
+    verify_inclusion(&header.tx_hash, &transaction.clone().into_bytes(), proof)
+}
+
+pub fn verify_inclusion(root: &Hash, item: &[u8], proof: &MerkleProof) -> LightResult {
+    if proof.verify(item, root) {
+        Ok(())
+    } else {
+        Err(LightError::InvalidProof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::{Generator, KeyPair, Random};
+    use crate::common::{merkle_tree, merkle_tree_proof};
+    use crate::types::votes::{encrypt_commit_bytes, Votes};
+
+    fn new_key_pairs(n: usize) -> Vec<KeyPair> {
+        (0..n).map(|_| Random.generate().unwrap()).collect()
+    }
+
+    fn addresses(key_pairs: &[KeyPair]) -> Vec<Address> {
+        key_pairs.iter().map(|key_pair| key_pair.address()).collect()
+    }
+
+    fn new_header(parent: &Header, proposer: Address, signers: &[KeyPair]) -> Header {
+        let mut header = Header::new_mock(parent.block_hash(), proposer, EMPTY_HASH, parent.height + 1, parent.time + 1, None);
+        let digest = header.block_hash();
+        header.votes = Some(Votes::new(signers.iter().map(|key_pair| encrypt_commit_bytes(&digest, key_pair.secret())).collect()));
+        header
+    }
+
+    #[test]
+    fn t_light_update() {
+        let key_pairs = new_key_pairs(4);
+        let trusted = Header::new_mock(EMPTY_HASH, key_pairs[0].address(), EMPTY_HASH, 10, 100, None);
+        let mut client = LightClient::new(trusted.clone(), addresses(&key_pairs));
+
+        let header = new_header(&trusted, key_pairs[0].address(), &key_pairs[..3]);
+        assert!(client.verify_header(&header).is_ok());
+
+        // lack seals
+        let lack = new_header(&trusted, key_pairs[0].address(), &key_pairs[..2]);
+        assert!(client.verify_header(&lack).is_err());
+
+        // sealed by stranger
+        let mut signers = key_pairs[..3].to_vec();
+        signers.push(Random.generate().unwrap());
+        let stranger = new_header(&trusted, key_pairs[0].address(), &signers);
+        assert!(client.verify_header(&stranger).is_err());
+
+        client.update(header.clone()).unwrap();
+        assert_eq!(client.height(), 11);
+        // not linked
+        assert!(client.verify_header(&new_header(&trusted, key_pairs[0].address(), &key_pairs)).is_err());
+    }
+
+    #[test]
+    fn t_light_validator_change() {
+        let (old, new) = (new_key_pairs(4), new_key_pairs(4));
+        let trusted = Header::new_mock(EMPTY_HASH, old[0].address(), EMPTY_HASH, 10, 100, None);
+        let change = ValidatorChange { height: 11, validators: addresses(&new) };
+
+        // the new validators have not sealed the change
+        let h11 = new_header(&trusted, old[0].address(), &old);
+        let mut client = LightClient::new(trusted.clone(), addresses(&old));
+        assert!(client.update_validators(h11, change.clone()).is_err());
+
+        let mut signers = old.clone();
+        signers.extend_from_slice(&new[..3]);
+        let h11 = new_header(&trusted, old[0].address(), &signers);
+        let h12 = new_header(&h11, new[0].address(), &new[..3]);
+        // the change is missing, h11 has the seals of strangers
+        assert!(client.verify_headers(vec![h11.clone(), h12.clone()], vec![]).is_err());
+
+        let mut client = LightClient::new(trusted.clone(), addresses(&old));
+        client.verify_headers(vec![h11.clone(), h12.clone()], vec![change.clone()]).unwrap();
+        assert_eq!(client.height(), 12);
+        assert_eq!(client.validators(), &addresses(&new));
+
+        // the change is skipped
+        let mut client = LightClient::new(trusted.clone(), addresses(&old));
+        let stale = ValidatorChange { height: 10, validators: addresses(&new) };
+        assert!(client.verify_headers(vec![h11, h12], vec![stale]).is_err());
+    }
+
+    #[test]
+    fn t_verify_transaction() {
+        let key_pair = Random.generate().unwrap();
+        let transactions: Vec<Transaction> = (0..5).map(|nonce| {
+            let mut transaction = Transaction::new(nonce, Address::from(100), 10, 10, 1, vec![1, 2, 3]);
+            transaction.sign(10, key_pair.secret());
+            transaction
+        }).collect();
+        let mut header = Header::new_mock(EMPTY_HASH, key_pair.address(), EMPTY_HASH, 1, 100, None);
+        header.tx_hash = merkle_tree(transactions.clone()).root();
+
+        let proof = merkle_tree_proof(transactions.clone(), 3).unwrap();
+        assert!(verify_transaction(&header, &transactions[3], &proof).is_ok());
+        assert!(verify_transaction(&header, &transactions[2], &proof).is_err());
+    }
+}
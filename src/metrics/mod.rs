@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use prometheus::{Encoder, Histogram, IntCounter, IntCounterVec, IntGauge, TextEncoder};
+
+lazy_static! {
+    pub static ref CHAIN_HEIGHT: IntGauge = register_int_gauge!(
+        "chain_block_height",
+        "Height of the latest block"
+    ).unwrap();
+    pub static ref CONSENSUS_COMMIT_LATENCY: Histogram = register_histogram!(
+        "consensus_commit_latency_seconds",
+        "Time from starting a height to committing its block",
+        vec![0.1, 0.25, 0.5, 1.0, 2.0, 3.0, 5.0, 10.0, 20.0, 60.0]
+    ).unwrap();
+    pub static ref CONSENSUS_ROUNDS: Histogram = register_histogram!(
+        "consensus_rounds_per_height",
+        "Number of rounds used to commit a height",
+        vec![1.0, 2.0, 3.0, 4.0, 5.0, 8.0, 13.0]
+    ).unwrap();
+    pub static ref CONSENSUS_ROUND_CHANGES: IntCounter = register_int_counter!(
+        "consensus_round_changes_total",
+        "Number of new rounds started by round change"
+    ).unwrap();
+    pub static ref CONSENSUS_MESSAGES_RECEIVED: IntCounterVec = register_int_counter_vec!(
+        "consensus_messages_received_total",
+        "Number of consensus messages received, by message type",
+        &["type"]
+    ).unwrap();
+    pub static ref CONSENSUS_MESSAGES_DROPPED: IntCounterVec = register_int_counter_vec!(
+        "consensus_messages_dropped_total",
+        "Number of consensus messages failed to handle, by message type",
+        &["type"]
+    ).unwrap();
+    pub static ref P2P_INVALID_BLOCKS: IntCounter = register_int_counter!(
+        "p2p_invalid_blocks_total",
+        "Number of blocks from network rejected by verification"
+    ).unwrap();
+    pub static ref P2P_PEERS: IntGauge = register_int_gauge!(
+        "p2p_peers",
+        "Number of connected peers"
+    ).unwrap();
+    pub static ref P2P_MISBEHAVIORS: IntCounterVec = register_int_counter_vec!(
+        "p2p_misbehaviors_total",
+        "Number of misbehaviors of peers, by misbehavior type",
+        &["type"]
+    ).unwrap();
+    pub static ref P2P_BANNED_PEERS: IntCounter = register_int_counter!(
+        "p2p_banned_peers_total",
+        "Number of peers banned by reputation"
+    ).unwrap();
+    pub static ref P2P_PING_RTT: Histogram = register_histogram!(
+        "p2p_ping_rtt_seconds",
+        "Round trip time of the pings to peers",
+        vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]
+    ).unwrap();
+    pub static ref P2P_KAD_NODES: IntGauge = register_int_gauge!(
+        "p2p_kad_nodes",
+        "Number of nodes in the kademlia routing table"
+    ).unwrap();
+    pub static ref TX_POOL_SIZE: IntGauge = register_int_gauge!(
+        "tx_pool_size",
+        "Number of pending transactions in the tx pool"
+    ).unwrap();
+    pub static ref STORE_WRITE_LATENCY: Histogram = register_histogram!(
+        "store_write_latency_seconds",
+        "Latency of writing and flushing a batch of changes into rocksdb",
+        vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5]
+    ).unwrap();
+}
+
+/// gather encodes all of the registered metrics by prometheus text format
+pub fn gather() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+pub fn duration_to_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_gather() {
+        CHAIN_HEIGHT.set(10);
+        CONSENSUS_MESSAGES_RECEIVED.with_label_values(&["Prepare"]).inc();
+        let output = gather();
+        assert!(output.contains("chain_block_height 10"));
+        assert!(output.contains("consensus_messages_received_total{type=\"Prepare\"} 1"));
+    }
+}
@@ -111,7 +111,7 @@ impl Minner {
 //        mock_transactions.push(coinbase);
 
         let pre_hash: Hash = pre_header.block_hash();
-        let tx_hash = merkle_root_transactions(vec![coinbase.clone()]);
+        let tx_hash = merkle_root_transactions(vec![coinbase.clone()], pre_header.height + 1, self.chain.config.merkle_upgrade_height);
         let extra = Vec::from("Coinse base");
 
         let mut header = Header::new_mock(pre_hash, self.minter, tx_hash, pre_header.height + 1, next_time, Some(extra));
@@ -164,7 +164,7 @@ mod test {
         transaction.sign(100, Random.generate().unwrap().secret());
 
         let coinbase = transaction;
-        let tx_hash = merkle_root_transactions(vec![coinbase.clone()]);
+        let tx_hash = merkle_root_transactions(vec![coinbase.clone()], 1, None);
         println!("coin base hash: {:?}", tx_hash);
     }
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
-use std::borrow::Cow;
+use std::cell::Cell;
 use std::io;
+use std::rc::Rc;
 
 use byteorder::{BigEndian, ByteOrder};
 use bytes::{BufMut, BytesMut};
@@ -7,12 +8,87 @@ use cryptocurrency_kit::storage::values::StorageValue;
 use tokio::codec::{Decoder, Encoder};
 
 use super::protocol::*;
+use super::secure::{FrameCipher, TAG_SIZE};
+use crate::common::decode;
 
-pub const MAX_MSG_SIZE: u32 = 1 << 10;
+pub const MAX_MSG_SIZE: u32 = 16 << 20;
 pub const MSG_SIZE: u32 = 4; // byte
+// the frames larger than it are compressed by snappy
+pub const COMPRESS_THRESHOLD: usize = 4 << 10;
 
-// |msg_size: 4bytes| msg encode |
-pub struct MsgPacketCodec;
+const FLAG_COMPRESSED: u8 = 0x01;
+const BODY_HEAD_SIZE: usize = 2; // |version: 1byte|flags: 1byte|
+
+/// SessionVersion is the protocol version of a session shared by its read and write codecs,
+/// it's zero until the handshake negotiates it
+pub type SessionVersion = Rc<Cell<u8>>;
+
+// |msg_size: 4bytes| body |
+// body: |version: 1byte|flags: 1byte| msg encode (snappy compressed if the flag is set) |
+// the handshake is sent by the lowest supported version, the frames after it by the negotiated one
+// the body is sealed by the cipher if it is set: |msg_size: 4bytes| ciphertext | tag: 16bytes |,
+// msg_size covers the tag and it's authenticated as well
+pub struct MsgPacketCodec {
+    cipher: Option<FrameCipher>,
+    max_frame_size: u32,
+    compression: bool,
+    version: SessionVersion,
+}
+
+impl MsgPacketCodec {
+    pub fn new(cipher: Option<FrameCipher>, max_frame_size: u32, compression: bool, version: SessionVersion) -> Self {
+        MsgPacketCodec { cipher, max_frame_size, compression, version }
+    }
+
+    /// supports_version accepts the supported versions before the negotiation, only the negotiated one after it
+    fn supports_version(&self, version: u8) -> bool {
+        match self.version.get() {
+            0 => MIN_PROTOCOL_VERSION <= version as u32 && version as u32 <= MAX_PROTOCOL_VERSION,
+            negotiated => version == negotiated,
+        }
+    }
+
+    fn decode_body(&self, body: &[u8]) -> io::Result<RawMessage> {
+        if body.len() < BODY_HEAD_SIZE {
+            return Err(invalid_data("frame is too short".to_string()));
+        }
+        if !self.supports_version(body[0]) {
+            return Err(invalid_data(format!("unsupported protocol version: {}", body[0])));
+        }
+        let msg = &body[BODY_HEAD_SIZE..];
+        let decompressed;
+        let msg = if body[1] & FLAG_COMPRESSED != 0 {
+            let size = snap::decompress_len(msg).map_err(|err| invalid_data(err.to_string()))?;
+            if size > self.max_frame_size as usize {
+                return Err(invalid_data(format!("decompressed frame is too large: {}", size)));
+            }
+            decompressed = snap::Decoder::new().decompress_vec(msg).map_err(|err| invalid_data(err.to_string()))?;
+            &decompressed[..]
+        } else {
+            msg
+        };
+        decode(msg).map_err(|err| invalid_data(format!("malformed message: {}", err)))
+    }
+
+    fn encode_body(&self, msg: RawMessage) -> io::Result<Vec<u8>> {
+        let msg = msg.into_bytes();
+        let version = match self.version.get() {
+            0 => MIN_PROTOCOL_VERSION as u8,
+            negotiated => negotiated,
+        };
+        let mut body = vec![version, 0];
+        if self.compression && msg.len() > COMPRESS_THRESHOLD {
+            let compressed = snap::Encoder::new().compress_vec(&msg).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            if compressed.len() < msg.len() {
+                body[1] |= FLAG_COMPRESSED;
+                body.extend_from_slice(&compressed);
+                return Ok(body);
+            }
+        }
+        body.extend_from_slice(&msg);
+        Ok(body)
+    }
+}
 
 impl Decoder for MsgPacketCodec {
     type Item = RawMessage;
@@ -26,13 +102,20 @@ impl Decoder for MsgPacketCodec {
             }
             BigEndian::read_u32(src.as_ref())
         };
+        if size > self.max_frame_size {
+            return Err(invalid_data(format!("frame is too large: {}", size)));
+        }
 
         if src.len() >= (size + MSG_SIZE) as usize {
-            src.split_to(MSG_SIZE as usize);
+            let size_buf = src.split_to(MSG_SIZE as usize);
             let buf = src.split_to(size as usize);
-            let raw_message: RawMessage = RawMessage::from_bytes(Cow::from(buf.to_vec()));
-            Ok(Some(raw_message))
+            let buf = match self.cipher {
+                Some(ref mut cipher) => cipher.open(&size_buf, &buf)?,
+                None => buf.to_vec(),
+            };
+            self.decode_body(&buf).map(Some)
         } else {
+            src.reserve((size + MSG_SIZE) as usize - src.len());
             Ok(None)
         }
     }
@@ -43,11 +126,116 @@ impl Encoder for MsgPacketCodec {
     type Error = io::Error;
 
     fn encode(&mut self, msg: RawMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let msg = msg.into_bytes();
-        let size = msg.len() as u32;
+        let body = self.encode_body(msg)?;
+        let sealed_size = body.len() + if self.cipher.is_some() { TAG_SIZE } else { 0 };
+        if sealed_size > self.max_frame_size as usize {
+            return Err(invalid_data(format!("frame is too large: {}", sealed_size)));
+        }
+        let body = match self.cipher {
+            Some(ref mut cipher) => {
+                let mut size_buf = [0; MSG_SIZE as usize];
+                BigEndian::write_u32(&mut size_buf, sealed_size as u32);
+                cipher.seal(&size_buf, &body)
+            }
+            None => body,
+        };
+        let size = body.len() as u32;
         dst.reserve((size + MSG_SIZE) as usize);
         dst.put_u32_be(size);
-        dst.put(msg);
+        dst.put(body);
         Ok(())
     }
 }
+
+fn invalid_data(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::hash;
+
+    fn new_codec(cipher: Option<FrameCipher>, max_frame_size: u32) -> MsgPacketCodec {
+        MsgPacketCodec::new(cipher, max_frame_size, true, SessionVersion::default())
+    }
+
+    fn new_msg(payload: Vec<u8>) -> RawMessage {
+        RawMessage::new(Header::new(P2PMsgCode::Ping, 3, 1, None), payload)
+    }
+
+    #[test]
+    fn t_encrypted_codec() {
+        let key = hash(&[1]);
+        let mut encoder = new_codec(Some(FrameCipher::new(key)), MAX_MSG_SIZE);
+        let mut decoder = new_codec(Some(FrameCipher::new(key)), MAX_MSG_SIZE);
+        let msg = new_msg(b"hello".to_vec());
+        let mut buf = BytesMut::new();
+        encoder.encode(msg.clone(), &mut buf).unwrap();
+        encoder.encode(msg.clone(), &mut buf).unwrap();
+
+        let mut tampered = buf.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+
+        assert_eq!(decoder.decode(&mut buf).unwrap().unwrap().payload(), msg.payload());
+        assert_eq!(decoder.decode(&mut buf).unwrap().unwrap().payload(), msg.payload());
+        assert!(decoder.decode(&mut buf).unwrap().is_none());
+
+        let mut decoder = new_codec(Some(FrameCipher::new(key)), MAX_MSG_SIZE);
+        decoder.decode(&mut tampered).unwrap();
+        assert!(decoder.decode(&mut tampered).is_err());
+    }
+
+    #[test]
+    fn t_codec_limits() {
+        let mut codec = new_codec(None, 16 << 10);
+        // compressed
+        let msg = new_msg(vec![7; 8 << 10]);
+        let mut buf = BytesMut::new();
+        codec.encode(msg.clone(), &mut buf).unwrap();
+        assert!(buf.len() < 1 << 10);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().payload(), msg.payload());
+
+        // too large
+        let msg = new_msg((0..32 << 10).map(|_| rand::random::<u8>()).collect());
+        assert!(codec.encode(msg, &mut buf).is_err());
+        let mut buf = BytesMut::new();
+        buf.put_u32_be(32 << 10);
+        assert!(codec.decode(&mut buf).is_err());
+        let mut buf = BytesMut::new();
+        new_codec(None, MAX_MSG_SIZE).encode(new_msg(vec![7; 32 << 10]), &mut buf).unwrap();
+        assert!(codec.decode(&mut buf).is_err());
+
+        // malformed message and unknown version
+        let mut buf = BytesMut::new();
+        buf.put_u32_be(5);
+        buf.put(&[MIN_PROTOCOL_VERSION as u8, 0, 1, 2, 3][..]);
+        assert!(codec.decode(&mut buf).is_err());
+        let mut buf = BytesMut::new();
+        buf.put_u32_be(2);
+        buf.put(&[MAX_PROTOCOL_VERSION as u8 + 1, 0][..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn t_codec_version() {
+        let version = SessionVersion::default();
+        let mut codec = MsgPacketCodec::new(None, MAX_MSG_SIZE, true, version.clone());
+        let mut buf = BytesMut::new();
+        codec.encode(new_msg(vec![1]), &mut buf).unwrap();
+        assert_eq!(buf[MSG_SIZE as usize], MIN_PROTOCOL_VERSION as u8);
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+
+        // the frames of other versions are refused after the negotiation
+        version.set(MAX_PROTOCOL_VERSION as u8);
+        let mut buf = BytesMut::new();
+        codec.encode(new_msg(vec![1]), &mut buf).unwrap();
+        assert_eq!(buf[MSG_SIZE as usize], MAX_PROTOCOL_VERSION as u8);
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+        let mut buf = BytesMut::new();
+        buf.put_u32_be(2);
+        buf.put(&[MAX_PROTOCOL_VERSION as u8 + 1, 0][..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+}
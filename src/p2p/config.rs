@@ -1,18 +1,183 @@
-#[derive(Default)]
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::time::Duration;
+
+use libp2p::{Multiaddr, PeerId};
+
+use super::codec::MAX_MSG_SIZE;
+use super::kad::parse_bootnode;
+use super::transport::TransportKind;
+
+/// The role of node in the sentry architecture
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeMode {
+    Full,
+    // the validator only connects to its sentries, it's hidden from the discovery
+    Validator,
+    // the sentry relays the messages for the private validators behind it
+    Sentry,
+}
+
+impl Default for NodeMode {
+    fn default() -> Self {
+        NodeMode::Full
+    }
+}
+
+/// Connection limits and peer banning of the tcp server
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    pub transport: TransportKind,
     pub max_inbound: u64,
     pub max_outbound: u64,
+    // max number of the connections of both directions
     pub max_connection_size: u64,
     pub seal: bool,
+    // slots of max_inbound and max_outbound kept for the validator peers
+    pub reserved_validators: u64,
+    // max messages received from a peer per second, the rest are counted as spam
+    pub max_message_rate: u64,
+    // number of the random peers a gossip message is relayed to, besides the validators
+    pub gossip_fanout: usize,
+    // max size of a frame in bytes, the larger frames are rejected
+    pub max_frame_size: u32,
+    // compress the large frames by snappy
+    pub compression: bool,
+    #[serde(with = "serde_millis")]
+    pub ban_duration: Duration,
+    // file of the banned peers, defaults to "p2p_bans.json" under the store
+    pub ban_file: Option<String>,
+    pub mode: NodeMode,
+    // validator mode: the sentries "<peer_id>@<multiaddr>", no other peer is connected
+    pub sentries: Vec<String>,
+    // sentry mode: peer ids of the private validators, they are never advertised by discovery
+    pub private_peers: Vec<String>,
+    #[serde(with = "serde_millis")]
+    pub ping_interval: Duration,
+    // the peer is dropped if nothing is received from it within the timeout
+    #[serde(with = "serde_millis")]
+    pub ping_timeout: Duration,
+    // the first delay of redialing a persistent peer, it's doubled on every failure
+    #[serde(with = "serde_millis")]
+    pub reconnect_backoff: Duration,
+    #[serde(with = "serde_millis")]
+    pub max_reconnect_backoff: Duration,
+    // the peers "<peer_id>@<multiaddr>" which are always redialed after disconnection, besides the bootnodes
+    pub persistent_peers: Vec<String>,
+}
 
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            transport: TransportKind::Tcp,
+            max_inbound: 32,
+            max_outbound: 16,
+            max_connection_size: 48,
+            seal: false,
+            reserved_validators: 8,
+            max_message_rate: 500,
+            gossip_fanout: 6,
+            max_frame_size: MAX_MSG_SIZE,
+            compression: true,
+            ban_duration: Duration::from_millis(60 * 60 * 1000),
+            ban_file: None,
+            mode: NodeMode::Full,
+            sentries: vec![],
+            private_peers: vec![],
+            ping_interval: Duration::from_millis(2 * 1000),
+            ping_timeout: Duration::from_millis(20 * 1000),
+            reconnect_backoff: Duration::from_millis(1000),
+            max_reconnect_backoff: Duration::from_millis(60 * 1000),
+            persistent_peers: vec![],
+        }
+    }
 }
 
 impl Config {
-//    pub fn new(max_inbound: u64, max_outbound: u64, max_connection_size: u64) -> Self {
-//        Config {
-//            max_inbound,
-//            max_outbound,
-//            max_connection_size,
-//        }
-//    }
-}
\ No newline at end of file
+    pub fn sentry_nodes(&self) -> Result<Vec<(PeerId, Multiaddr)>, String> {
+        self.sentries.iter().map(|sentry| parse_bootnode(sentry)).collect()
+    }
+
+    pub fn persistent_nodes(&self) -> Result<Vec<(PeerId, Multiaddr)>, String> {
+        self.persistent_peers.iter().map(|peer| parse_bootnode(peer)).collect()
+    }
+
+    /// reconnect_delay is the exponential backoff of redialing a persistent peer
+    pub fn reconnect_delay(&self, attempts: u32) -> Duration {
+        self.reconnect_backoff
+            .checked_mul(1 << attempts.min(16))
+            .map_or(self.max_reconnect_backoff, |delay| delay.min(self.max_reconnect_backoff))
+    }
+
+    pub fn private_peer_ids(&self) -> Result<HashSet<PeerId>, String> {
+        self.private_peers
+            .iter()
+            .map(|peer| PeerId::from_str(peer).map_err(|_| format!("invalid private peer id: {}", peer)))
+            .collect()
+    }
+
+    /// validate checks the options which can't work together
+    pub fn validate(&self) -> Result<(), String> {
+        // the source of floodsub message isn't signed, the sentries can't be told from the others
+        if self.transport == TransportKind::Libp2p && self.mode == NodeMode::Validator {
+            return Err("validator mode requires the tcp transport".to_string());
+        }
+        Ok(())
+    }
+
+    /// allowed_peers returns the only peers which can be connected, none means no restriction
+    pub fn allowed_peers(&self) -> Result<Option<HashSet<PeerId>>, String> {
+        if self.mode != NodeMode::Validator {
+            return Ok(None);
+        }
+        let sentries = self.sentry_nodes()?;
+        if sentries.is_empty() {
+            return Err("validator mode requires at least one sentry".to_string());
+        }
+        Ok(Some(sentries.into_iter().map(|(peer_id, _)| peer_id).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_sentry_config() {
+        let mut config = Config::default();
+        assert_eq!(config.allowed_peers(), Ok(None));
+
+        config.mode = NodeMode::Validator;
+        assert!(config.allowed_peers().is_err());
+        let sentry = PeerId::random();
+        config.sentries = vec![format!("{}@/ip4/127.0.0.1/tcp/7960", sentry.to_base58())];
+        assert_eq!(config.allowed_peers().unwrap().unwrap().into_iter().collect::<Vec<_>>(), vec![sentry]);
+
+        config.private_peers = vec!["invalid".to_string()];
+        assert!(config.private_peer_ids().is_err());
+    }
+
+    #[test]
+    fn t_transport_config() {
+        assert_eq!(Config::default().transport, TransportKind::Tcp);
+        let config: Config = toml::from_str("transport = \"libp2p\"\nping_interval = 500").unwrap();
+        assert_eq!(config.transport, TransportKind::Libp2p);
+        assert_eq!(config.ping_interval, Duration::from_millis(500));
+        assert_eq!(config.ping_timeout, Config::default().ping_timeout);
+        assert!(config.validate().is_ok());
+
+        let config: Config = toml::from_str("transport = \"libp2p\"\nmode = \"validator\"").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn t_reconnect_delay() {
+        let config = Config::default();
+        assert_eq!(config.reconnect_delay(0), Duration::from_secs(1));
+        assert_eq!(config.reconnect_delay(3), Duration::from_secs(8));
+        assert_eq!(config.reconnect_delay(6), Duration::from_secs(60));
+        assert_eq!(config.reconnect_delay(100), Duration::from_secs(60));
+    }
+}
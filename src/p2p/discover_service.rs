@@ -120,7 +120,7 @@ mod tests {
                         msg
                     );
                 }
-                P2PEvent::DropPeer(_, _) => {
+                P2PEvent::DropPeer(_, _) | P2PEvent::Misbehave(_, _) => {
                     writeln!(io::stdout(), "work receive a msg: {:?}", msg);
                 }
             }
@@ -0,0 +1,415 @@
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
+use std::io::Cursor;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use ::actix::prelude::*;
+use actix_broker::BrokerIssue;
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::storage::values::StorageValue;
+use libp2p::{Multiaddr, PeerId};
+use lru_time_cache::LruCache;
+
+use super::protocol::{Header as RawHeader, P2PMsgCode, RawMessage};
+use crate::{
+    common::decode,
+    metrics,
+    subscriber::{P2PEvent, ProcessSignals},
+    subscriber::events::BroadcastEvent,
+};
+
+pub const BUCKET_SIZE: usize = 16;
+// max number of nodes in a neighbors response
+pub const MAX_NEIGHBORS: usize = 16;
+// the dialed node is not dialed again during the duration
+const DIAL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The node advertised on the network, the addresses are the listen addresses of the node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeRecord {
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+}
+
+implement_storagevalue_traits! {NodeRecord}
+
+impl NodeRecord {
+    pub fn new(peer_id: &PeerId, addresses: &[Multiaddr]) -> Self {
+        NodeRecord {
+            peer_id: peer_id.to_base58(),
+            addresses: addresses.iter().map(|address| address.to_string()).collect(),
+        }
+    }
+
+    /// parse returns none if the peer id is invalid or no address is valid
+    pub fn parse(&self) -> Option<(PeerId, Vec<Multiaddr>)> {
+        let peer_id = PeerId::from_str(&self.peer_id).ok()?;
+        let addresses: Vec<Multiaddr> = self.addresses.iter().filter_map(|address| Multiaddr::from_str(address).ok()).collect();
+        if addresses.is_empty() {
+            return None;
+        }
+        Some((peer_id, addresses))
+    }
+}
+
+/// FindNode asks the peer for the nodes closest to the target, the sender is added into the
+/// routing table of the peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FindNode {
+    pub target: Hash,
+    pub sender: NodeRecord,
+}
+
+implement_storagevalue_traits! {FindNode}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Neighbors {
+    pub nodes: Vec<NodeRecord>,
+}
+
+implement_storagevalue_traits! {Neighbors}
+
+/// parse_bootnode parses "<peer_id>@<multiaddr>",
+/// e.g. "QmbBr2fHwLFKvHkAq1BpbEr4dvR8P6orQxHkVaxeJsJiW8@/ip4/10.0.0.1/tcp/7960"
+pub fn parse_bootnode(bootnode: &str) -> Result<(PeerId, Multiaddr), String> {
+    let mut parts = bootnode.splitn(2, '@');
+    let (peer_id, address) = match (parts.next(), parts.next()) {
+        (Some(peer_id), Some(address)) => (peer_id, address),
+        _ => return Err(format!("invalid bootnode: {}, expect <peer_id>@<multiaddr>", bootnode)),
+    };
+    let peer_id = PeerId::from_str(peer_id).map_err(|_| format!("invalid bootnode peer id: {}", peer_id))?;
+    let address = Multiaddr::from_str(address).map_err(|err| format!("invalid bootnode address: {}, err: {}", address, err))?;
+    Ok((peer_id, address))
+}
+
+/// node_id is the position of the peer in the kademlia key space
+pub fn node_id(peer_id: &PeerId) -> Hash {
+    hash(peer_id.as_bytes())
+}
+
+fn distance(id1: &Hash, id2: &Hash) -> Vec<u8> {
+    id1.as_ref().iter().zip(id2.as_ref().iter()).map(|(byte1, byte2)| byte1 ^ byte2).collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeEntry {
+    pub id: Hash,
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+    pub last_seen: Instant,
+}
+
+/// Kademlia node table, ported from the `KNodeTable` of the in-tree p2p crate.
+///
+/// Keeps nodes in k-buckets, the N-th bucket contains the nodes with distance from 2^N to 2^(N+1)
+/// from the local node. The full bucket keeps its old nodes, the nodes which are not seen for a
+/// while are removed by `expire`.
+pub struct KNodeTable {
+    local_id: Hash,
+    bucket_size: usize,
+    buckets: Vec<VecDeque<NodeEntry>>,
+}
+
+impl KNodeTable {
+    pub fn new(local_id: Hash, bucket_size: usize) -> Self {
+        let bits = local_id.as_ref().len() * 8;
+        KNodeTable {
+            local_id,
+            bucket_size,
+            buckets: (0..bits).map(|_| VecDeque::new()).collect(),
+        }
+    }
+
+    /// bucket_number is none for the local node
+    fn bucket_number(&self, id: &Hash) -> Option<usize> {
+        let distance = distance(&self.local_id, id);
+        let bits = distance.len() * 8;
+        for (idx, byte) in distance.iter().enumerate() {
+            if *byte != 0 {
+                return Some(bits - idx * 8 - byte.leading_zeros() as usize - 1);
+            }
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        let id = node_id(peer_id);
+        self.bucket_number(&id)
+            .map_or(false, |number| self.buckets[number].iter().any(|node| node.id == id))
+    }
+
+    /// update inserts the node or refreshes it, false if the bucket is full
+    pub fn update(&mut self, peer_id: PeerId, addresses: Vec<Multiaddr>) -> bool {
+        let id = node_id(&peer_id);
+        let number = match self.bucket_number(&id) {
+            Some(number) => number,
+            None => return false,
+        };
+        let bucket = &mut self.buckets[number];
+        if let Some(pos) = bucket.iter().position(|node| node.id == id) {
+            bucket.remove(pos);
+        } else if bucket.len() >= self.bucket_size {
+            return false;
+        }
+        bucket.push_back(NodeEntry { id, peer_id, addresses, last_seen: Instant::now() });
+        true
+    }
+
+    pub fn remove(&mut self, peer_id: &PeerId) -> Option<NodeEntry> {
+        let id = node_id(peer_id);
+        let number = self.bucket_number(&id)?;
+        let bucket = &mut self.buckets[number];
+        let pos = bucket.iter().position(|node| node.id == id)?;
+        bucket.remove(pos)
+    }
+
+    /// find returns the nodes closest to the target
+    pub fn find(&self, target: &Hash, count: usize) -> Vec<NodeEntry> {
+        let mut nodes: Vec<NodeEntry> = self.buckets.iter().flat_map(|bucket| bucket.iter().cloned()).collect();
+        nodes.sort_by_key(|node| distance(target, &node.id));
+        nodes.truncate(count);
+        nodes
+    }
+
+    /// expire removes the nodes which are not seen during the duration
+    pub fn expire(&mut self, duration: Duration) -> Vec<NodeEntry> {
+        let mut expired = vec![];
+        for bucket in self.buckets.iter_mut() {
+            while bucket.front().map_or(false, |node| node.last_seen.elapsed() > duration) {
+                expired.push(bucket.pop_front().unwrap());
+            }
+        }
+        expired
+    }
+}
+
+#[derive(Message)]
+pub struct DiscoveryMessage(pub PeerId, pub RawMessage);
+
+/// KadDiscovery finds the peers by the kademlia protocol over the connected peers.
+///
+/// The bootnodes are dialed at startup and redialed once they are disconnected. Every round a
+/// `FindNode` of a random target is sent to the connected peers, the nodes of the responses are put
+/// into the routing table and the closest ones are dialed through `P2PEvent::AddPeer`. The nodes
+/// which are not seen for a while are removed by `P2PEvent::DropPeer`.
+///
+/// The private peers (the validators behind a sentry) are never put into the routing table, so
+/// they are not advertised. A hidden node (a validator behind sentries) only keeps its bootnodes
+/// connected, it neither looks up nor answers the lookups.
+pub struct KadDiscovery {
+    p2p_subscriber: Addr<ProcessSignals>,
+    local: NodeRecord,
+    local_id: PeerId,
+    bootnodes: Vec<(PeerId, Multiaddr)>,
+    table: KNodeTable,
+    dialed: LruCache<PeerId, ()>,
+    max_peers: usize,
+    interval: Duration,
+    private_peers: HashSet<PeerId>,
+    hidden: bool,
+}
+
+impl Actor for KadDiscovery {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.lookup();
+        ctx.run_interval(self.interval, |act, _| act.lookup());
+        info!("Kademlia discovery has started, bootnodes: {}", self.bootnodes.len());
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!("Kademlia discovery has stopped");
+    }
+}
+
+impl Handler<DiscoveryMessage> for KadDiscovery {
+    type Result = ();
+
+    fn handle(&mut self, msg: DiscoveryMessage, _ctx: &mut Self::Context) {
+        let DiscoveryMessage(peer, msg) = msg;
+        if self.hidden {
+            return;
+        }
+        let payload = msg.payload();
+        let result = match msg.header().code {
+            P2PMsgCode::FindNode => decode(payload).ok().map(|request| self.on_find_node(peer.clone(), request)),
+            P2PMsgCode::Neighbors => decode(payload).ok().map(|response| self.on_neighbors(peer.clone(), response)),
+            _ => None,
+        };
+        if result.is_none() {
+            debug!("Ignore malformed discovery message from {}", peer.to_base58());
+        }
+    }
+}
+
+impl KadDiscovery {
+    pub fn new(
+        p2p_subscriber: Addr<ProcessSignals>,
+        local_id: PeerId,
+        local_address: Multiaddr,
+        bootnodes: Vec<(PeerId, Multiaddr)>,
+        max_peers: usize,
+        interval: Duration,
+    ) -> Self {
+        KadDiscovery {
+            p2p_subscriber,
+            local: NodeRecord::new(&local_id, &[local_address]),
+            table: KNodeTable::new(node_id(&local_id), BUCKET_SIZE),
+            local_id,
+            bootnodes,
+            dialed: LruCache::with_expiry_duration(DIAL_INTERVAL),
+            max_peers,
+            interval,
+            private_peers: HashSet::new(),
+            hidden: false,
+        }
+    }
+
+    pub fn with_private_peers(mut self, private_peers: HashSet<PeerId>) -> Self {
+        self.private_peers = private_peers;
+        self
+    }
+
+    pub fn hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+
+    fn lookup(&mut self) {
+        // the bootnodes are persistent, the connected ones are ignored by the server
+        for (peer_id, address) in &self.bootnodes {
+            self.p2p_subscriber.do_send(P2PEvent::AddPeer(peer_id.clone(), vec![address.clone()]));
+        }
+        if self.hidden {
+            return;
+        }
+
+        for node in self.table.expire(self.interval * 5) {
+            debug!("Drop the expired node: {}", node.peer_id.to_base58());
+            self.p2p_subscriber.do_send(P2PEvent::DropPeer(node.peer_id, node.addresses));
+        }
+
+        // dial the closest nodes
+        let local_id = node_id(&self.local_id);
+        for node in self.table.find(&local_id, self.max_peers) {
+            if self.dialed.get(&node.peer_id).is_some() {
+                continue;
+            }
+            self.dialed.insert(node.peer_id.clone(), ());
+            self.p2p_subscriber.do_send(P2PEvent::AddPeer(node.peer_id, node.addresses));
+        }
+        metrics::P2P_KAD_NODES.set(self.table.len() as i64);
+
+        let target = hash(&rand::random::<[u8; 32]>());
+        let request = FindNode { target, sender: self.local.clone() };
+        self.send(None, P2PMsgCode::FindNode, request.into_bytes());
+    }
+
+    fn on_find_node(&mut self, peer: PeerId, request: FindNode) {
+        // the sender record must belong to the peer of the connection
+        match request.sender.parse() {
+            Some((ref peer_id, _)) if *peer_id == peer && self.private_peers.contains(peer_id) => {}
+            Some((peer_id, addresses)) if peer_id == peer => {
+                self.table.update(peer_id, addresses);
+            }
+            _ => debug!("Invalid sender record from {}", peer.to_base58()),
+        }
+        let nodes = self.table
+            .find(&request.target, MAX_NEIGHBORS + 1)
+            .into_iter()
+            .filter(|node| node.peer_id != peer)
+            .take(MAX_NEIGHBORS)
+            .map(|node| NodeRecord::new(&node.peer_id, &node.addresses))
+            .collect();
+        self.send(Some(&peer), P2PMsgCode::Neighbors, Neighbors { nodes }.into_bytes());
+    }
+
+    fn on_neighbors(&mut self, peer: PeerId, response: Neighbors) {
+        let mut seen = HashSet::new();
+        for record in response.nodes.iter().take(MAX_NEIGHBORS) {
+            if let Some((peer_id, addresses)) = record.parse() {
+                if peer_id == self.local_id || self.private_peers.contains(&peer_id) || !seen.insert(peer_id.clone()) {
+                    continue;
+                }
+                // the refreshed nodes are not dialed again, they are known already
+                if !self.table.contains(&peer_id) {
+                    self.table.update(peer_id, addresses);
+                }
+            }
+        }
+        trace!("Receive {} neighbors from {}, nodes: {}", seen.len(), peer.to_base58(), self.table.len());
+    }
+
+    fn send(&mut self, peer: Option<&PeerId>, code: P2PMsgCode, payload: Vec<u8>) {
+        let header = RawHeader::new(
+            code,
+            1,
+            chrono::Local::now().timestamp_millis() as u64,
+            peer.map(|peer| peer.as_bytes().to_vec()),
+        );
+        self.issue_async(BroadcastEvent::Sync(RawMessage::new(header, payload)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_address(port: u16) -> Multiaddr {
+        Multiaddr::from_str(&format!("/ip4/127.0.0.1/tcp/{}", port)).unwrap()
+    }
+
+    #[test]
+    fn t_knode_table() {
+        let local = PeerId::random();
+        let mut table = KNodeTable::new(node_id(&local), 2);
+        assert!(!table.update(local.clone(), vec![new_address(1)]));
+
+        let peers: Vec<PeerId> = (0..50).map(|_| PeerId::random()).collect();
+        for (idx, peer) in peers.iter().enumerate() {
+            table.update(peer.clone(), vec![new_address(idx as u16)]);
+        }
+        // most of the nodes are in the farthest buckets, they are full
+        assert!(table.len() < peers.len());
+        assert!(table.buckets.iter().all(|bucket| bucket.len() <= 2));
+
+        let target = node_id(&peers[0]);
+        let closest = table.find(&target, 3);
+        assert_eq!(closest.len(), 3);
+        assert!(closest.windows(2).all(|pair| distance(&target, &pair[0].id) <= distance(&target, &pair[1].id)));
+
+        let peer = closest[0].peer_id.clone();
+        assert!(table.contains(&peer));
+        assert!(table.remove(&peer).is_some());
+        assert!(!table.contains(&peer));
+
+        let len = table.len();
+        assert!(table.expire(Duration::from_secs(60)).is_empty());
+        ::std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(table.expire(Duration::from_millis(1)).len(), len);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn t_parse_bootnode() {
+        let peer = PeerId::random();
+        let (peer_id, address) = parse_bootnode(&format!("{}@/ip4/127.0.0.1/tcp/7960", peer.to_base58())).unwrap();
+        assert_eq!(peer_id, peer);
+        assert_eq!(address, new_address(7960));
+        assert!(parse_bootnode("/ip4/127.0.0.1/tcp/7960").is_err());
+        assert!(parse_bootnode(&format!("{}@127.0.0.1:7960", peer.to_base58())).is_err());
+
+        let record = NodeRecord::new(&peer, &[address]);
+        assert_eq!(record.parse(), Some((peer, vec![new_address(7960)])));
+    }
+}
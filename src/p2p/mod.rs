@@ -1,9 +1,16 @@
 pub mod config;
 pub mod discover_service;
+pub mod kad;
 pub mod node;
 pub mod server;
 pub mod session;
 pub mod codec;
 pub mod protocol;
+pub mod reputation;
+pub mod secure;
+pub mod sync;
+pub mod swarm;
+pub mod transport;
+pub mod snapshot;
 #[macro_use]
 pub use crate::subscriber::*;
\ No newline at end of file
@@ -4,23 +4,98 @@ use std::str::FromStr;
 
 use libp2p::{PeerId, Multiaddr};
 use cryptocurrency_kit::crypto::{CryptoHash, Hash, hash};
+use cryptocurrency_kit::ethkey::signature::{recover_bytes, sign_bytes};
+use cryptocurrency_kit::ethkey::{public_to_address, Address, Secret, Signature};
 use cryptocurrency_kit::storage::values::StorageValue;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Deserialize, Serialize, Message, Eq, PartialEq)]
+use crate::types::Height;
+
+// the range of the p2p protocol versions supported by the node, the highest common version is
+// negotiated by the handshake
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+pub const MAX_PROTOCOL_VERSION: u32 = 1;
+
+// the relayed gossips are deduplicated within it, the older ones are dropped, otherwise they are
+// relayed again once forgotten
+pub const GOSSIP_EXPIRY_MILLIS: u64 = 5 * 1000;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Message, Eq, PartialEq, Hash)]
 pub enum P2PMsgCode {
     Ping,
     Handshake,
     Transaction,
     Block,
     Consensus,
+    // reserved, the block sync goes through the codes below
     Sync,
+    Status,
+    GetHeaders,
+    Headers,
+    GetBodies,
+    Bodies,
+    GetSnapshot,
+    Snapshot,
+    FindNode,
+    Neighbors,
+    Pong,
 }
 
 implement_storagevalue_traits! {P2PMsgCode}
 implement_cryptohash_traits! {P2PMsgCode}
 
-#[derive(Debug, Clone, Copy)]
+impl P2PMsgCode {
+    pub fn all() -> Vec<P2PMsgCode> {
+        vec![
+            P2PMsgCode::Ping,
+            P2PMsgCode::Handshake,
+            P2PMsgCode::Transaction,
+            P2PMsgCode::Block,
+            P2PMsgCode::Consensus,
+            P2PMsgCode::Sync,
+            P2PMsgCode::Status,
+            P2PMsgCode::GetHeaders,
+            P2PMsgCode::Headers,
+            P2PMsgCode::GetBodies,
+            P2PMsgCode::Bodies,
+            P2PMsgCode::GetSnapshot,
+            P2PMsgCode::Snapshot,
+            P2PMsgCode::FindNode,
+            P2PMsgCode::Neighbors,
+            P2PMsgCode::Pong,
+        ]
+    }
+
+    /// code returns the wire code of the message, it's the index of the variant
+    pub fn code(&self) -> u32 {
+        self.clone() as u32
+    }
+
+    /// from_code returns none if the code is unknown to the node
+    pub fn from_code(code: u32) -> Option<P2PMsgCode> {
+        P2PMsgCode::all().into_iter().find(|msg_code| msg_code.code() == code)
+    }
+
+    /// is_routed returns true if the message is passed to the handles of transport,
+    /// the others are answered by the transport itself
+    pub fn is_routed(&self) -> bool {
+        match self {
+            P2PMsgCode::Ping | P2PMsgCode::Handshake | P2PMsgCode::Sync | P2PMsgCode::Pong => false,
+            _ => true,
+        }
+    }
+
+    /// capabilities returns the codes of the messages handled by the node
+    pub fn capabilities() -> Vec<u32> {
+        P2PMsgCode::all()
+            .into_iter()
+            .filter(|msg_code| *msg_code != P2PMsgCode::Sync)
+            .map(|msg_code| msg_code.code())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BoundType {
     InBound,
     OutBound,
@@ -58,6 +133,35 @@ impl RawMessage {
     pub(crate) fn mut_payload(&mut self) -> &mut Payload {
         &mut self.payload
     }
+
+    /// gossip_id identifies the message during relaying, the ttl is excluded because it's
+    /// decremented on every hop
+    pub(crate) fn gossip_id(&self) -> Hash {
+        let mut buffer = self.header.code.clone().into_bytes();
+        buffer.extend_from_slice(&self.header.create_time.to_be_bytes());
+        if let Some(ref peer_id) = self.header.peer_id {
+            buffer.extend_from_slice(peer_id);
+        }
+        buffer.extend_from_slice(&self.payload);
+        hash(&buffer)
+    }
+
+    /// is_gossip returns true if the message is relayed to the peers which are not connected to
+    /// the origin, the direct messages and sync requests are never relayed
+    pub(crate) fn is_gossip(&self) -> bool {
+        if self.header.peer_id.is_some() {
+            return false;
+        }
+        match self.header.code {
+            P2PMsgCode::Consensus | P2PMsgCode::Transaction | P2PMsgCode::Block => true,
+            _ => false,
+        }
+    }
+
+    /// is_stale_gossip returns true if the gossip is created `GOSSIP_EXPIRY_MILLIS` before now (millis)
+    pub(crate) fn is_stale_gossip(&self, now: u64) -> bool {
+        self.is_gossip() && now.saturating_sub(self.header.create_time) >= GOSSIP_EXPIRY_MILLIS
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -82,33 +186,202 @@ pub type Payload = Vec<u8>;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Handshake {
+    // version of the node software
     version: String,
+    // range of the supported protocol versions
+    min_version: u32,
+    max_version: u32,
+    // the codes of the messages handled by the node, the codes unknown to the remote are ignored
+    capabilities: Vec<u32>,
     peer_id: String,
+    chain_id: u64,
     genesis: Hash,
+    // the head of chain
+    best_height: Height,
+    best_hash: Hash,
+    // signature of the validator key over peer_id || genesis, it binds the peer to the validator
+    validator_signature: Option<Signature>,
 }
 
 implement_storagevalue_traits! {Handshake}
 implement_cryptohash_traits! {Handshake}
 
 impl Handshake {
-    pub fn new(version: String, peer_id: PeerId, genesis: Hash) -> Self {
+    pub fn new(peer_id: PeerId, chain_id: u64, genesis: Hash, best_height: Height, best_hash: Hash) -> Self {
         let peer_id = peer_id.to_base58();
         Handshake {
-            version: version,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: MAX_PROTOCOL_VERSION,
+            capabilities: P2PMsgCode::capabilities(),
             peer_id: peer_id,
+            chain_id: chain_id,
             genesis: genesis,
+            best_height: best_height,
+            best_hash: best_hash,
+            validator_signature: None,
+        }
+    }
+
+    /// negotiate returns the highest protocol version supported by both the remote handshake and
+    /// the local node
+    pub fn negotiate(&self) -> Option<u32> {
+        let min_version = self.min_version.max(MIN_PROTOCOL_VERSION);
+        let max_version = self.max_version.min(MAX_PROTOCOL_VERSION);
+        if min_version > max_version {
+            return None;
         }
+        Some(max_version)
+    }
+
+    /// sign proves the validator identity of the peer
+    pub fn sign(&mut self, secret: &Secret) {
+        let signature = sign_bytes(secret, &self.signature_payload());
+        self.validator_signature = signature.ok();
+    }
+
+    /// validator returns the address which signed the handshake, the peer is not a validator
+    /// unless the address is in the validator set
+    pub fn validator(&self) -> Option<Address> {
+        let signature = self.validator_signature.as_ref()?;
+        recover_bytes(signature, &self.signature_payload())
+            .ok()
+            .map(|public| public_to_address(&public))
+    }
+
+    fn signature_payload(&self) -> Vec<u8> {
+        let mut payload = self.peer_id.as_bytes().to_vec();
+        payload.extend_from_slice(self.genesis.as_ref());
+        payload
     }
 
     pub fn version(&self) -> &String {
         &self.version
     }
 
-    pub fn peer_id(&self) -> PeerId {
-        PeerId::from_str(&self.peer_id).unwrap()
+    /// peer_id returns none if the peer id is malformed
+    pub fn peer_id(&self) -> Option<PeerId> {
+        PeerId::from_str(&self.peer_id).ok()
+    }
+
+    /// capabilities returns the messages handled by the peer, the unknown codes are skipped
+    pub fn capabilities(&self) -> Vec<P2PMsgCode> {
+        self.capabilities.iter().filter_map(|code| P2PMsgCode::from_code(*code)).collect()
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
     }
 
     pub fn genesis(&self) -> &Hash {
         &self.genesis
     }
-}
\ No newline at end of file
+
+    pub fn best_height(&self) -> Height {
+        self.best_height
+    }
+
+    pub fn best_hash(&self) -> &Hash {
+        &self.best_hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::ethkey::{Generator, Random};
+
+    fn new_handshake(peer_id: PeerId, genesis: Hash) -> Handshake {
+        Handshake::new(peer_id, 98, genesis, 0, genesis)
+    }
+
+    #[test]
+    fn t_msg_code_wire() {
+        // the codes are encoded by their index, the old nodes depend on it
+        assert_eq!(P2PMsgCode::Consensus.into_bytes(), 4_u32.to_le_bytes().to_vec());
+        assert_eq!(P2PMsgCode::Sync.into_bytes(), 5_u32.to_le_bytes().to_vec());
+        for msg_code in P2PMsgCode::all() {
+            assert_eq!(msg_code.clone().into_bytes(), msg_code.code().to_le_bytes().to_vec());
+            assert_eq!(P2PMsgCode::from_code(msg_code.code()), Some(msg_code));
+        }
+    }
+
+    #[test]
+    fn t_handshake_capabilities() {
+        let mut handshake = new_handshake(PeerId::random(), hash(&[1]));
+        assert!(!handshake.capabilities().contains(&P2PMsgCode::Sync));
+        assert!(handshake.capabilities().contains(&P2PMsgCode::Consensus));
+
+        // the codes of the newer nodes are ignored
+        handshake.capabilities = vec![P2PMsgCode::Block.code(), 1000];
+        assert_eq!(handshake.capabilities(), vec![P2PMsgCode::Block]);
+    }
+
+    #[test]
+    fn t_gossip_id() {
+        let msg = RawMessage::new(Header::new(P2PMsgCode::Consensus, 10, 1, None), vec![1, 2, 3]);
+        assert!(msg.is_gossip());
+        let mut relay_msg = msg.clone();
+        relay_msg.mut_header().ttl -= 1;
+        assert_eq!(msg.gossip_id(), relay_msg.gossip_id());
+        relay_msg.mut_payload().push(4);
+        assert_ne!(msg.gossip_id(), relay_msg.gossip_id());
+
+        let direct_msg = RawMessage::new(Header::new(P2PMsgCode::Block, 10, 1, Some(PeerId::random().into_bytes())), vec![]);
+        assert!(!direct_msg.is_gossip());
+        let sync_msg = RawMessage::new(Header::new(P2PMsgCode::Status, 1, 1, None), vec![]);
+        assert!(!sync_msg.is_gossip());
+    }
+
+    #[test]
+    fn t_stale_gossip() {
+        let now = 100_000;
+        let msg = RawMessage::new(Header::new(P2PMsgCode::Block, 10, now - GOSSIP_EXPIRY_MILLIS + 1, None), vec![1]);
+        assert!(!msg.is_stale_gossip(now));
+        // relayed after the dedup cache forgets it
+        let mut msg = RawMessage::new(Header::new(P2PMsgCode::Block, 10, now - GOSSIP_EXPIRY_MILLIS, None), vec![1]);
+        msg.mut_header().ttl -= 1;
+        assert!(msg.is_stale_gossip(now));
+        // the direct messages are not relayed
+        let msg = RawMessage::new(Header::new(P2PMsgCode::Block, 10, 0, Some(vec![1])), vec![1]);
+        assert!(!msg.is_stale_gossip(now));
+    }
+
+    #[test]
+    fn t_handshake_validator() {
+        let key_pair = Random.generate().unwrap();
+        let peer_id = PeerId::random();
+        let mut handshake = new_handshake(peer_id.clone(), hash(&[1]));
+        assert_eq!(handshake.validator(), None);
+
+        handshake.sign(key_pair.secret());
+        let handshake = Handshake::from_bytes(Cow::from(handshake.into_bytes()));
+        assert_eq!(handshake.validator(), Some(key_pair.address()));
+
+        // the signature is bound to the peer id and genesis
+        let mut other = new_handshake(PeerId::random(), hash(&[1]));
+        other.validator_signature = handshake.validator_signature.clone();
+        assert_ne!(other.validator(), Some(key_pair.address()));
+        let mut other = new_handshake(peer_id, hash(&[2]));
+        other.validator_signature = handshake.validator_signature.clone();
+        assert_ne!(other.validator(), Some(key_pair.address()));
+    }
+
+    #[test]
+    fn t_handshake_negotiate() {
+        let mut remote = new_handshake(PeerId::random(), hash(&[1]));
+        assert_eq!(remote.negotiate(), Some(MAX_PROTOCOL_VERSION));
+
+        remote.min_version = MAX_PROTOCOL_VERSION;
+        remote.max_version = MAX_PROTOCOL_VERSION + 2;
+        assert_eq!(remote.negotiate(), Some(MAX_PROTOCOL_VERSION));
+
+        remote.min_version = MIN_PROTOCOL_VERSION - 1;
+        remote.max_version = MIN_PROTOCOL_VERSION;
+        assert_eq!(remote.negotiate(), Some(MIN_PROTOCOL_VERSION));
+
+        remote.min_version = MAX_PROTOCOL_VERSION + 1;
+        remote.max_version = MAX_PROTOCOL_VERSION + 2;
+        assert_eq!(remote.negotiate(), None);
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use libp2p::PeerId;
+
+use crate::types::Timestamp;
+
+pub const INITIAL_SCORE: i32 = 100;
+// the score recovered per minute
+const SCORE_RECOVERY: i32 = 5;
+
+/// Misbehavior of a peer, every kind costs some score of the peer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Misbehavior {
+    // the message is unexpected or can't be handled
+    InvalidMessage,
+    // the frame can't be decoded or decrypted
+    UndecodableFrame,
+    // the block failed to verify
+    BadBlock,
+    // too many messages in a short time
+    Spam,
+    // the request isn't answered in time or the answer is empty
+    Timeout,
+}
+
+impl Misbehavior {
+    pub fn penalty(&self) -> i32 {
+        match self {
+            Misbehavior::InvalidMessage => 20,
+            Misbehavior::UndecodableFrame => 50,
+            Misbehavior::BadBlock => 50,
+            Misbehavior::Spam => 10,
+            Misbehavior::Timeout => 10,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Misbehavior::InvalidMessage => "invalid_message",
+            Misbehavior::UndecodableFrame => "undecodable_frame",
+            Misbehavior::BadBlock => "bad_block",
+            Misbehavior::Spam => "spam",
+            Misbehavior::Timeout => "timeout",
+        }
+    }
+}
+
+/// Reputation keeps the score of the peers, the peer is banned for a while once its score is used
+/// up. The bans are saved into the file as json (peer id -> expiry time in secs), so they survive
+/// the restarts.
+pub struct Reputation {
+    scores: HashMap<PeerId, i32>,
+    bans: HashMap<PeerId, Timestamp>,
+    ban_duration: Duration,
+    path: Option<PathBuf>,
+}
+
+impl Reputation {
+    pub fn new(ban_duration: Duration) -> Self {
+        Reputation {
+            scores: HashMap::new(),
+            bans: HashMap::new(),
+            ban_duration,
+            path: None,
+        }
+    }
+
+    /// load restores the alive bans from the file, the file is created on the first ban
+    pub fn load<P: AsRef<Path>>(path: P, ban_duration: Duration, now: Timestamp) -> io::Result<Self> {
+        let mut reputation = Reputation::new(ban_duration);
+        reputation.path = Some(path.as_ref().to_path_buf());
+        if !path.as_ref().exists() {
+            return Ok(reputation);
+        }
+        let bans: HashMap<String, Timestamp> = serde_json::from_reader(BufReader::new(File::open(path)?))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        for (peer, expiry) in bans {
+            match PeerId::from_str(&peer) {
+                Ok(peer) if expiry > now => {
+                    reputation.bans.insert(peer, expiry);
+                }
+                Ok(_) => {}
+                Err(_) => warn!("Skip invalid banned peer: {}", peer),
+            }
+        }
+        Ok(reputation)
+    }
+
+    pub fn score(&self, peer: &PeerId) -> i32 {
+        *self.scores.get(peer).unwrap_or(&INITIAL_SCORE)
+    }
+
+    pub fn is_banned(&self, peer: &PeerId, now: Timestamp) -> bool {
+        self.bans.get(peer).map_or(false, |expiry| *expiry > now)
+    }
+
+    /// report decreases the score of the peer, returns true if the peer is banned by it
+    pub fn report(&mut self, peer: &PeerId, misbehavior: Misbehavior, now: Timestamp) -> bool {
+        let score = self.scores.entry(peer.clone()).or_insert(INITIAL_SCORE);
+        *score -= misbehavior.penalty();
+        if *score > 0 {
+            return false;
+        }
+        self.ban(peer, now);
+        true
+    }
+
+    pub fn ban(&mut self, peer: &PeerId, now: Timestamp) {
+        self.scores.remove(peer);
+        self.bans.insert(peer.clone(), now + self.ban_duration.as_secs());
+        if let Err(err) = self.save() {
+            warn!("Failed to save the banned peers, err: {}", err);
+        }
+    }
+
+    /// tick recovers the scores and expires the bans, it's called every minute
+    pub fn tick(&mut self, now: Timestamp) {
+        self.scores.retain(|_, score| {
+            *score += SCORE_RECOVERY;
+            *score < INITIAL_SCORE
+        });
+        let banned = self.bans.len();
+        self.bans.retain(|_, expiry| *expiry > now);
+        if banned != self.bans.len() {
+            if let Err(err) = self.save() {
+                warn!("Failed to save the banned peers, err: {}", err);
+            }
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let bans: HashMap<String, Timestamp> = self.bans.iter().map(|(peer, expiry)| (peer.to_base58(), *expiry)).collect();
+        let tmp_path = path.with_extension("new");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, &bans).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            writer.flush()?;
+        }
+        fs::rename(&tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::random_dir;
+
+    #[test]
+    fn t_reputation() {
+        let path = PathBuf::from(*random_dir()).with_extension("json");
+        let mut reputation = Reputation::load(&path, Duration::from_secs(60), 1000).unwrap();
+        let (peer1, peer2) = (PeerId::random(), PeerId::random());
+
+        assert!(!reputation.report(&peer1, Misbehavior::Spam, 1000));
+        assert_eq!(reputation.score(&peer1), INITIAL_SCORE - Misbehavior::Spam.penalty());
+        reputation.tick(1000);
+        assert_eq!(reputation.score(&peer1), INITIAL_SCORE - Misbehavior::Spam.penalty() + SCORE_RECOVERY);
+
+        assert!(!reputation.report(&peer2, Misbehavior::BadBlock, 1000));
+        assert!(reputation.report(&peer2, Misbehavior::BadBlock, 1000));
+        assert!(reputation.is_banned(&peer2, 1059));
+        assert!(!reputation.is_banned(&peer1, 1059));
+
+        // the bans are persisted
+        let mut reputation = Reputation::load(&path, Duration::from_secs(60), 1030).unwrap();
+        assert!(reputation.is_banned(&peer2, 1030));
+        assert_eq!(reputation.score(&peer2), INITIAL_SCORE);
+        reputation.tick(1060);
+        assert!(!reputation.is_banned(&peer2, 1060));
+        let reputation = Reputation::load(&path, Duration::from_secs(60), 1030).unwrap();
+        assert!(!reputation.is_banned(&peer2, 1030));
+    }
+}
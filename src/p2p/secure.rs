@@ -0,0 +1,255 @@
+use std::borrow::Cow;
+use std::io::{self, Cursor};
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder};
+use chacha20_poly1305_aead::{decrypt, encrypt};
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::signature::{recover_bytes, sign_bytes};
+use cryptocurrency_kit::ethkey::{public_to_address, Address, Generator, KeyPair, Public, Random, Secret, Signature};
+use cryptocurrency_kit::storage::values::StorageValue;
+use futures::prelude::*;
+use libp2p::{core::PublicKey, PeerId};
+use secp256k1::{ecdh::SharedSecret, key, Secp256k1};
+use tokio::io::{read_exact, write_all, AsyncRead, AsyncWrite};
+use tokio::timer::Timeout;
+
+use crate::common::decode;
+
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_AUTH_SIZE: usize = 1 << 10;
+pub const TAG_SIZE: usize = 16;
+
+/// NodeKey is the identity of the node on the network, the peer id is derived from its public key
+#[derive(Debug, Clone)]
+pub struct NodeKey {
+    key_pair: KeyPair,
+}
+
+impl NodeKey {
+    pub fn from_secret(secret: Secret) -> Result<Self, String> {
+        let key_pair = KeyPair::from_secret(secret).map_err(|err| err.to_string())?;
+        Ok(NodeKey { key_pair })
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        public_to_peer_id(self.key_pair.public())
+    }
+
+    pub fn address(&self) -> Address {
+        self.key_pair.address()
+    }
+
+    pub fn secret(&self) -> &Secret {
+        self.key_pair.secret()
+    }
+}
+
+/// public_to_peer_id derives the peer id from the compressed secp256k1 key, the same as secio
+pub fn public_to_peer_id(public: &Public) -> PeerId {
+    let mut compressed = Vec::with_capacity(33);
+    compressed.push(0x02 | (public[63] & 1));
+    compressed.extend_from_slice(&public[..32]);
+    PublicKey::Secp256k1(compressed).into_peer_id()
+}
+
+/// The first message of the connection, it's sent by both sides in plaintext.
+/// The signature of the node key over `hash(ephemeral || nonce)` binds the ephemeral key to the
+/// node, the node key is recovered from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthMessage {
+    pub ephemeral: Public,
+    pub nonce: Hash,
+    pub signature: Signature,
+}
+
+implement_storagevalue_traits! {AuthMessage}
+
+impl AuthMessage {
+    fn new(node_key: &NodeKey, ephemeral: &KeyPair, nonce: Hash) -> Self {
+        let signature = sign_bytes(node_key.secret(), &Self::payload(ephemeral.public(), &nonce)).unwrap();
+        AuthMessage { ephemeral: *ephemeral.public(), nonce, signature }
+    }
+
+    fn payload(ephemeral: &Public, nonce: &Hash) -> Vec<u8> {
+        let mut payload = ephemeral[..].to_vec();
+        payload.extend_from_slice(nonce.as_ref());
+        hash(&payload).as_ref().to_vec()
+    }
+
+    /// node_public recovers the node key of the sender
+    fn node_public(&self) -> io::Result<Public> {
+        recover_bytes(&self.signature, &Self::payload(&self.ephemeral, &self.nonce))
+            .map_err(|_| invalid_data("invalid auth signature"))
+    }
+}
+
+/// FrameCipher seals the frames of one direction by ChaCha20-Poly1305,
+/// the nonce is the frame counter so the frames can't be replayed or reordered.
+pub struct FrameCipher {
+    key: Vec<u8>,
+    counter: u64,
+}
+
+impl FrameCipher {
+    pub fn new(key: Hash) -> Self {
+        FrameCipher { key: key.as_ref().to_vec(), counter: 0 }
+    }
+
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        BigEndian::write_u64(&mut nonce[4..], self.counter);
+        self.counter += 1;
+        nonce
+    }
+
+    /// seal returns ciphertext || tag, the aad is authenticated but not encrypted
+    pub fn seal(&mut self, aad: &[u8], plain: &[u8]) -> Vec<u8> {
+        let nonce = self.next_nonce();
+        let mut sealed = Vec::with_capacity(plain.len() + TAG_SIZE);
+        let tag = encrypt(&self.key, &nonce, aad, plain, &mut sealed).unwrap();
+        sealed.extend_from_slice(&tag);
+        sealed
+    }
+
+    pub fn open(&mut self, aad: &[u8], sealed: &[u8]) -> io::Result<Vec<u8>> {
+        if sealed.len() < TAG_SIZE {
+            return Err(invalid_data("frame is too short"));
+        }
+        let nonce = self.next_nonce();
+        let (cipher, tag) = sealed.split_at(sealed.len() - TAG_SIZE);
+        let mut plain = Vec::with_capacity(cipher.len());
+        decrypt(&self.key, &nonce, aad, cipher, tag, &mut plain).map_err(|_| invalid_data("frame authentication failed"))?;
+        Ok(plain)
+    }
+}
+
+/// The result of handshake, the remote peer id is authenticated
+pub struct SecureSession {
+    pub remote: PeerId,
+    pub remote_address: Address,
+    pub send: FrameCipher,
+    pub recv: FrameCipher,
+}
+
+/// handshake exchanges the `AuthMessage` over the stream and derives the keys of both directions
+/// from the ECDH of the ephemeral keys: key = hash(shared || sender nonce || receiver nonce).
+pub fn handshake<S>(stream: S, node_key: NodeKey) -> impl Future<Item = (S, SecureSession), Error = io::Error>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let ephemeral = Random.generate().unwrap();
+    let nonce = hash(&rand::random::<[u8; 32]>());
+    let auth = AuthMessage::new(&node_key, &ephemeral, nonce).into_bytes();
+    let mut packet = vec![0; 4];
+    BigEndian::write_u32(&mut packet, auth.len() as u32);
+    packet.extend_from_slice(&auth);
+
+    let future = write_all(stream, packet)
+        .and_then(|(stream, _)| read_exact(stream, [0; 4]))
+        .and_then(|(stream, size)| {
+            let size = BigEndian::read_u32(&size) as usize;
+            if size > MAX_AUTH_SIZE {
+                return Err(invalid_data("auth message is too large"));
+            }
+            Ok(read_exact(stream, vec![0; size]))
+        })
+        .flatten()
+        .and_then(move |(stream, payload)| {
+            let remote = decode_auth(&payload)?;
+            let remote_public = remote.node_public()?;
+            if remote_public == *node_key.key_pair.public() {
+                return Err(invalid_data("connect to self"));
+            }
+            let shared = agree(ephemeral.secret(), &remote.ephemeral)?;
+            let session = SecureSession {
+                remote: public_to_peer_id(&remote_public),
+                remote_address: public_to_address(&remote_public),
+                send: FrameCipher::new(derive_key(&shared, &nonce, &remote.nonce)),
+                recv: FrameCipher::new(derive_key(&shared, &remote.nonce, &nonce)),
+            };
+            Ok((stream, session))
+        });
+    Timeout::new(future, HANDSHAKE_TIMEOUT).map_err(|err| {
+        if err.is_elapsed() {
+            io::Error::new(io::ErrorKind::TimedOut, "handshake timeout")
+        } else {
+            err.into_inner().unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, "handshake timer failed"))
+        }
+    })
+}
+
+fn decode_auth(payload: &[u8]) -> io::Result<AuthMessage> {
+    decode(payload).map_err(|_| invalid_data("malformed auth message"))
+}
+
+/// agree is the ECDH of secp256k1
+fn agree(secret: &Secret, public: &Public) -> io::Result<Vec<u8>> {
+    let secp = Secp256k1::new();
+    let mut public_data = vec![0x04];
+    public_data.extend_from_slice(&public[..]);
+    let public = key::PublicKey::from_slice(&secp, &public_data).map_err(|_| invalid_data("invalid ephemeral key"))?;
+    let secret = key::SecretKey::from_slice(&secp, &secret[..]).map_err(|_| invalid_data("invalid secret"))?;
+    Ok(SharedSecret::new(&secp, &public, &secret)[0..32].to_vec())
+}
+
+fn derive_key(shared: &[u8], sender_nonce: &Hash, receiver_nonce: &Hash) -> Hash {
+    let mut material = shared.to_vec();
+    material.extend_from_slice(sender_nonce.as_ref());
+    material.extend_from_slice(receiver_nonce.as_ref());
+    hash(&material)
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_node_key() -> NodeKey {
+        NodeKey::from_secret(Random.generate().unwrap().secret().clone()).unwrap()
+    }
+
+    #[test]
+    fn t_frame_cipher() {
+        let key = hash(&[1, 2, 3]);
+        let (mut sender, mut receiver) = (FrameCipher::new(key), FrameCipher::new(key));
+        let first = sender.seal(b"aad", b"hello");
+        let second = sender.seal(b"aad", b"world");
+        assert_ne!(&first[..5], b"hello");
+        assert_eq!(receiver.open(b"aad", &first).unwrap(), b"hello".to_vec());
+        // replay
+        assert!(receiver.open(b"aad", &first).is_err());
+
+        let mut receiver = FrameCipher::new(key);
+        assert!(receiver.open(b"bad", &first).is_err());
+        let mut receiver = FrameCipher::new(key);
+        let mut tampered = second.clone();
+        tampered[0] ^= 1;
+        receiver.open(b"aad", &first).unwrap();
+        assert!(receiver.open(b"aad", &tampered).is_err());
+    }
+
+    #[test]
+    fn t_auth_message() {
+        let node_key = new_node_key();
+        let ephemeral = Random.generate().unwrap();
+        let auth = AuthMessage::new(&node_key, &ephemeral, hash(&[1]));
+        let decoded = decode_auth(&auth.clone().into_bytes()).unwrap();
+        assert_eq!(public_to_peer_id(&decoded.node_public().unwrap()), node_key.peer_id());
+
+        // the ephemeral key is replaced
+        let mut forged = auth;
+        forged.ephemeral = *Random.generate().unwrap().public();
+        assert!(forged.node_public().map(|public| public_to_peer_id(&public) != node_key.peer_id()).unwrap_or(true));
+        assert!(decode_auth(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn t_agree() {
+        let (key1, key2) = (Random.generate().unwrap(), Random.generate().unwrap());
+        assert_eq!(agree(key1.secret(), key2.public()).unwrap(), agree(key2.secret(), key1.public()).unwrap());
+    }
+}
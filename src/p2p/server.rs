@@ -1,5 +1,5 @@
 use std::any::{Any, TypeId};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
@@ -8,6 +8,7 @@ use ::actix::prelude::*;
 use actix_broker::BrokerSubscribe;
 use cryptocurrency_kit::storage::values::StorageValue;
 use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::{Address, Secret};
 use futures::prelude::*;
 use libp2p::{
     core::nodes::swarm::NetworkBehaviour,
@@ -15,20 +16,27 @@ use libp2p::{
     floodsub::FloodsubMessage,
     mplex,
     multiaddr::Protocol,
-    secio, Multiaddr, PeerId, Transport,
+    Multiaddr, PeerId, Transport,
 };
 use tokio::{timer::Delay, codec::FramedRead, io::AsyncRead, io::WriteHalf, net::TcpListener, net::TcpStream};
 use uuid::Uuid;
 use lru_time_cache::LruCache;
 use chrono::Local;
+use rand::Rng;
 
-use super::codec::MsgPacketCodec;
-use super::protocol::{BoundType, RawMessage, Header as RawHeader, P2PMsgCode, Payload, Handshake};
+use super::codec::{MsgPacketCodec, SessionVersion};
+use super::config::Config as P2PConfig;
+use super::protocol::{BoundType, RawMessage, Header as RawHeader, P2PMsgCode, Payload, Handshake, GOSSIP_EXPIRY_MILLIS};
+use super::reputation::{Misbehavior, Reputation};
+use super::secure::{handshake, NodeKey, SecureSession};
 use super::session::Session;
 use crate::{
-    types::block::Blocks,
-    common::{multiaddr_to_ipv4, random_uuid},
+    light::verify_seals,
+    protocol::GossipMessage,
+    types::{Height, block::Blocks, transaction::Transaction},
+    common::{decode, multiaddr_to_ipv4, random_uuid},
     error::P2PError,
+    metrics,
     subscriber::P2PEvent,
     subscriber::events::{BroadcastEvent, ChainEvent},
 };
@@ -44,22 +52,42 @@ lazy_static! {
 pub type AuthorFn = Fn(Handshake) -> bool;
 pub type HandleMsgFn = Fn(PeerId, RawMessage) -> Result<(), String>;
 
+/// HandshakePacketFn builds the local handshake of a new session
 pub type HandshakePacketFn = Fn() -> Handshake;
 
-pub fn author_handshake(genesis: Hash) -> impl Fn(Handshake) -> bool {
+/// ValidatorsFn returns the current validator set, the consensus messages are sent to them first
+pub type ValidatorsFn = Fn() -> Vec<Address>;
+
+pub fn author_handshake(genesis: Hash, chain_id: u64) -> impl Fn(Handshake) -> bool {
     move |handshake: Handshake| {
-        if *handshake.genesis() != genesis {
+        if *handshake.genesis() != genesis || handshake.chain_id() != chain_id {
             return false;
         }
         true
     }
 }
 
+/// handshake_packet builds the handshake with the current head of chain, it's signed by the
+/// validator key, so the remote peers know which validator it is
+pub fn handshake_packet<F>(peer_id: PeerId, chain_id: u64, genesis: Hash, validator_key: Secret, head: F) -> impl Fn() -> Handshake
+where
+    F: Fn() -> (Height, Hash),
+{
+    move || {
+        let (best_height, best_hash) = head();
+        let mut handshake = Handshake::new(peer_id.clone(), chain_id, genesis, best_height, best_hash);
+        handshake.sign(&validator_key);
+        handshake
+    }
+}
+
 pub enum ServerEvent {
     Connected(PeerId, BoundType, Addr<Session>, RawMessage),
     Disconnected(PeerId),
     Message(PeerId, RawMessage),
     Ping(PeerId),
+    Pong(PeerId, Duration),
+    Misbehave(PeerId, Misbehavior),
 }
 
 impl Message for ServerEvent {
@@ -76,39 +104,89 @@ impl Message for SessionEvent {
 
 pub struct TcpServer {
     pid: Addr<TcpServer>,
-    key: Option<secio::SecioKeyPair>,
+    node_key: NodeKey,
     node_info: (PeerId, Multiaddr),
     peers: HashMap<PeerId, ConnectInfo>,
     genesis: Hash,
+    chain_id: u64,
+    handshake_fn: Box<HandshakePacketFn>,
     cache: LruCache<Hash, bool>,
     author_fn: Box<AuthorFn>,
     handles: Box<HandleMsgFn>,
+    validators_fn: Box<ValidatorsFn>,
+    // the validator set of the current height, it's reloaded once a block is committed
+    validators: HashSet<Address>,
+    config: P2PConfig,
+    reputation: Reputation,
+    // validator mode: only the sentries can be connected
+    allowed_peers: Option<HashSet<PeerId>>,
+    // sentry mode: the private validators, they always have a slot and receive the relayed messages
+    private_peers: HashSet<PeerId>,
+    // the peers which are redialed with backoff after disconnection
+    persistent_peers: HashMap<PeerId, Reconnect>,
+}
+
+struct Reconnect {
+    address: Multiaddr,
+    // the failed dials since the last successful handshake
+    attempts: u32,
+    // a redial is scheduled
+    pending: bool,
 }
 
 struct ConnectInfo {
     connect_time: chrono::DateTime<chrono::Utc>,
+    // refreshed by every ping, pong and message of the peer
+    last_seen: Instant,
+    // the round trip time of the last ping
+    rtt: Option<Duration>,
     bound_type: BoundType,
     pid: Addr<Session>,
+    // the validator address proved by the handshake
+    validator: Option<Address>,
+    // messages received in the current second
+    received: u64,
+    // the negotiated protocol version
+    version: u32,
+    // the message codes handled by the peer
+    capabilities: HashSet<P2PMsgCode>,
+    // the head of chain advertised by the handshake
+    best_height: Height,
 }
 
 impl ConnectInfo {
-    fn new(connect_time: chrono::DateTime<chrono::Utc>, bound_type: BoundType, pid: Addr<Session>) -> Self {
+    fn new(connect_time: chrono::DateTime<chrono::Utc>, bound_type: BoundType, pid: Addr<Session>, validator: Option<Address>, version: u32, handshake: &Handshake) -> Self {
         ConnectInfo {
             connect_time: connect_time,
+            last_seen: Instant::now(),
+            rtt: None,
             bound_type: bound_type,
             pid: pid,
+            validator: validator,
+            received: 0,
+            version: version,
+            capabilities: handshake.capabilities().into_iter().collect(),
+            best_height: handshake.best_height(),
         }
     }
+
+    fn supports(&self, code: &P2PMsgCode) -> bool {
+        self.capabilities.contains(code)
+    }
 }
 
 fn node_info(peers: &HashMap<PeerId, ConnectInfo>) -> String {
     let mut info: Vec<String> = vec![];
     for peer in peers {
         info.push(format!(
-            "{}----> [bound: {:?}, connect_time: {:?}]",
+            "{}----> [bound: {:?}, connect_time: {:?}, rtt: {:?}, validator: {:?}, version: {}, best_height: {}]",
             peer.0.to_base58(),
             peer.1.bound_type,
-            peer.1.connect_time
+            peer.1.connect_time,
+            peer.1.rtt,
+            peer.1.validator,
+            peer.1.version,
+            peer.1.best_height
         ));
     }
     info.join("\n")
@@ -124,6 +202,7 @@ impl Actor for TcpServer {
         );
         self.subscribe_async::<BroadcastEvent>(ctx);
         ctx.run_interval(::std::time::Duration::from_secs(2), |act, _| {
+            metrics::P2P_PEERS.set(act.peers.len() as i64);
             debug!(
                 "Connect clients: {}\nlocal-id:{}, \n{}",
                 act.peers.len(),
@@ -132,22 +211,56 @@ impl Actor for TcpServer {
             );
         });
 
-        ctx.run_interval(Duration::from_secs(3), |act, _| {
-            let mut peers = vec![];
-            act.peers.iter().for_each(|kv| {
-                let sub = chrono::Utc::now().timestamp() - kv.1.connect_time.timestamp();
-                if sub > 3 {
-                    peers.push(kv.0.clone());
-                }
-            });
-
+        // the peers not seen within the ping timeout are dead, they are redialed if persistent
+        ctx.run_interval(self.config.ping_interval, |act, ctx| {
+            let ping_timeout = act.config.ping_timeout;
+            let peers: Vec<PeerId> = act.peers
+                .iter()
+                .filter(|(_, info)| info.last_seen.elapsed() > ping_timeout)
+                .map(|(peer, _)| peer.clone())
+                .collect();
             for peer in peers {
-                debug!("Remove peer {}", peer.to_base58());
-                if let Some(connect_info) = act.peers.remove(&peer) {
-                    connect_info.pid.do_send(SessionEvent::Stop);
-                }
+                debug!("Remove peer {}, ping timeout", peer.to_base58());
+                act.drop_peer(peer, vec![]);
+            }
+            // the session failed before handshake is never disconnected, redial it too
+            let unconnected: Vec<PeerId> = act.persistent_peers
+                .keys()
+                .filter(|peer| !act.peers.contains_key(*peer))
+                .cloned()
+                .collect();
+            for peer in unconnected {
+                act.reconnect(&peer, ctx);
             }
         });
+
+        let persistent_peers: Vec<(PeerId, Multiaddr)> = self.persistent_peers
+            .iter()
+            .map(|(peer, reconnect)| (peer.clone(), reconnect.address.clone()))
+            .collect();
+        for (peer, address) in persistent_peers {
+            self.add_peer(peer, vec![address]);
+        }
+
+        ctx.run_interval(Duration::from_secs(1), |act, _| {
+            let max_message_rate = act.config.max_message_rate;
+            let spammers: Vec<PeerId> = act.peers
+                .iter_mut()
+                .filter_map(|(peer, info)| {
+                    let received = ::std::mem::replace(&mut info.received, 0);
+                    if received > max_message_rate {
+                        Some(peer.clone())
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            for peer in spammers {
+                act.report(&peer, Misbehavior::Spam);
+            }
+        });
+
+        ctx.run_interval(Duration::from_secs(60), |act, _| act.reputation.tick(unix_now()));
     }
 
     fn stopped(&mut self, _: &mut Self::Context) {
@@ -171,6 +284,9 @@ impl Handler<P2PEvent> for TcpServer {
             P2PEvent::DropPeer(remote_peer, remote_addresses) => {
                 self.drop_peer(remote_peer, remote_addresses);
             }
+            P2PEvent::Misbehave(remote_peer, misbehavior) => {
+                self.report(&remote_peer, misbehavior);
+            }
         }
         ()
     }
@@ -187,7 +303,7 @@ impl Handler<BroadcastEvent> for TcpServer {
                 let header = RawHeader::new(P2PMsgCode::Consensus, 10, chrono::Local::now().timestamp_millis() as u64, None);
                 let payload = msg.into_payload();
                 let msg = RawMessage::new(header, payload);
-                self.broadcast(&msg);
+                self.broadcast_validators(&msg);
             }
             BroadcastEvent::Blocks(peer_id, blocks) => {
                 let mut header = RawHeader::new(P2PMsgCode::Block, 10, chrono::Local::now().timestamp_millis() as u64, None);
@@ -198,15 +314,11 @@ impl Handler<BroadcastEvent> for TcpServer {
                 let msg = RawMessage::new(header, payload);
                 self.broadcast(&msg);
             }
-            BroadcastEvent::Sync(height) => {
-                self.peers.keys().take(1).for_each(|peer_id| {
-                    let header = RawHeader::new(P2PMsgCode::Sync, 10, chrono::Local::now().timestamp_millis() as u64, Some(peer_id.as_bytes().to_vec()));
-                    let payload = height.into_bytes();
-                    let msg = RawMessage::new(header, payload);
-                    self.broadcast(&msg);
-                });
+            BroadcastEvent::Sync(msg) => {
+                self.broadcast(&msg);
             }
-            _ => unimplemented!()
+            // the transactions aren't gossiped yet
+            BroadcastEvent::Transaction(_) => {}
         }
         ()
     }
@@ -220,15 +332,11 @@ impl Handler<ChainEvent> for TcpServer {
     fn handle(&mut self, msg: ChainEvent, ctx: &mut Self::Context) -> Self::Result {
         match msg {
             ChainEvent::NewBlock(block) => {
+                self.validators = (self.validators_fn)().into_iter().collect();
                 ctx.notify(BroadcastEvent::Blocks(None, Blocks(vec![block])));
             }
-            ChainEvent::NewHeader(_) => {}
-            ChainEvent::SyncBlock(height) => {
-                ctx.notify(BroadcastEvent::Sync(height))
-            }
-            ChainEvent::PostBlock(peer_id, blocks) => {
-                ctx.notify(BroadcastEvent::Blocks(peer_id, blocks))
-            }
+            // handled by synchronizer
+            ChainEvent::NewHeader(_) | ChainEvent::SyncBlock(_) => {}
         }
         ()
     }
@@ -236,36 +344,66 @@ impl Handler<ChainEvent> for TcpServer {
 
 impl Handler<ServerEvent> for TcpServer {
     type Result = Result<PeerId, P2PError>;
-    fn handle(&mut self, msg: ServerEvent, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: ServerEvent, ctx: &mut Self::Context) -> Self::Result {
         match msg {
             ServerEvent::Connected(ref peer_id, ref bound_type, ref pid, ref raw_msg) => {
                 debug!("Connected peer: {:?}", peer_id);
-                return self.handle_handshake(bound_type.clone(), pid.clone(), raw_msg.payload());
+                return self.handle_handshake(peer_id.clone(), bound_type.clone(), pid.clone(), raw_msg.payload());
             }
             ServerEvent::Disconnected(ref peer_id) => {
                 debug!("Disconnected peer: {:?}", peer_id);
                 self.peers.remove(&peer_id);
+                self.reconnect(peer_id, ctx);
                 return Ok(peer_id.clone());
             }
             ServerEvent::Ping(ref peer_id) => {
-                let mut info = self.peers.get_mut(peer_id).unwrap();
-                info.connect_time = chrono::Utc::now();
+                let info = self.peers.get_mut(peer_id).ok_or(P2PError::InvalidMessage)?;
+                info.last_seen = Instant::now();
+                return Ok(peer_id.clone());
+            }
+            ServerEvent::Pong(ref peer_id, rtt) => {
+                let info = self.peers.get_mut(peer_id).ok_or(P2PError::InvalidMessage)?;
+                info.last_seen = Instant::now();
+                info.rtt = Some(rtt);
+                metrics::P2P_PING_RTT.observe(metrics::duration_to_secs(rtt));
+                return Ok(peer_id.clone());
+            }
+            ServerEvent::Misbehave(ref peer_id, misbehavior) => {
+                self.report(peer_id, misbehavior);
                 return Ok(peer_id.clone());
             }
 
             // 接收端
             ServerEvent::Message(ref peer_id, ref raw_msg) => {
-                let hash: Hash = raw_msg.hash();
+                if let Some(info) = self.peers.get_mut(peer_id) {
+                    info.received += 1;
+                    info.last_seen = Instant::now();
+                }
+                let hash: Hash = raw_msg.gossip_id();
                 let now = Local::now().timestamp_millis() as u64;
                 if now < raw_msg.header().create_time {
                     trace!("Skip message({:?}) cause of timeout", hash.short());
                     return Ok(peer_id.clone());
                 }
+                if raw_msg.is_stale_gossip(now) {
+                    trace!("Skip message({:?}) cause of stale", hash.short());
+                    return Ok(peer_id.clone());
+                }
                 if self.cache.get(&hash).is_some() {
                     trace!("Skip message({:?}) cause of received", hash.short());
                     return Ok(peer_id.clone());
                 } else {
-                    (self.handles)(peer_id.clone(), raw_msg.clone());
+                    self.cache.insert(hash, true);
+                    if let Err(err) = (self.handles)(peer_id.clone(), raw_msg.clone()) {
+                        debug!("Failed to handle message from {}, err: {}", peer_id.to_base58(), err);
+                        self.report(peer_id, Misbehavior::InvalidMessage);
+                        return Ok(peer_id.clone());
+                    }
+                    if raw_msg.is_gossip() && raw_msg.header().ttl > 1 && self.verify_gossip(raw_msg) {
+                        let mut relay_msg = raw_msg.clone();
+                        relay_msg.mut_header().ttl -= 1;
+                        self.relay(peer_id, &relay_msg);
+                    }
                     return Ok(peer_id.clone());
                 }
             }
@@ -275,13 +413,19 @@ impl Handler<ServerEvent> for TcpServer {
 }
 
 impl TcpServer {
+    /// the peer id of the node is derived from the node key, the sessions are encrypted by it
     pub fn new(
-        peer_id: PeerId,
+        node_key: NodeKey,
         mul_addr: Multiaddr,
-        key: Option<secio::SecioKeyPair>,
         genesis: Hash,
+        chain_id: u64,
+        config: P2PConfig,
+        reputation: Reputation,
+        handshake_fn: Box<Fn() -> Handshake>,
         author: Box<Fn(Handshake) -> bool>,
         handles: Box<Fn(PeerId, RawMessage) -> Result<(), String>>,
+        validators: Box<Fn() -> Vec<Address>>,
+        persistent_peers: Vec<(PeerId, Multiaddr)>,
     ) -> Addr<TcpServer> {
         let mut addr: String = String::new();
         mul_addr.iter().for_each(|item| match &item {
@@ -297,6 +441,13 @@ impl TcpServer {
 
         // bind tcp listen address
         let lis = TcpListener::bind(&socket_addr).unwrap();
+        let peer_id = node_key.peer_id();
+        let allowed_peers = config.allowed_peers().unwrap();
+        let private_peers = config.private_peer_ids().unwrap();
+        let persistent_peers = persistent_peers
+            .into_iter()
+            .map(|(peer, address)| (peer, Reconnect { address, attempts: 0, pending: false }))
+            .collect();
         // create tcp server and dispatch coming connection to self handle
         TcpServer::create(move |ctx| {
             ctx.set_mailbox_capacity(MAX_INBOUND_CONNECTION_MAILBOX);
@@ -306,13 +457,22 @@ impl TcpServer {
             }));
             TcpServer {
                 pid: ctx.address().clone(),
-                key: key,
+                node_key: node_key,
                 node_info: (peer_id.clone(), mul_addr.clone()),
                 peers: HashMap::new(),
-                cache: LruCache::with_expiry_duration_and_capacity(Duration::from_secs(5), 100_000),
+                cache: LruCache::with_expiry_duration_and_capacity(Duration::from_millis(GOSSIP_EXPIRY_MILLIS), 100_000),
                 genesis: genesis,
+                chain_id: chain_id,
+                handshake_fn: handshake_fn,
                 author_fn: author,
                 handles: handles,
+                validators: validators().into_iter().collect(),
+                validators_fn: validators,
+                config: config,
+                reputation: reputation,
+                allowed_peers: allowed_peers,
+                private_peers: private_peers,
+                persistent_peers: persistent_peers,
             }
         })
     }
@@ -321,59 +481,230 @@ impl TcpServer {
         if self.peers.contains_key(&remote_id) {
             return;
         }
+        // the persistent peer waits for its backoff
+        if self.persistent_peers.get(&remote_id).map_or(false, |reconnect| reconnect.pending) {
+            return;
+        }
+        if self.reputation.is_banned(&remote_id, unix_now()) {
+            trace!("Skip dialing banned peer {}", remote_id.to_base58());
+            return;
+        }
+        if !self.is_allowed(&remote_id) {
+            trace!("Skip dialing peer {}, only the sentries are connected", remote_id.to_base58());
+            return;
+        }
+        let outbound = self.peers.values().filter(|info| info.bound_type == BoundType::OutBound).count() as u64;
+        if outbound >= self.config.max_outbound || self.peers.len() as u64 >= self.config.max_connection_size {
+            trace!("Skip dialing peer {}, the outbound connections are full", remote_id.to_base58());
+            return;
+        }
 
         let mul_addr = remote_addresses[0].clone();
-        let local_id = self.node_info.0.clone();
+        let node_key = self.node_key.clone();
         let server_id = self.pid.clone();
-        let genesis = self.genesis.clone();
         let delay = rand::random::<u64>() % 100;
         let timer_fut = Delay::new(Instant::now() + Duration::from_millis(delay));
         tokio::spawn(timer_fut.and_then(move |_| {
             // try to connect, dial it
             TcpDial::new(
                 remote_id,
-                local_id,
+                node_key,
                 mul_addr,
-                genesis,
                 server_id,
             );
             futures::future::ok(())
         }).map_err(|err| panic!(err)));
     }
 
-    // TODO
-    fn drop_peer(&mut self, _remote_id: PeerId, _remote_addresses: Vec<Multiaddr>) {}
+    /// drop_peer closes the session, the `Disconnected` of it schedules the redial of persistent peers
+    fn drop_peer(&mut self, remote_id: PeerId, _remote_addresses: Vec<Multiaddr>) {
+        if let Some(connect_info) = self.peers.remove(&remote_id) {
+            debug!("Drop peer {}", remote_id.to_base58());
+            connect_info.pid.do_send(SessionEvent::Stop);
+        }
+    }
 
+    /// reconnect redials the persistent peer after the backoff, it's doubled on every failure
+    fn reconnect(&mut self, peer: &PeerId, ctx: &mut Context<Self>) {
+        let delay = match self.persistent_peers.get_mut(peer) {
+            Some(ref mut reconnect) if !reconnect.pending => {
+                reconnect.pending = true;
+                reconnect.attempts += 1;
+                self.config.reconnect_delay(reconnect.attempts - 1)
+            }
+            _ => return,
+        };
+        debug!("Redial persistent peer {} in {:?}", peer.to_base58(), delay);
+        let peer = peer.clone();
+        ctx.run_later(delay, move |act, _| {
+            let address = match act.persistent_peers.get_mut(&peer) {
+                Some(reconnect) => {
+                    reconnect.pending = false;
+                    reconnect.address.clone()
+                }
+                None => return,
+            };
+            act.add_peer(peer, vec![address]);
+        });
+    }
+
+    /// peer_id is authenticated by the secure handshake, the claimed peer id must be the same
     fn handle_handshake(
         &mut self,
+        peer_id: PeerId,
         bound_type: BoundType,
         pid: Addr<Session>,
         payload: &Vec<u8>,
     ) -> Result<PeerId, P2PError> {
-        use std::borrow::Cow;
-        let handshake: Handshake = Handshake::from_bytes(Cow::from(payload));
-        let peer_id = handshake.peer_id();
+        let handshake: Handshake = match decode(payload) {
+            Ok(handshake) => handshake,
+            Err(_) => {
+                self.report(&peer_id, Misbehavior::InvalidMessage);
+                return Err(P2PError::HandShakeFailed);
+            }
+        };
+        if handshake.peer_id().as_ref() != Some(&peer_id) {
+            self.report(&peer_id, Misbehavior::InvalidMessage);
+            return Err(P2PError::HandShakeFailed);
+        }
+        if self.reputation.is_banned(&peer_id, unix_now()) {
+            return Err(P2PError::Banned);
+        }
         if self.peers.contains_key(&peer_id) {
             return Err(P2PError::DumpConnected);
         }
-        if self.node_info.0 == handshake.peer_id() {
+        if self.node_info.0 == peer_id {
             return Err(P2PError::HandShakeFailed);
         }
 
         if !(self.author_fn)(handshake.clone()) {
             return Err(P2PError::DifferentGenesis);
         }
+        let version = match handshake.negotiate() {
+            Some(version) => version,
+            None => {
+                debug!("Incompatible protocol version of peer {}, version: {}", peer_id.to_base58(), handshake.version());
+                return Err(P2PError::IncompatibleVersion);
+            }
+        };
 
-        match bound_type {
-            BoundType::InBound => {}
-            BoundType::OutBound => {}
+        let validator = handshake.validator();
+        if let Some(ref validator) = validator {
+            debug!("Peer {} is bound to validator {:?}", peer_id.to_base58(), validator);
+        }
+        let is_validator = validator.map_or(false, |validator| self.validators.contains(&validator));
+        let reserved = is_validator || self.private_peers.contains(&peer_id) || self.allowed_peers.is_some();
+        if !self.has_slot(bound_type, reserved) {
+            return Err(P2PError::TooManyPeers);
         }
-        let connect_info = ConnectInfo::new(chrono::Utc::now(), BoundType::InBound, pid);
+        debug!(
+            "Peer {} handshaked, version: {}, protocol: {}, best height: {}, best hash: {:?}",
+            peer_id.to_base58(),
+            handshake.version(),
+            version,
+            handshake.best_height(),
+            handshake.best_hash()
+        );
+        if let Some(reconnect) = self.persistent_peers.get_mut(&peer_id) {
+            reconnect.attempts = 0;
+        }
+        let connect_info = ConnectInfo::new(chrono::Utc::now(), bound_type, pid, validator, version, &handshake);
         self.peers.entry(peer_id.clone()).or_insert(connect_info);
         Ok(peer_id)
     }
 
-    fn broadcast(&self, msg: &RawMessage) {
+    /// is_allowed returns false if the node is hidden behind the sentries and the peer is not one of them
+    fn is_allowed(&self, peer: &PeerId) -> bool {
+        self.allowed_peers.as_ref().map_or(true, |allowed_peers| allowed_peers.contains(peer))
+    }
+
+    /// has_slot checks the connection limits, the last `reserved_validators` slots of each
+    /// direction are kept for the validators
+    fn has_slot(&self, bound_type: BoundType, reserved: bool) -> bool {
+        let max = match bound_type {
+            BoundType::InBound => self.config.max_inbound,
+            BoundType::OutBound => self.config.max_outbound,
+        };
+        let count = self.peers.values().filter(|info| info.bound_type == bound_type).count() as u64;
+        if count >= max || self.peers.len() as u64 >= self.config.max_connection_size {
+            return false;
+        }
+        reserved || count + self.config.reserved_validators < max
+    }
+
+    /// report decreases the reputation of the peer, it's disconnected and banned once the
+    /// reputation is used up
+    fn report(&mut self, peer: &PeerId, misbehavior: Misbehavior) {
+        metrics::P2P_MISBEHAVIORS.with_label_values(&[misbehavior.as_str()]).inc();
+        debug!("Peer {} misbehaved: {:?}", peer.to_base58(), misbehavior);
+        if self.reputation.report(peer, misbehavior, unix_now()) {
+            warn!("Ban peer {} for {:?}", peer.to_base58(), self.config.ban_duration);
+            metrics::P2P_BANNED_PEERS.inc();
+            self.drop_peer(peer.clone(), vec![]);
+        }
+    }
+
+    /// verify_gossip checks the gossip message before it's relayed, the handles are asynchronous so
+    /// their result can't be waited for. The transaction must be signed, the block must be sealed by
+    /// the current validators and the consensus message must be signed by one of them.
+    fn verify_gossip(&self, msg: &RawMessage) -> bool {
+        match msg.header().code {
+            P2PMsgCode::Transaction => decode::<Transaction>(msg.payload())
+                .map_or(false, |transaction| transaction.verify_sign(self.chain_id)),
+            P2PMsgCode::Block => {
+                let validators: Vec<Address> = self.validators.iter().cloned().collect();
+                decode::<Blocks>(msg.payload()).map_or(false, |blocks| {
+                    blocks.0.iter().all(|block| verify_seals(block.header(), &validators).is_ok())
+                })
+            }
+            P2PMsgCode::Consensus => decode::<GossipMessage>(msg.payload())
+                .ok()
+                .and_then(|mut gossip| gossip.address().ok())
+                .map_or(false, |address| self.validators.contains(&address)),
+            _ => false,
+        }
+    }
+
+    /// relay forwards the gossip message to the gossip targets, the sender is excluded
+    fn relay(&self, sender: &PeerId, msg: &RawMessage) {
+        let targets = self.gossip_targets(Some(sender), msg);
+        trace!("Relay message, code: {:?}, ttl: {}, peers: {}", msg.header().code, msg.header().ttl, targets.len());
+        for pid in targets {
+            pid.do_send(msg.clone());
+        }
+    }
+
+    /// broadcast_validators sends the message to the gossip targets, the validators get it directly
+    /// and the fanout peers relay it to the validators which are not connected to us
+    fn broadcast_validators(&mut self, msg: &RawMessage) {
+        self.cache.insert(msg.gossip_id(), true);
+        let targets = self.gossip_targets(None, msg);
+        trace!("Broadcast message to validators, code: {:?}, peers: {}", msg.header().code, targets.len());
+        for pid in targets {
+            pid.do_send(msg.clone());
+        }
+    }
+
+    /// gossip_targets returns the connected validators, the private peers and a random fanout
+    /// of the other peers which handle the message
+    fn gossip_targets(&self, sender: Option<&PeerId>, msg: &RawMessage) -> Vec<Addr<Session>> {
+        let (mut targets, mut others): (Vec<_>, Vec<_>) = self.peers
+            .iter()
+            .filter(|(peer, info)| sender != Some(*peer) && info.supports(&msg.header().code))
+            .partition(|(peer, info)| {
+                self.private_peers.contains(*peer) || info.validator.map_or(false, |validator| self.validators.contains(&validator))
+            });
+        rand::thread_rng().shuffle(&mut others);
+        others.truncate(self.config.gossip_fanout);
+        targets.extend(others);
+        targets.into_iter().map(|(_, info)| info.pid.clone()).collect()
+    }
+
+    fn broadcast(&mut self, msg: &RawMessage) {
+        // the echoes of our own gossip are dropped
+        if msg.is_gossip() {
+            self.cache.insert(msg.gossip_id(), true);
+        }
         if let Some(ref peer) = msg.header().peer_id {
             let peer = PeerId::from_bytes(peer.clone()).unwrap();
             debug!("Broadcast message, code: {:?}, peer: {:?}", msg.header(), peer.to_base58());
@@ -381,7 +712,8 @@ impl TcpServer {
                 info.pid.do_send(msg.clone());
             }
         } else {
-            for (peer, info) in &self.peers {
+            // the peers which don't handle the message are skipped
+            for (peer, info) in self.peers.iter().filter(|(_, info)| info.supports(&msg.header().code)) {
                 debug!("Broadcast message, code: {:?}, peer: {:?}", msg.header(), peer.to_base58());
                 info.pid.do_send(msg.clone());
             }
@@ -389,41 +721,67 @@ impl TcpServer {
     }
 }
 
+pub(crate) fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+/// A connection which has passed the secure handshake
 #[derive(Message)]
-struct TcpConnectOutBound(TcpStream, PeerId);
+struct TcpConnectSecured(TcpStream, SecureSession, BoundType);
 
-/// Handle stream of TcpStream's
-impl Handler<TcpConnectOutBound> for TcpServer {
+impl Handler<TcpConnectSecured> for TcpServer {
     type Result = ();
 
-    fn handle(&mut self, msg: TcpConnectOutBound, _ctx: &mut Context<Self>) {
-        trace!("TcpServer receive tcp connect event, peerid: {:?}", msg.1);
-        // For each incoming connection we create `session` actor with out chat server
-        if self.peers.contains_key(&msg.1) {
-            msg.0.shutdown(net::Shutdown::Both).unwrap();
+    fn handle(&mut self, msg: TcpConnectSecured, _: &mut Context<Self>) {
+        let TcpConnectSecured(stream, secure, bound_type) = msg;
+        trace!("TcpServer receive secured connection, peerid: {:?}", secure.remote);
+        if self.peers.contains_key(&secure.remote)
+            || self.reputation.is_banned(&secure.remote, unix_now())
+            || !self.is_allowed(&secure.remote)
+        {
+            stream.shutdown(net::Shutdown::Both).unwrap_or(());
             return;
         }
 
-        let peer_id = msg.1.clone();
         let server_id = self.pid.clone();
         let local_id = self.node_info.0.clone();
-        let genesis = self.genesis.clone();
+        let handshake = (self.handshake_fn)();
+        let (max_frame_size, compression) = (self.config.max_frame_size, self.config.compression);
+        let (ping_interval, ping_timeout) = (self.config.ping_interval, self.config.ping_timeout);
+        // For each connection we create `session` actor with out chat server
         Session::create(move |ctx| {
-            let (r, w) = msg.0.split();
-            Session::add_stream(FramedRead::new(r, MsgPacketCodec), ctx);
+            let SecureSession { remote, send, recv, .. } = secure;
+            let (r, w) = stream.split();
+            let version = SessionVersion::default();
+            Session::add_stream(FramedRead::new(r, MsgPacketCodec::new(Some(recv), max_frame_size, compression, version.clone())), ctx);
             Session::new(
                 ctx.address().clone(),
-                peer_id,
+                remote,
                 local_id,
                 server_id,
-                actix::io::FramedWrite::new(w, MsgPacketCodec, ctx),
-                BoundType::OutBound,
-                genesis,
+                actix::io::FramedWrite::new(w, MsgPacketCodec::new(Some(send), max_frame_size, compression, version.clone()), ctx),
+                bound_type,
+                handshake,
+                version,
+                ping_interval,
+                ping_timeout,
             )
         });
     }
 }
 
+/// The dialing failed before the session was created
+#[derive(Message)]
+struct TcpDialFailed(PeerId);
+
+impl Handler<TcpDialFailed> for TcpServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: TcpDialFailed, ctx: &mut Context<Self>) {
+        self.reconnect(&msg.0, ctx);
+    }
+}
+
 #[derive(Message)]
 struct TcpConnectInBound(TcpStream);
 
@@ -432,21 +790,15 @@ impl Handler<TcpConnectInBound> for TcpServer {
 
     fn handle(&mut self, msg: TcpConnectInBound, _: &mut Context<Self>) {
         let server_id = self.pid.clone();
-        let local_id = self.node_info.0.clone();
-        let genesis = self.genesis.clone();
-        Session::create(move |ctx| {
-            let (r, w) = msg.0.split();
-            Session::add_stream(FramedRead::new(r, MsgPacketCodec), ctx);
-            Session::new(
-                ctx.address().clone(),
-                ZERO_PEER.clone(),
-                local_id,
-                server_id,
-                actix::io::FramedWrite::new(w, MsgPacketCodec, ctx),
-                BoundType::InBound,
-                genesis,
-            )
-        });
+        Arbiter::spawn(
+            handshake(msg.0, self.node_key.clone())
+                .map(move |(stream, secure)| {
+                    server_id.do_send(TcpConnectSecured(stream, secure, BoundType::InBound));
+                })
+                .map_err(|err| {
+                    debug!("Inbound secure handshake fail, err: {}", err);
+                }),
+        );
     }
 }
 
@@ -461,9 +813,8 @@ impl Actor for TcpDial {
 impl TcpDial {
     pub fn new(
         peer_id: PeerId,
-        local_id: PeerId,
+        node_key: NodeKey,
         mul_addr: Multiaddr,
-        genesis: Hash,
         tcp_server: Addr<TcpServer>,
     ) {
         let socket_addr = multiaddr_to_ipv4(&mul_addr).unwrap();
@@ -472,33 +823,26 @@ impl TcpDial {
             &peer_id,
             &socket_addr
         );
+        let (failed_id, failed_server) = (peer_id.clone(), tcp_server.clone());
         Arbiter::spawn(
             TcpStream::connect(&socket_addr)
-                .and_then(move |stream| {
+                .and_then(move |stream| handshake(stream, node_key))
+                .and_then(move |(stream, secure)| {
                     trace!("Dialing remote peer: {:?}", peer_id);
-                    let peer_id = peer_id.clone();
-                    let local_id = local_id.clone();
-                    let genesis = genesis.clone();
-                    let tcp_server = tcp_server.clone();
-                    Session::create(move |ctx| {
-                        let (r, w) = stream.split();
-                        Session::add_stream(FramedRead::new(r, MsgPacketCodec), ctx);
-                        Session::new(
-                            ctx.address().clone(),
-                            peer_id,
-                            local_id,
-                            tcp_server,
-                            actix::io::FramedWrite::new(w, MsgPacketCodec, ctx),
-                            BoundType::OutBound,
-                            genesis,
-                        )
-                    });
-
-                    futures::future::ok(())
+                    // the remote must own the key of the dialed peer id
+                    if secure.remote != peer_id {
+                        stream.shutdown(net::Shutdown::Both).unwrap_or(());
+                        return Err(::std::io::Error::new(
+                            ::std::io::ErrorKind::PermissionDenied,
+                            format!("remote peer is {}, not {}", secure.remote.to_base58(), peer_id.to_base58()),
+                        ));
+                    }
+                    tcp_server.do_send(TcpConnectSecured(stream, secure, BoundType::OutBound));
+                    Ok(())
                 })
-                .map_err(|e| {
+                .map_err(move |e| {
                     error!("Dial tcp connect fail, err: {}", e);
-                    ()
+                    failed_server.do_send(TcpDialFailed(failed_id));
                 }),
         );
     }
@@ -11,13 +11,13 @@ use futures::stream::once;
 use libp2p::multiaddr::Protocol;
 use libp2p::Multiaddr;
 use libp2p::PeerId;
-use cryptocurrency_kit::crypto::Hash;
 use tokio::{codec::FramedRead, io::WriteHalf, net::TcpListener, net::TcpStream};
 
-use super::codec::MsgPacketCodec;
-use super::protocol::{BoundType, RawMessage, Header, P2PMsgCode, Handshake};
+use super::codec::{MsgPacketCodec, SessionVersion};
+use super::protocol::{BoundType, RawMessage, Header, P2PMsgCode, Handshake, MIN_PROTOCOL_VERSION};
+use super::reputation::Misbehavior;
 use super::server::{ServerEvent, SessionEvent, TcpServer};
-use crate::common::multiaddr_to_ipv4;
+use crate::common::{decode, multiaddr_to_ipv4};
 use crate::error::P2PError;
 
 pub struct Session {
@@ -27,7 +27,11 @@ pub struct Session {
     server: Addr<TcpServer>,
     bound_type: BoundType,
     handshaked: bool,
-    genesis: Hash,
+    handshake: Handshake,
+    // shared with the codecs of the session
+    version: SessionVersion,
+    ping_interval: Duration,
+    ping_timeout: Duration,
     framed: actix::io::FramedWrite<WriteHalf<TcpStream>, MsgPacketCodec>,
 }
 
@@ -37,8 +41,7 @@ impl Actor for Session {
     fn started(&mut self, ctx: &mut Self::Context) {
         // send a handshake message
         {
-            let peer_id = self.local_id.clone();
-            let handshake = Handshake::new("0.1.1".to_string(), peer_id.clone(), self.genesis.clone());
+            let handshake = self.handshake.clone();
             let raw_message = RawMessage::new(
                 Header::new(
                     P2PMsgCode::Handshake,
@@ -51,8 +54,8 @@ impl Actor for Session {
             ctx.add_message_stream(once(Ok(raw_message)));
         }
 
-        ctx.run_later(Duration::from_secs(1), |act, ctx| {
-            // pass 1s, not receive handshake packet, close the session
+        ctx.run_later(self.ping_timeout, |act, ctx| {
+            // not receive handshake packet within the ping timeout, close the session
             if act.handshaked {
                 return;
             }
@@ -66,7 +69,7 @@ impl Actor for Session {
             ctx.stop();
         });
 
-        ctx.run_interval(Duration::from_secs(1), |act, ctx| {
+        ctx.run_interval(self.ping_interval, |act, ctx| {
             if act.handshaked {
                 let raw_msg = RawMessage::new(Header::new(
                     P2PMsgCode::Ping, 3, chrono::Local::now().timestamp_millis() as u64, None),
@@ -101,6 +104,8 @@ impl StreamHandler<RawMessage, io::Error> for Session {
         debug!("Read message: {:?}, local_id:{:?}, peer_id:{:?}", msg.header(), self.local_id.to_base58(), self.peer_id.to_base58());
         match msg.header().code {
             P2PMsgCode::Handshake => {
+                // the server has checked the versions are compatible once the handshake is accepted
+                let version = decode::<Handshake>(msg.payload()).ok().and_then(|remote| remote.negotiate());
                 self.server
                     .send(ServerEvent::Connected(
                         self.peer_id.clone(),
@@ -109,7 +114,7 @@ impl StreamHandler<RawMessage, io::Error> for Session {
                         msg.clone(),
                     ))
                     .into_actor(self)
-                    .then(|res, act, ctx| {
+                    .then(move |res, act, ctx| {
                         match res {
                             Ok(res) => {
                                 if let Err(err) = res {
@@ -117,6 +122,7 @@ impl StreamHandler<RawMessage, io::Error> for Session {
                                     ctx.stop();
                                 } else {
                                     let peer = res.unwrap();
+                                    act.version.set(version.unwrap_or(MIN_PROTOCOL_VERSION) as u8);
                                     act.handshaked = true;
                                     act.peer_id = peer;
                                     trace!(
@@ -132,31 +138,41 @@ impl StreamHandler<RawMessage, io::Error> for Session {
                     })
                     .wait(ctx);
             }
-            P2PMsgCode::Transaction => {}
-            P2PMsgCode::Block | P2PMsgCode::Consensus | P2PMsgCode::Sync => {
+            _ if msg.header().code.is_routed() => {
                 self.server.do_send(ServerEvent::Message(self.peer_id.clone(), msg));
             }
             P2PMsgCode::Ping => {
-                assert!(self.handshaked);
-                self.server
-                    .send(ServerEvent::Ping(self.peer_id.clone()))
-                    .into_actor(self)
-                    .then(|res, act, ctx| {
-                        match res {
-                            Ok(res) => {
-                                if res.is_err() {
-                                    ctx.stop();
-                                }
-                            }
-                            Err(err) => panic!(err),
-                        }
-                        actix::fut::ok(())
-                    })
-                    .wait(ctx);
+                if !self.handshaked {
+                    return;
+                }
+                // echo the create time of ping, the remote measures the round trip by it
+                let pong = RawMessage::new(Header::new(P2PMsgCode::Pong, 3, msg.header().create_time, None), vec![]);
+                self.framed.write(pong);
+                self.server.do_send(ServerEvent::Ping(self.peer_id.clone()));
+            }
+            P2PMsgCode::Pong => {
+                if !self.handshaked {
+                    return;
+                }
+                let now = chrono::Local::now().timestamp_millis() as u64;
+                let rtt = Duration::from_millis(now.saturating_sub(msg.header().create_time));
+                self.server.do_send(ServerEvent::Pong(self.peer_id.clone(), rtt));
+            }
+            _ => {
+                self.server.do_send(ServerEvent::Misbehave(self.peer_id.clone(), Misbehavior::InvalidMessage));
+                ctx.stop()
             }
-            _ => ctx.stop(),
         }
     }
+
+    /// the frame which can't be decrypted or decoded is reported to server
+    fn error(&mut self, err: io::Error, _: &mut Context<Self>) -> Running {
+        debug!("Read message fail, peer_id:{:?}, err: {}", self.peer_id.to_base58(), err);
+        if err.kind() == io::ErrorKind::InvalidData {
+            self.server.do_send(ServerEvent::Misbehave(self.peer_id.clone(), Misbehavior::UndecodableFrame));
+        }
+        Running::Stop
+    }
 }
 
 /// receive raw message from server, forward it to network
@@ -164,7 +180,7 @@ impl Handler<RawMessage> for Session {
     type Result = ();
 
     fn handle(&mut self, msg: RawMessage, _: &mut Context<Self>) {
-        if msg.header().code != P2PMsgCode::Ping {
+        if msg.header().code != P2PMsgCode::Ping && msg.header().code != P2PMsgCode::Pong {
             debug!("Write message: {:?}, local_id:{:?}, peer_id:{:?}", msg.header(), self.local_id.to_base58(), self.peer_id.to_base58());
         }
         self.framed.write(msg);
@@ -186,7 +202,10 @@ impl Session {
         server: Addr<TcpServer>,
         framed: actix::io::FramedWrite<WriteHalf<TcpStream>, MsgPacketCodec>,
         bound_type: BoundType,
-        genesis: Hash,
+        handshake: Handshake,
+        version: SessionVersion,
+        ping_interval: Duration,
+        ping_timeout: Duration,
     ) -> Session {
         Session {
             pid: Some(self_pid),
@@ -196,7 +215,10 @@ impl Session {
             handshaked: false,
             framed: framed,
             bound_type: bound_type,
-            genesis: genesis,
+            handshake: handshake,
+            version: version,
+            ping_interval: ping_interval,
+            ping_timeout: ping_timeout,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,241 @@
+use std::io::Cursor;
+
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+
+use crate::{
+    common::decode,
+    config::Config,
+    consensus::backend::verify_transactions,
+    core::{chain::Chain, ledger::Ledger},
+    light::{ValidatorTransition, verify_validator_transition, verify_validator_transitions},
+    types::{Height, block::Block},
+};
+
+pub const SNAPSHOT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The trusted block of checkpoint sync
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub height: Height,
+    pub hash: Hash,
+}
+
+/// StateSnapshot is the state of chain at the checkpoint. The transactions are not executed, so the
+/// state is the checkpoint block (with the commit seals) and the validator set which follows the chain
+/// from the checkpoint, there is no account state to download. `transitions` proves the validator set
+/// changes between the trusted validators of the downloader and the checkpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub block: Block,
+    pub validators: Vec<Address>,
+    pub transitions: Vec<ValidatorTransition>,
+}
+
+implement_storagevalue_traits! {StateSnapshot}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetSnapshot {
+    pub request_id: u64,
+    pub height: Height,
+    pub index: u32,
+}
+
+implement_storagevalue_traits! {GetSnapshot}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    pub request_id: u64,
+    pub height: Height,
+    pub index: u32,
+    pub total: u32,
+    pub data: Vec<u8>,
+}
+
+implement_storagevalue_traits! {SnapshotChunk}
+
+impl StateSnapshot {
+    /// new takes the snapshot of local chain, none if the block of the height is not found.
+    pub fn new(chain: &Chain, height: Height) -> Option<Self> {
+        Self::from_ledger(&chain.get_ledger().read(), height)
+    }
+
+    /// from_ledger serves the validator transitions recorded after the genesis and before the height,
+    /// none if the block or a transition header is missing, e.g. below the checkpoint of a synced node
+    pub fn from_ledger(ledger: &Ledger, height: Height) -> Option<Self> {
+        let block = ledger.get_block_by_height(height)?;
+        let records = ledger.get_validator_transitions(height);
+        let (_, validators) = records.last()?.clone();
+        let mut transitions = vec![];
+        for (transition_height, transition_validators) in records {
+            if transition_height == 0 || transition_height >= height {
+                continue;
+            }
+            let header = ledger.get_header_by_height(transition_height)?;
+            transitions.push(ValidatorTransition { header, validators: transition_validators });
+        }
+        Some(StateSnapshot { block, validators, transitions })
+    }
+
+    /// chunks splits the encoded snapshot into the chunks of `SNAPSHOT_CHUNK_SIZE` for the transport,
+    /// the transitions of a long chain don't fit in one message
+    pub fn chunks(self) -> Vec<Vec<u8>> {
+        self.into_bytes().chunks(SNAPSHOT_CHUNK_SIZE).map(|chunk| chunk.to_vec()).collect()
+    }
+
+    /// from_chunks assembles the snapshot, none if the data is malformed
+    pub fn from_chunks(chunks: Vec<Vec<u8>>) -> Option<Self> {
+        let data: Vec<u8> = chunks.into_iter().flat_map(|chunk| chunk.into_iter()).collect();
+        decode(&data).ok()
+    }
+
+    /// verify checks the snapshot against the trusted checkpoint: the validator set is followed from
+    /// the trusted validators through the transitions before the checkpoint, and the checkpoint block
+    /// is sealed by the resulting set. The validator set of the snapshot is accepted only if it has
+    /// sealed the checkpoint block as well.
+    pub fn verify(&self, checkpoint: &Checkpoint, trusted: &[Address], config: &Config) -> Result<(), String> {
+        let header = self.block.header();
+        if header.height != checkpoint.height || self.block.hash() != checkpoint.hash {
+            return Err(format!("snapshot mismatch the checkpoint, height: {}, hash: {}", header.height, self.block.hash().short()));
+        }
+        if self.transitions.iter().any(|transition| transition.header.height >= header.height) {
+            return Err("validator transition is not before the checkpoint".to_string());
+        }
+        let validators = verify_validator_transitions(trusted, &self.transitions)
+            .map_err(|err| format!("invalid validator transitions, err: {}", err))?;
+        verify_validator_transition(header, &validators).map_err(|err| format!("invalid checkpoint seal, err: {}", err))?;
+        verify_transactions(&self.block, config.chain_id, config.merkle_upgrade_height).map_err(|err| format!("invalid checkpoint transactions, err: {}", err))?;
+        verify_validator_transition(header, &self.validators).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+    use cryptocurrency_kit::ethkey::{Generator, KeyPair, Random};
+    use lru_time_cache::LruCache;
+    use crate::{
+        common::random_dir,
+        config::StoreConfig,
+        core::ledger::LastMeta,
+        store::schema::{open_database, Schema},
+        types::{Validator, block::Header, transaction::merkle_root_transactions, votes::{encrypt_commit_bytes, Votes}},
+    };
+
+    fn addresses(key_pairs: &[KeyPair]) -> Vec<Address> {
+        key_pairs.iter().map(|key_pair| key_pair.address()).collect()
+    }
+
+    fn new_sealed_header(key_pairs: &[KeyPair], height: Height) -> Header {
+        let tx_hash = merkle_root_transactions(vec![], height, Some(0));
+        let mut header = Header::new_mock(EMPTY_HASH, key_pairs[0].address(), tx_hash, height, 100, None);
+        let digest = header.block_hash();
+        let votes = key_pairs.iter().map(|key_pair| encrypt_commit_bytes(&digest, key_pair.secret())).collect();
+        header.votes = Some(Votes::new(votes));
+        header
+    }
+
+    fn new_sealed_block(key_pairs: &[KeyPair]) -> Block {
+        Block::new(new_sealed_header(key_pairs, 10), vec![])
+    }
+
+    fn new_ledger(key_pairs: &[KeyPair]) -> Ledger {
+        let database = open_database(&random_dir(), &StoreConfig::default()).unwrap();
+        let mut ledger = Ledger::new(
+            LastMeta::new_zero(),
+            LruCache::with_capacity(1 << 10),
+            LruCache::with_capacity(1 << 10),
+            vec![],
+            Schema::new(Arc::new(database)),
+        );
+        ledger.add_validators(0, validators(key_pairs));
+        ledger.add_genesis_block(&Block::new(new_sealed_header(key_pairs, 0), vec![]));
+        ledger
+    }
+
+    fn validators(key_pairs: &[KeyPair]) -> Vec<Validator> {
+        key_pairs.iter().map(|key_pair| Validator::new(key_pair.address())).collect()
+    }
+
+    #[test]
+    fn t_snapshot_verify() {
+        let old: Vec<KeyPair> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let new: Vec<KeyPair> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let block = new_sealed_block(&new);
+        let checkpoint = Checkpoint { height: 10, hash: block.hash() };
+        let config = Config { merkle_upgrade_height: Some(0), ..Config::default() };
+        let mut snapshot = StateSnapshot { block, validators: addresses(&new), transitions: vec![] };
+        // the checkpoint is sealed by the validators which are unknown to the trusted set
+        assert!(snapshot.verify(&checkpoint, &addresses(&old), &config).is_err());
+        assert!(snapshot.verify(&checkpoint, &addresses(&new), &config).is_ok());
+
+        let mut signers = old.clone();
+        signers.extend_from_slice(&new);
+        snapshot.transitions = vec![ValidatorTransition { header: new_sealed_header(&signers, 5), validators: addresses(&new) }];
+        assert!(snapshot.verify(&checkpoint, &addresses(&old), &config).is_ok());
+
+        // the transition is not sealed by the trusted set
+        snapshot.transitions = vec![ValidatorTransition { header: new_sealed_header(&new, 5), validators: addresses(&new) }];
+        assert!(snapshot.verify(&checkpoint, &addresses(&old), &config).is_err());
+    }
+
+    #[test]
+    fn t_snapshot_validator_change() {
+        let old: Vec<KeyPair> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let new: Vec<KeyPair> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let mut signers = old.clone();
+        signers.extend_from_slice(&new);
+        // the validators change at the height 5, the block is sealed by both of the sets
+        let mut ledger = new_ledger(&old);
+        for height in 1..=10 {
+            let key_pairs = match height {
+                height if height < 5 => &old,
+                5 => &signers,
+                _ => &new,
+            };
+            ledger.add_block(&Block::new(new_sealed_header(key_pairs, height), vec![]));
+            if height == 5 {
+                ledger.add_validators(5, validators(&new));
+            }
+        }
+
+        let snapshot = StateSnapshot::from_ledger(&ledger, 10).unwrap();
+        assert_eq!(snapshot.validators, addresses(&new));
+        assert_eq!(snapshot.transitions.len(), 1);
+        assert_eq!(snapshot.transitions[0].header.height, 5);
+        let checkpoint = Checkpoint { height: 10, hash: snapshot.block.hash() };
+        let config = Config { merkle_upgrade_height: Some(0), ..Config::default() };
+        snapshot.verify(&checkpoint, &addresses(&old), &config).unwrap();
+
+        // the node which follows the old validators syncs to the checkpoint
+        let mut synced = new_ledger(&old);
+        let transitions = snapshot.transitions
+            .iter()
+            .map(|transition| (transition.header.height, validators(&new)))
+            .collect();
+        synced.import_checkpoint(&snapshot.block, validators(&new), transitions);
+        assert_eq!(*synced.get_last_block_height(), 10);
+        assert_eq!(*synced.get_validators(10), validators(&new));
+        assert_eq!(synced.get_validator_transitions(10), vec![(0, addresses(&old)), (5, addresses(&new))]);
+        // the header of the transition is not downloaded, the synced node can't serve the snapshot
+        assert!(StateSnapshot::from_ledger(&synced, 10).is_none());
+    }
+
+    #[test]
+    fn t_snapshot_chunks() {
+        let key_pairs: Vec<KeyPair> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let block = new_sealed_block(&key_pairs);
+        let validators = addresses(&key_pairs);
+        let snapshot = StateSnapshot { block: block.clone(), validators: validators.clone(), transitions: vec![] };
+        let chunks = snapshot.chunks();
+        assert_eq!(chunks.len(), 1);
+
+        let snapshot = StateSnapshot::from_chunks(chunks).unwrap();
+        assert_eq!(snapshot.block.hash(), block.hash());
+        assert_eq!(snapshot.validators, validators);
+        assert!(StateSnapshot::from_chunks(vec![vec![1, 2, 3]]).is_none());
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use ::actix::prelude::*;
+use actix_broker::BrokerSubscribe;
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use futures::prelude::*;
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use libp2p::{
+    core::swarm::NetworkBehaviourEventProcess,
+    floodsub::{Floodsub, FloodsubEvent, TopicBuilder},
+    identify::{Identify, IdentifyEvent},
+    ping::{Ping, PingEvent},
+    secio::SecioKeyPair,
+    tokio_io::{AsyncRead, AsyncWrite},
+    Multiaddr, NetworkBehaviour, PeerId, Swarm,
+};
+
+use super::config::Config as P2PConfig;
+use super::protocol::{Handshake, Header as RawHeader, P2PMsgCode, Payload, RawMessage};
+use super::reputation::{Misbehavior, Reputation};
+use super::server::{unix_now, AuthorFn, HandleMsgFn, HandshakePacketFn};
+use super::transport::{Transport, TransportContext, TransportHandle};
+use crate::{
+    common::decode,
+    metrics,
+    subscriber::P2PEvent,
+    subscriber::events::{BroadcastEvent, ChainEvent},
+    types::block::Blocks,
+};
+
+// all messages are published to the topic, the direct messages are dropped by the other receivers
+const TOPIC: &str = "/tendermint/messages/1";
+const PROTOCOL_VERSION: &str = "/tendermint/1";
+
+enum SwarmCommand {
+    Dial(Multiaddr),
+    Publish(RawMessage),
+}
+
+/// The events of the swarm behaviours, they are handled by `SwarmServer`
+#[derive(Message)]
+enum SwarmEvent {
+    Message(PeerId, RawMessage),
+    Identified(PeerId, String),
+    Pong(PeerId, Duration),
+}
+
+#[derive(NetworkBehaviour)]
+struct Behaviour<TSubstream: AsyncRead + AsyncWrite> {
+    floodsub: Floodsub<TSubstream>,
+    identify: Identify<TSubstream>,
+    ping: Ping<TSubstream>,
+    #[behaviour(ignore)]
+    server: Addr<SwarmServer>,
+}
+
+impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<FloodsubEvent> for Behaviour<TSubstream> {
+    fn inject_event(&mut self, event: FloodsubEvent) {
+        if let FloodsubEvent::Message(message) = event {
+            match decode::<RawMessage>(&message.data) {
+                Ok(msg) => self.server.do_send(SwarmEvent::Message(message.source, msg)),
+                Err(_) => self.server.do_send(P2PEvent::Misbehave(message.source, Misbehavior::UndecodableFrame)),
+            }
+        }
+    }
+}
+
+impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<IdentifyEvent> for Behaviour<TSubstream> {
+    fn inject_event(&mut self, event: IdentifyEvent) {
+        if let IdentifyEvent::Identified { peer_id, info, .. } = event {
+            self.server.do_send(SwarmEvent::Identified(peer_id, info.protocol_version));
+        }
+    }
+}
+
+impl<TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<PingEvent> for Behaviour<TSubstream> {
+    fn inject_event(&mut self, event: PingEvent) {
+        if let PingEvent::PingSuccess { peer, time } = event {
+            self.server.do_send(SwarmEvent::Pong(peer, time));
+        }
+    }
+}
+
+struct SwarmPeer {
+    // the validator address proved by the handshake
+    validator: Option<Address>,
+    // the negotiated protocol version
+    version: u32,
+    // the round trip time of the last ping
+    rtt: Option<Duration>,
+}
+
+/// SwarmServer is the transport based on the libp2p swarm, the streams are encrypted by secio
+/// and multiplexed by mplex or yamux. The peers exchange the same `Handshake` as `TcpServer`
+/// over the topic, the messages of the peers which aren't handshaked are dropped. The source of
+/// floodsub message isn't signed, so the handshake is only accepted from the identified connections.
+/// The swarm can't close a connection, so the dropped and banned peers are only ignored.
+pub struct SwarmServer {
+    local_id: PeerId,
+    commands: UnboundedSender<SwarmCommand>,
+    peers: HashMap<PeerId, SwarmPeer>,
+    // the peers directly connected to us, they are identified by the identify behaviour
+    connected: HashSet<PeerId>,
+    handshake_fn: Box<HandshakePacketFn>,
+    author_fn: Box<AuthorFn>,
+    handles: Box<HandleMsgFn>,
+    config: P2PConfig,
+    reputation: Reputation,
+    // validator mode: only the sentries can be connected
+    allowed_peers: Option<HashSet<PeerId>>,
+    persistent_peers: Vec<(PeerId, Multiaddr)>,
+}
+
+impl Actor for SwarmServer {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("[{:?}] Swarm server start", self.local_id.to_base58());
+        self.subscribe_async::<BroadcastEvent>(ctx);
+        ctx.run_interval(Duration::from_secs(2), |act, _| {
+            metrics::P2P_PEERS.set(act.peers.len() as i64);
+            for (peer, info) in &act.peers {
+                debug!(
+                    "{}----> [rtt: {:?}, validator: {:?}, version: {}]",
+                    peer.to_base58(),
+                    info.rtt,
+                    info.validator,
+                    info.version
+                );
+            }
+        });
+        ctx.run_interval(Duration::from_secs(60), |act, _| act.reputation.tick(unix_now()));
+
+        // the swarm redials the persistent peers which aren't handshaked
+        self.dial_persistent_peers();
+        ctx.run_interval(self.config.max_reconnect_backoff, |act, _| act.dial_persistent_peers());
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        info!("[{:?}] Swarm server stopped", self.local_id.to_base58());
+    }
+}
+
+impl Handler<SwarmEvent> for SwarmServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SwarmEvent, _: &mut Self::Context) {
+        match msg {
+            SwarmEvent::Identified(peer, protocol_version) => {
+                if protocol_version != PROTOCOL_VERSION {
+                    debug!("Skip peer {}, protocol: {}", peer.to_base58(), protocol_version);
+                    return;
+                }
+                // a new connection, announce the handshake to it
+                self.connected.insert(peer.clone());
+                if !self.peers.contains_key(&peer) {
+                    self.publish_handshake();
+                }
+            }
+            SwarmEvent::Pong(peer, rtt) => {
+                if let Some(info) = self.peers.get_mut(&peer) {
+                    info.rtt = Some(rtt);
+                    metrics::P2P_PING_RTT.observe(metrics::duration_to_secs(rtt));
+                }
+            }
+            SwarmEvent::Message(peer, msg) => self.on_message(peer, msg),
+        }
+    }
+}
+
+impl Handler<P2PEvent> for SwarmServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: P2PEvent, _: &mut Self::Context) {
+        match msg {
+            P2PEvent::AddPeer(remote_peer, remote_addresses) => {
+                if self.peers.contains_key(&remote_peer) || remote_addresses.is_empty() {
+                    return;
+                }
+                if self.reputation.is_banned(&remote_peer, unix_now()) || !self.is_allowed(&remote_peer) {
+                    return;
+                }
+                if self.peers.len() as u64 >= self.config.max_connection_size {
+                    return;
+                }
+                self.send(SwarmCommand::Dial(remote_addresses[0].clone()));
+            }
+            P2PEvent::DropPeer(remote_peer, _) => {
+                debug!("Drop peer {}", remote_peer.to_base58());
+                self.peers.remove(&remote_peer);
+                self.connected.remove(&remote_peer);
+            }
+            P2PEvent::Misbehave(remote_peer, misbehavior) => self.report(&remote_peer, misbehavior),
+        }
+    }
+}
+
+impl Handler<BroadcastEvent> for SwarmServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: BroadcastEvent, _: &mut Self::Context) {
+        match msg {
+            BroadcastEvent::Consensus(msg) => {
+                let header = RawHeader::new(P2PMsgCode::Consensus, 10, chrono::Local::now().timestamp_millis() as u64, None);
+                self.send(SwarmCommand::Publish(RawMessage::new(header, msg.into_payload())));
+            }
+            BroadcastEvent::Blocks(peer_id, blocks) => {
+                let mut header = RawHeader::new(P2PMsgCode::Block, 10, chrono::Local::now().timestamp_millis() as u64, None);
+                if let Some(peer_id) = peer_id {
+                    header.peer_id = Some(peer_id.as_bytes().to_vec());
+                }
+                self.send(SwarmCommand::Publish(RawMessage::new(header, blocks.into_bytes())));
+            }
+            BroadcastEvent::Sync(msg) => self.send(SwarmCommand::Publish(msg)),
+            // the transactions aren't gossiped yet
+            BroadcastEvent::Transaction(_) => {}
+        }
+    }
+}
+
+impl Handler<ChainEvent> for SwarmServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChainEvent, ctx: &mut Self::Context) {
+        match msg {
+            ChainEvent::NewBlock(block) => {
+                ctx.notify(BroadcastEvent::Blocks(None, Blocks(vec![block])));
+            }
+            // handled by synchronizer
+            ChainEvent::NewHeader(_) | ChainEvent::SyncBlock(_) => {}
+        }
+    }
+}
+
+impl SwarmServer {
+    /// the peer id of swarm is derived from the node key, the same as `TcpServer`
+    pub fn new(context: TransportContext) -> Result<Addr<SwarmServer>, String> {
+        let TransportContext { node_key, mul_addr, config, reputation, handshake_fn, author, handles, persistent_peers, .. } = context;
+        let key = SecioKeyPair::secp256k1_raw_key(&node_key.secret()[..]).map_err(|err| err.to_string())?;
+        let local_id = key.to_peer_id();
+        let allowed_peers = config.allowed_peers()?;
+        let transport = libp2p::build_development_transport(key.clone());
+        let topic = TopicBuilder::new(TOPIC).build();
+
+        let ctx = Context::new();
+        let mut swarm = {
+            let mut behaviour = Behaviour {
+                floodsub: Floodsub::new(local_id.clone()),
+                identify: Identify::new(PROTOCOL_VERSION.to_string(), format!("tendermint/{}", env!("CARGO_PKG_VERSION")), key.to_public_key()),
+                ping: Ping::default(),
+                server: ctx.address(),
+            };
+            behaviour.floodsub.subscribe(topic.clone());
+            Swarm::new(transport, behaviour, local_id.clone())
+        };
+        Swarm::listen_on(&mut swarm, mul_addr.clone()).map_err(|addr| format!("Failed to listen on {}", addr))?;
+
+        // the swarm is polled by the arbiter, the server talks with it by the commands
+        let (commands, mut receiver) = unbounded();
+        Arbiter::spawn(futures::future::poll_fn(move || -> Poll<(), ()> {
+            loop {
+                match receiver.poll() {
+                    Ok(Async::Ready(Some(SwarmCommand::Dial(address)))) => {
+                        if let Err(address) = Swarm::dial_addr(&mut swarm, address) {
+                            debug!("Failed to dial {}", address);
+                        }
+                    }
+                    Ok(Async::Ready(Some(SwarmCommand::Publish(msg)))) => swarm.floodsub.publish(&topic, msg.into_bytes()),
+                    // the server is stopped
+                    Ok(Async::Ready(None)) | Err(_) => return Ok(Async::Ready(())),
+                    Ok(Async::NotReady) => break,
+                }
+            }
+            loop {
+                match swarm.poll() {
+                    Ok(Async::Ready(Some(_))) => {}
+                    Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                    Err(err) => {
+                        error!("Swarm failed, err: {}", err);
+                        return Err(());
+                    }
+                }
+            }
+            Ok(Async::NotReady)
+        }));
+
+        info!("Swarm listen on: {:?}", mul_addr);
+        Ok(ctx.run(SwarmServer {
+            local_id,
+            commands,
+            peers: HashMap::new(),
+            connected: HashSet::new(),
+            handshake_fn,
+            author_fn: author,
+            handles,
+            config,
+            reputation,
+            allowed_peers,
+            persistent_peers,
+        }))
+    }
+
+    fn send(&self, command: SwarmCommand) {
+        if self.commands.unbounded_send(command).is_err() {
+            error!("Swarm has exited");
+        }
+    }
+
+    fn publish_handshake(&self) {
+        let header = RawHeader::new(P2PMsgCode::Handshake, 10, chrono::Local::now().timestamp_millis() as u64, None);
+        self.send(SwarmCommand::Publish(RawMessage::new(header, (self.handshake_fn)().into_bytes())));
+    }
+
+    fn dial_persistent_peers(&self) {
+        for (peer, address) in &self.persistent_peers {
+            if !self.peers.contains_key(peer) {
+                self.send(SwarmCommand::Dial(address.clone()));
+            }
+        }
+    }
+
+    fn on_message(&mut self, peer: PeerId, msg: RawMessage) {
+        if self.reputation.is_banned(&peer, unix_now()) || !self.is_allowed(&peer) {
+            return;
+        }
+        if let Some(ref target) = msg.header().peer_id {
+            if PeerId::from_bytes(target.clone()).ok().as_ref() != Some(&self.local_id) {
+                return;
+            }
+        }
+        if msg.header().code == P2PMsgCode::Handshake {
+            self.on_handshake(peer, msg.payload());
+            return;
+        }
+        if !self.peers.contains_key(&peer) {
+            trace!("Skip message from peer {}, not handshaked", peer.to_base58());
+            return;
+        }
+        // the liveness is checked by the ping behaviour
+        if !msg.header().code.is_routed() {
+            trace!("Skip message from peer {}, code: {:?}", peer.to_base58(), msg.header().code);
+            return;
+        }
+        if let Err(err) = (self.handles)(peer.clone(), msg) {
+            debug!("Failed to handle message from {}, err: {}", peer.to_base58(), err);
+            self.report(&peer, Misbehavior::InvalidMessage);
+        }
+    }
+
+    /// the handshake is checked as `TcpServer` does, the accepted peer gets our handshake back
+    fn on_handshake(&mut self, peer: PeerId, payload: &Payload) {
+        let handshake: Handshake = match decode(payload) {
+            Ok(handshake) => handshake,
+            Err(_) => return self.report(&peer, Misbehavior::InvalidMessage),
+        };
+        if handshake.peer_id().as_ref() != Some(&peer) {
+            return self.report(&peer, Misbehavior::InvalidMessage);
+        }
+        if !self.connected.contains(&peer) {
+            trace!("Skip handshake of peer {}, not connected", peer.to_base58());
+            return;
+        }
+        if self.peers.contains_key(&peer) || self.peers.len() as u64 >= self.config.max_connection_size {
+            return;
+        }
+        if !(self.author_fn)(handshake.clone()) {
+            debug!("Skip peer {}, different chain", peer.to_base58());
+            return;
+        }
+        let version = match handshake.negotiate() {
+            Some(version) => version,
+            None => {
+                debug!("Incompatible protocol version of peer {}, version: {}", peer.to_base58(), handshake.version());
+                return;
+            }
+        };
+        debug!("Peer {} handshaked, version: {}, best height: {}", peer.to_base58(), version, handshake.best_height());
+        self.peers.insert(peer, SwarmPeer { validator: handshake.validator(), version, rtt: None });
+        self.publish_handshake();
+    }
+
+    fn is_allowed(&self, peer: &PeerId) -> bool {
+        self.allowed_peers.as_ref().map_or(true, |allowed_peers| allowed_peers.contains(peer))
+    }
+
+    fn report(&mut self, peer: &PeerId, misbehavior: Misbehavior) {
+        metrics::P2P_MISBEHAVIORS.with_label_values(&[misbehavior.as_str()]).inc();
+        debug!("Peer {} misbehaved: {:?}", peer.to_base58(), misbehavior);
+        if self.reputation.report(peer, misbehavior, unix_now()) {
+            warn!("Ban peer {} for {:?}", peer.to_base58(), self.config.ban_duration);
+            metrics::P2P_BANNED_PEERS.inc();
+            self.peers.remove(peer);
+            self.connected.remove(peer);
+        }
+    }
+}
+
+pub struct SwarmTransport;
+
+impl Transport for SwarmTransport {
+    fn start(&self, context: TransportContext) -> Result<TransportHandle, String> {
+        let server = SwarmServer::new(context)?;
+        Ok(TransportHandle {
+            p2p_event: server.clone().recipient(),
+            chain_event: server.recipient(),
+        })
+    }
+}
@@ -0,0 +1,790 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ::actix::prelude::*;
+use actix_broker::BrokerIssue;
+use cryptocurrency_kit::crypto::{CryptoHash, Hash};
+use cryptocurrency_kit::ethkey::Address;
+use cryptocurrency_kit::storage::values::StorageValue;
+use libp2p::PeerId;
+
+use super::protocol::{Header as RawHeader, P2PMsgCode, RawMessage};
+use super::reputation::Misbehavior;
+use super::snapshot::{Checkpoint, GetSnapshot, SnapshotChunk, StateSnapshot, SNAPSHOT_CHUNK_SIZE};
+use crate::{
+    common::decode,
+    consensus::backend::verify_transactions,
+    consensus::consensus::SafeEngine,
+    consensus::error::EngineError,
+    core::chain::Chain,
+    error::ChainError,
+    metrics,
+    subscriber::{P2PEvent, ProcessSignals},
+    subscriber::events::{BroadcastEvent, ChainEvent},
+    types::{Height, block::{Block, Blocks, Header}, transaction::{Transaction, merkle_root_transactions}},
+};
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(2);
+const TICK_INTERVAL: Duration = Duration::from_millis(500);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// peer is removed if no status is received during the duration
+const PEER_EXPIRE: Duration = Duration::from_secs(10);
+
+pub const MAX_HEADERS_PER_REQUEST: u64 = 128;
+pub const MAX_BODIES_PER_REQUEST: usize = 32;
+// max number of downloaded but not imported headers
+const MAX_DOWNLOAD_AHEAD: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    pub height: Height,
+    pub hash: Hash,
+}
+
+implement_storagevalue_traits! {Status}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetHeaders {
+    pub request_id: u64,
+    pub from: Height,
+    pub limit: u64,
+}
+
+implement_storagevalue_traits! {GetHeaders}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Headers {
+    pub request_id: u64,
+    pub headers: Vec<Header>,
+}
+
+implement_storagevalue_traits! {Headers}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetBodies {
+    pub request_id: u64,
+    pub hashes: Vec<Hash>,
+}
+
+implement_storagevalue_traits! {GetBodies}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bodies {
+    pub request_id: u64,
+    pub bodies: Vec<Vec<Transaction>>,
+}
+
+implement_storagevalue_traits! {Bodies}
+
+/// A sync message received from the peer
+#[derive(Message)]
+pub struct SyncMessage(pub PeerId, pub RawMessage);
+
+struct PeerState {
+    height: Height,
+    last_seen: Instant,
+    inflight: usize,
+}
+
+enum RequestKind {
+    Headers { from: Height, limit: u64 },
+    Bodies { heights: Vec<Height> },
+    Snapshot { index: u32 },
+}
+
+struct Request {
+    peer: PeerId,
+    kind: RequestKind,
+    deadline: Instant,
+}
+
+/// The snapshot of checkpoint is downloaded from one peer chunk by chunk
+struct SnapshotDownload {
+    checkpoint: Checkpoint,
+    peer: Option<PeerId>,
+    total: u32,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl SnapshotDownload {
+    fn new(checkpoint: Checkpoint) -> Self {
+        SnapshotDownload {
+            checkpoint,
+            peer: None,
+            total: 0,
+            chunks: vec![],
+        }
+    }
+}
+
+/// Synchronizer downloads the missing blocks from peers.
+///
+/// Every node broadcasts its chain head by `Status`. If a peer is higher than us, the headers
+/// are requested from the best peer, their commit seals are verified, then the bodies are requested
+/// from all of the peers which have them in parallel. The blocks are verified again before they are
+/// inserted into the chain. Timeout and bad data are reported as `P2PEvent::Misbehave`, the reputation
+/// of server bans and disconnects the peer once its score is used up.
+///
+/// If a trusted checkpoint is configured, the state snapshot at it is downloaded and verified first,
+/// the chain jumps to the checkpoint and follows the peers from there.
+pub struct Synchronizer {
+    chain: Arc<Chain>,
+    verifier: SafeEngine,
+    p2p_subscriber: Addr<ProcessSignals>,
+    peers: HashMap<PeerId, PeerState>,
+    next_request_id: u64,
+    requests: HashMap<u64, Request>,
+    // verified headers, they are waiting for bodies
+    headers: BTreeMap<Height, Header>,
+    // downloaded blocks, they are waiting for importing
+    blocks: BTreeMap<Height, (PeerId, Block)>,
+    // checkpoint sync in progress
+    snapshot: Option<SnapshotDownload>,
+    // chunks of the last snapshot served to peers
+    served_snapshot: Option<(Height, Vec<Vec<u8>>)>,
+}
+
+impl Actor for Synchronizer {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(STATUS_INTERVAL, |act, _| act.broadcast_status());
+        ctx.run_interval(TICK_INTERVAL, |act, _| act.tick());
+        info!("Synchronizer has started");
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        info!("Synchronizer has stopped");
+    }
+}
+
+impl Handler<ChainEvent> for Synchronizer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChainEvent, _ctx: &mut Self::Context) {
+        match msg {
+            ChainEvent::NewBlock(_) => self.broadcast_status(),
+            ChainEvent::SyncBlock(height) => {
+                trace!("Receive a sync event, height: {}", height);
+                self.tick();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Handler<SyncMessage> for Synchronizer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SyncMessage, _ctx: &mut Self::Context) {
+        let SyncMessage(peer, msg) = msg;
+        let payload = msg.payload();
+        let result = match msg.header().code {
+            P2PMsgCode::Block => decode(payload).ok().map(|blocks| self.on_blocks(peer.clone(), blocks)),
+            P2PMsgCode::Status => decode(payload).ok().map(|status| self.on_status(peer.clone(), status)),
+            P2PMsgCode::GetHeaders => decode(payload).ok().map(|request| self.on_get_headers(peer.clone(), request)),
+            P2PMsgCode::Headers => decode(payload).ok().map(|response| self.on_headers(peer.clone(), response)),
+            P2PMsgCode::GetBodies => decode(payload).ok().map(|request| self.on_get_bodies(peer.clone(), request)),
+            P2PMsgCode::Bodies => decode(payload).ok().map(|response| self.on_bodies(peer.clone(), response)),
+            P2PMsgCode::GetSnapshot => decode(payload).ok().map(|request| self.on_get_snapshot(peer.clone(), request)),
+            P2PMsgCode::Snapshot => decode(payload).ok().map(|response| self.on_snapshot(peer.clone(), response)),
+            _ => None,
+        };
+        if result.is_none() {
+            self.misbehave(&peer, Misbehavior::InvalidMessage, "malformed sync message");
+        }
+    }
+}
+
+impl Synchronizer {
+    pub fn new(
+        chain: Arc<Chain>,
+        verifier: SafeEngine,
+        p2p_subscriber: Addr<ProcessSignals>,
+        checkpoint: Option<Checkpoint>,
+    ) -> Self {
+        let snapshot = checkpoint
+            .filter(|checkpoint| checkpoint.height > chain.get_last_height())
+            .map(SnapshotDownload::new);
+        Synchronizer {
+            chain,
+            verifier,
+            p2p_subscriber,
+            peers: HashMap::new(),
+            next_request_id: 0,
+            requests: HashMap::new(),
+            headers: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            snapshot,
+            served_snapshot: None,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.expire_requests();
+        self.expire_peers();
+        if self.snapshot.is_some() {
+            self.request_snapshot();
+            return;
+        }
+        self.import_blocks();
+        self.request_headers();
+        self.request_bodies();
+    }
+
+    fn broadcast_status(&mut self) {
+        let status = Status {
+            height: self.chain.get_last_height(),
+            hash: self.chain.get_last_hash(),
+        };
+        self.send(None, P2PMsgCode::Status, status.into_bytes());
+    }
+
+    /// the highest height which has been imported or downloaded
+    fn download_tip(&self) -> Height {
+        let local_height = self.chain.get_last_height();
+        let header_height = self.headers.keys().next_back().cloned().unwrap_or(0);
+        let block_height = self.blocks.keys().next_back().cloned().unwrap_or(0);
+        local_height.max(header_height).max(block_height)
+    }
+
+    fn header_hash_at(&self, height: Height) -> Option<Hash> {
+        if height <= self.chain.get_last_height() {
+            return self.chain.get_block_hash_by_height(height);
+        }
+        self.headers.get(&height).map(|header| header.block_hash())
+            .or_else(|| self.blocks.get(&height).map(|(_, block)| block.hash()))
+    }
+
+    fn on_status(&mut self, peer: PeerId, status: Status) {
+        let now = Instant::now();
+        let state = self.peers.entry(peer).or_insert_with(|| PeerState {
+            height: 0,
+            last_seen: now,
+            inflight: 0,
+        });
+        state.height = status.height;
+        state.last_seen = now;
+        if status.height > self.chain.get_last_height() {
+            self.request_headers();
+        }
+    }
+
+    /// on_blocks handles the new blocks broadcast by peer, they are inserted after verification.
+    /// If the parent is unknown, the missing blocks are synchronized from the peer.
+    fn on_blocks(&mut self, peer: PeerId, blocks: Blocks) {
+        debug!("Receive blocks from {}, size: {}", peer.to_base58(), blocks.0.len());
+        for block in blocks.0 {
+            if block.height() <= self.chain.get_last_height() {
+                continue;
+            }
+            match self.verify_block(&block) {
+                Ok(()) => {}
+                Err(EngineError::UnknownAncestor(height, _)) => {
+                    trace!("Unknown ancestor of block from {}, height: {}", peer.to_base58(), height);
+                    if let Some(state) = self.peers.get_mut(&peer) {
+                        state.height = state.height.max(height);
+                    }
+                    self.request_headers();
+                    return;
+                }
+                Err(err) => {
+                    metrics::P2P_INVALID_BLOCKS.inc();
+                    warn!("Reject block from {}, hash: {}, height: {}, err: {}", peer.to_base58(), block.hash().short(), block.height(), err);
+                    self.misbehave(&peer, Misbehavior::BadBlock, "invalid block");
+                    return;
+                }
+            }
+            if let Err(err) = self.chain.insert_block(&block) {
+                trace!("Failed to insert block from {}, height: {}, err: {}", peer.to_base58(), block.height(), err);
+            }
+        }
+    }
+
+    /// verify_block checks the header (parent, timestamp, commit seals and proposer) and transactions
+    fn verify_block(&self, block: &Block) -> Result<(), EngineError> {
+        self.verifier.verify_header(block.header(), true)?;
+        verify_transactions(block, self.chain.config.chain_id, self.chain.config.merkle_upgrade_height)
+    }
+
+    fn on_get_headers(&mut self, peer: PeerId, request: GetHeaders) {
+        let limit = request.limit.min(MAX_HEADERS_PER_REQUEST);
+        let headers: Vec<Header> = (request.from..request.from.saturating_add(limit))
+            .map(|height| self.chain.get_header_by_height(height))
+            .take_while(|header| header.is_some())
+            .map(|header| header.unwrap())
+            .collect();
+        trace!("Serve headers to {}, from: {}, size: {}", peer.to_base58(), request.from, headers.len());
+        let response = Headers { request_id: request.request_id, headers };
+        self.send(Some(&peer), P2PMsgCode::Headers, response.into_bytes());
+    }
+
+    fn on_get_bodies(&mut self, peer: PeerId, request: GetBodies) {
+        let bodies: Vec<Vec<Transaction>> = request.hashes
+            .iter()
+            .take(MAX_BODIES_PER_REQUEST)
+            .map(|block_hash| self.chain.get_block_by_hash(block_hash))
+            .take_while(|block| block.is_some())
+            .map(|block| block.unwrap().transactions().clone())
+            .collect();
+        trace!("Serve bodies to {}, size: {}", peer.to_base58(), bodies.len());
+        let response = Bodies { request_id: request.request_id, bodies };
+        self.send(Some(&peer), P2PMsgCode::Bodies, response.into_bytes());
+    }
+
+    fn on_headers(&mut self, peer: PeerId, response: Headers) {
+        let (from, limit) = match self.take_request(&peer, response.request_id) {
+            Some(RequestKind::Headers { from, limit }) => (from, limit),
+            Some(_) => {
+                self.misbehave(&peer, Misbehavior::InvalidMessage, "unexpected headers response");
+                return;
+            }
+            None => return,
+        };
+        if response.headers.is_empty() {
+            self.misbehave(&peer, Misbehavior::Timeout, "empty headers response");
+            return;
+        }
+        if response.headers.len() as u64 > limit {
+            self.misbehave(&peer, Misbehavior::InvalidMessage, "too many headers");
+            return;
+        }
+        // the chain has been moved, the response is stale
+        let parent_hash = match self.header_hash_at(from - 1) {
+            Some(parent_hash) => parent_hash,
+            None => return,
+        };
+        if let Err(err) = link_headers(&parent_hash, from, &response.headers) {
+            self.misbehave(&peer, Misbehavior::InvalidMessage, &err);
+            return;
+        }
+        for header in &response.headers {
+            if let Err(err) = self.verifier.verify_seal(header) {
+                self.misbehave(&peer, Misbehavior::InvalidMessage, &format!("invalid header seal, height: {}, err: {}", header.height, err));
+                return;
+            }
+        }
+        debug!("Download headers from {}, from: {}, size: {}", peer.to_base58(), from, response.headers.len());
+        for mut header in response.headers {
+            if header.height > self.chain.get_last_height() && !self.blocks.contains_key(&header.height) {
+                header.cache_hash(None);
+                self.headers.insert(header.height, header);
+            }
+        }
+        self.request_bodies();
+    }
+
+    fn on_bodies(&mut self, peer: PeerId, response: Bodies) {
+        let heights = match self.take_request(&peer, response.request_id) {
+            Some(RequestKind::Bodies { heights }) => heights,
+            Some(_) => {
+                self.misbehave(&peer, Misbehavior::InvalidMessage, "unexpected bodies response");
+                return;
+            }
+            None => return,
+        };
+        if response.bodies.is_empty() {
+            self.misbehave(&peer, Misbehavior::Timeout, "empty bodies response");
+            return;
+        }
+        if response.bodies.len() > heights.len() {
+            self.misbehave(&peer, Misbehavior::InvalidMessage, "too many bodies");
+            return;
+        }
+        let mut blocks = vec![];
+        for (height, transactions) in heights.iter().zip(response.bodies.into_iter()) {
+            let header = match self.headers.get(height) {
+                Some(header) => header.clone(),
+                // has been imported or reset
+                None => continue,
+            };
+            let tx_hash = merkle_root_transactions(transactions.clone(), *height, self.chain.config.merkle_upgrade_height);
+            if tx_hash != header.tx_hash {
+                self.misbehave(&peer, Misbehavior::InvalidMessage, &format!("invalid transaction root, height: {}", height));
+                return;
+            }
+            blocks.push(Block::new(header, transactions));
+        }
+        debug!("Download bodies from {}, size: {}", peer.to_base58(), blocks.len());
+        for block in blocks {
+            self.headers.remove(&block.height());
+            self.blocks.insert(block.height(), (peer.clone(), block));
+        }
+        self.import_blocks();
+        self.request_bodies();
+    }
+
+    /// import_blocks inserts the continuous downloaded blocks into the chain
+    fn import_blocks(&mut self) {
+        loop {
+            let next_height = self.chain.get_last_height() + 1;
+            // drop the blocks which have been imported by consensus
+            while self.blocks.keys().next().map_or(false, |height| *height < next_height) {
+                let height = *self.blocks.keys().next().unwrap();
+                self.blocks.remove(&height);
+            }
+            let (peer, block) = match self.blocks.remove(&next_height) {
+                Some(item) => item,
+                None => break,
+            };
+            if let Err(err) = self.verify_block(&block) {
+                metrics::P2P_INVALID_BLOCKS.inc();
+                self.misbehave(&peer, Misbehavior::BadBlock, &format!("invalid block, height: {}, err: {}", next_height, err));
+                self.reset_download(next_height);
+                break;
+            }
+            match self.chain.insert_block(&block) {
+                Ok(()) => {
+                    debug!("Import a block from {}, hash: {}, height: {}", peer.to_base58(), block.hash().short(), next_height);
+                }
+                Err(ChainError::Exists(_)) => {}
+                Err(err) => {
+                    error!("Failed to import block, height: {}, err: {}", next_height, err);
+                    self.reset_download(next_height);
+                    break;
+                }
+            }
+        }
+        self.headers = self.headers.split_off(&(self.chain.get_last_height() + 1));
+    }
+
+    fn request_headers(&mut self) {
+        let requesting = self.requests.values().any(|request| match request.kind {
+            RequestKind::Headers { .. } => true,
+            _ => false,
+        });
+        // the blocks before checkpoint are not downloaded
+        if self.snapshot.is_some() || requesting || self.headers.len() + self.blocks.len() >= MAX_DOWNLOAD_AHEAD {
+            return;
+        }
+        let tip = self.download_tip();
+        let best = self.peers
+            .iter()
+            .filter(|(_, state)| state.height > tip)
+            .max_by_key(|(_, state)| state.height)
+            .map(|(peer, state)| (peer.clone(), state.height));
+        if let Some((peer, height)) = best {
+            let from = tip + 1;
+            let limit = (height - tip).min(MAX_HEADERS_PER_REQUEST);
+            let request_id = self.new_request(&peer, RequestKind::Headers { from, limit });
+            debug!("Request headers from {}, from: {}, limit: {}", peer.to_base58(), from, limit);
+            let request = GetHeaders { request_id, from, limit };
+            self.send(Some(&peer), P2PMsgCode::GetHeaders, request.into_bytes());
+        }
+    }
+
+    /// request_bodies splits the headers which are not requested into chunks,
+    /// and requests them from the idle peers in parallel
+    fn request_bodies(&mut self) {
+        let requested: HashSet<Height> = self.requests
+            .values()
+            .flat_map(|request| match request.kind {
+                RequestKind::Bodies { ref heights } => heights.clone(),
+                _ => vec![],
+            })
+            .collect();
+        let pending: Vec<Height> = self.headers.keys().filter(|height| !requested.contains(height)).cloned().collect();
+        if pending.is_empty() {
+            return;
+        }
+        let mut idle_peers: Vec<(PeerId, Height)> = self.peers
+            .iter()
+            .filter(|(_, state)| state.inflight == 0)
+            .map(|(peer, state)| (peer.clone(), state.height))
+            .collect();
+        idle_peers.sort_by(|a, b| b.1.cmp(&a.1));
+
+        for chunk in pending.chunks(MAX_BODIES_PER_REQUEST) {
+            let last_height = *chunk.last().unwrap();
+            let idx = match idle_peers.iter().position(|(_, height)| *height >= last_height) {
+                Some(idx) => idx,
+                None => break,
+            };
+            let (peer, _) = idle_peers.remove(idx);
+            let hashes: Vec<Hash> = chunk.iter().map(|height| self.headers[height].block_hash()).collect();
+            let request_id = self.new_request(&peer, RequestKind::Bodies { heights: chunk.to_vec() });
+            debug!("Request bodies from {}, from: {}, size: {}", peer.to_base58(), chunk[0], chunk.len());
+            let request = GetBodies { request_id, hashes };
+            self.send(Some(&peer), P2PMsgCode::GetBodies, request.into_bytes());
+        }
+    }
+
+    /// request_snapshot requests the next chunk of the checkpoint snapshot,
+    /// the download restarts from the first chunk if the peer is gone
+    fn request_snapshot(&mut self) {
+        let requesting = self.requests.values().any(|request| match request.kind {
+            RequestKind::Snapshot { .. } => true,
+            _ => false,
+        });
+        if requesting {
+            return;
+        }
+        let local_height = self.chain.get_last_height();
+        if self.snapshot.as_ref().map_or(false, |download| download.checkpoint.height <= local_height) {
+            info!("Chain has reached the checkpoint, height: {}", local_height);
+            self.snapshot = None;
+            return;
+        }
+        let (peer, height, index) = match self.snapshot {
+            Some(ref mut download) => {
+                if download.peer.as_ref().map_or(true, |peer| !self.peers.contains_key(peer)) {
+                    let checkpoint_height = download.checkpoint.height;
+                    let best = self.peers
+                        .iter()
+                        .find(|(_, state)| state.height >= checkpoint_height)
+                        .map(|(peer, _)| peer.clone());
+                    *download = SnapshotDownload::new(download.checkpoint.clone());
+                    download.peer = best;
+                }
+                match download.peer {
+                    Some(ref peer) => (peer.clone(), download.checkpoint.height, download.chunks.len() as u32),
+                    None => return,
+                }
+            }
+            None => return,
+        };
+        let request_id = self.new_request(&peer, RequestKind::Snapshot { index });
+        debug!("Request snapshot from {}, height: {}, chunk: {}", peer.to_base58(), height, index);
+        let request = GetSnapshot { request_id, height, index };
+        self.send(Some(&peer), P2PMsgCode::GetSnapshot, request.into_bytes());
+    }
+
+    fn on_get_snapshot(&mut self, peer: PeerId, request: GetSnapshot) {
+        if self.served_snapshot.as_ref().map_or(true, |(height, _)| *height != request.height) {
+            self.served_snapshot = StateSnapshot::new(&self.chain, request.height)
+                .map(|snapshot| (request.height, snapshot.chunks()));
+        }
+        // total is zero if the snapshot is not available
+        let (total, data) = match self.served_snapshot {
+            Some((_, ref chunks)) => (chunks.len() as u32, chunks.get(request.index as usize).cloned().unwrap_or_default()),
+            None => (0, vec![]),
+        };
+        trace!("Serve snapshot to {}, height: {}, chunk: {}/{}", peer.to_base58(), request.height, request.index, total);
+        let response = SnapshotChunk {
+            request_id: request.request_id,
+            height: request.height,
+            index: request.index,
+            total,
+            data,
+        };
+        self.send(Some(&peer), P2PMsgCode::Snapshot, response.into_bytes());
+    }
+
+    fn on_snapshot(&mut self, peer: PeerId, response: SnapshotChunk) {
+        let index = match self.take_request(&peer, response.request_id) {
+            Some(RequestKind::Snapshot { index }) => index,
+            Some(_) => {
+                self.misbehave(&peer, Misbehavior::InvalidMessage, "unexpected snapshot response");
+                return;
+            }
+            None => return,
+        };
+        if response.total == 0 {
+            self.misbehave(&peer, Misbehavior::Timeout, "snapshot is not available");
+            return;
+        }
+        let completed = match self.snapshot {
+            Some(ref mut download) => {
+                let consistent = response.height == download.checkpoint.height
+                    && response.index == index
+                    && response.index < response.total
+                    && response.data.len() <= SNAPSHOT_CHUNK_SIZE
+                    && (download.chunks.is_empty() || download.total == response.total);
+                if consistent {
+                    download.total = response.total;
+                    download.chunks.push(response.data);
+                }
+                if consistent { Some(download.chunks.len() as u32 == download.total) } else { None }
+            }
+            None => return,
+        };
+        match completed {
+            Some(true) => self.import_snapshot(),
+            Some(false) => self.request_snapshot(),
+            None => self.misbehave(&peer, Misbehavior::InvalidMessage, "inconsistent snapshot chunk"),
+        }
+    }
+
+    /// import_snapshot verifies the downloaded snapshot against the checkpoint and imports it
+    fn import_snapshot(&mut self) {
+        let download = match self.snapshot.take() {
+            Some(download) => download,
+            None => return,
+        };
+        let peer = download.peer.clone().unwrap();
+        // the validators of local chain are trusted, the snapshot proves the changes from them
+        let trusted: Vec<Address> = self.chain
+            .get_validators(self.chain.get_last_height())
+            .iter()
+            .map(|validator| *validator.address())
+            .collect();
+        let result = StateSnapshot::from_chunks(download.chunks)
+            .ok_or_else(|| "malformed snapshot".to_string())
+            .and_then(|snapshot| {
+                snapshot.verify(&download.checkpoint, &trusted, &self.chain.config).map(|_| snapshot)
+            });
+        let snapshot = match result {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                self.snapshot = Some(SnapshotDownload::new(download.checkpoint));
+                self.misbehave(&peer, Misbehavior::InvalidMessage, &err);
+                return;
+            }
+        };
+        let transitions = snapshot.transitions
+            .into_iter()
+            .map(|transition| (transition.header.height, transition.validators))
+            .collect();
+        match self.chain.import_checkpoint(&snapshot.block, snapshot.validators, transitions) {
+            Ok(()) => info!("Checkpoint sync from {} finished, height: {}", peer.to_base58(), download.checkpoint.height),
+            Err(err) => warn!("Failed to import checkpoint, height: {}, err: {}", download.checkpoint.height, err),
+        }
+    }
+
+    fn new_request(&mut self, peer: &PeerId, kind: RequestKind) -> u64 {
+        self.next_request_id += 1;
+        let request = Request {
+            peer: peer.clone(),
+            kind,
+            deadline: Instant::now() + REQUEST_TIMEOUT,
+        };
+        self.requests.insert(self.next_request_id, request);
+        if let Some(state) = self.peers.get_mut(peer) {
+            state.inflight += 1;
+        }
+        self.next_request_id
+    }
+
+    fn take_request(&mut self, peer: &PeerId, request_id: u64) -> Option<RequestKind> {
+        if self.requests.get(&request_id).map_or(true, |request| request.peer != *peer) {
+            trace!("Unknown sync response from {}, request_id: {}", peer.to_base58(), request_id);
+            return None;
+        }
+        if let Some(state) = self.peers.get_mut(peer) {
+            state.inflight = state.inflight.saturating_sub(1);
+        }
+        self.requests.remove(&request_id).map(|request| request.kind)
+    }
+
+    /// the expired requests are removed, their ranges are requested again from other peers
+    fn expire_requests(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<(u64, PeerId)> = self.requests
+            .iter()
+            .filter(|(_, request)| request.deadline < now)
+            .map(|(request_id, request)| (*request_id, request.peer.clone()))
+            .collect();
+        for (request_id, peer) in expired {
+            self.take_request(&peer, request_id);
+            self.misbehave(&peer, Misbehavior::Timeout, "sync request timeout");
+        }
+    }
+
+    fn expire_peers(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<PeerId> = self.peers
+            .iter()
+            .filter(|(_, state)| now.duration_since(state.last_seen) > PEER_EXPIRE)
+            .map(|(peer, _)| peer.clone())
+            .collect();
+        for peer in expired {
+            trace!("Remove expired sync peer: {}", peer.to_base58());
+            self.remove_peer(&peer);
+        }
+    }
+
+    fn remove_peer(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        self.requests.retain(|_, request| request.peer != *peer);
+    }
+
+    /// reset_download drops the downloaded data from the height
+    fn reset_download(&mut self, height: Height) {
+        self.headers.split_off(&height);
+        self.blocks.split_off(&height);
+        self.requests.retain(|_, request| match request.kind {
+            RequestKind::Headers { .. } => false,
+            RequestKind::Bodies { ref heights } => heights.iter().all(|h| *h < height),
+            RequestKind::Snapshot { .. } => true,
+        });
+        for state in self.peers.values_mut() {
+            state.inflight = 0;
+        }
+        let peers: Vec<PeerId> = self.requests.values().map(|request| request.peer.clone()).collect();
+        for peer in peers {
+            if let Some(state) = self.peers.get_mut(&peer) {
+                state.inflight += 1;
+            }
+        }
+    }
+
+    /// misbehave reports the peer to the reputation of server, which bans and disconnects it
+    /// once its score is used up
+    fn misbehave(&mut self, peer: &PeerId, misbehavior: Misbehavior, reason: &str) {
+        warn!("Sync peer {} misbehaved, kind: {}, reason: {}", peer.to_base58(), misbehavior.as_str(), reason);
+        self.p2p_subscriber.do_send(P2PEvent::Misbehave(peer.clone(), misbehavior));
+    }
+
+    fn send(&mut self, peer: Option<&PeerId>, code: P2PMsgCode, payload: Vec<u8>) {
+        let header = RawHeader::new(
+            code,
+            1,
+            chrono::Local::now().timestamp_millis() as u64,
+            peer.map(|peer| peer.as_bytes().to_vec()),
+        );
+        self.issue_async(BroadcastEvent::Sync(RawMessage::new(header, payload)));
+    }
+}
+
+/// link_headers checks the headers are continuous and linked to the parent
+pub fn link_headers(parent_hash: &Hash, from: Height, headers: &[Header]) -> Result<(), String> {
+    let mut parent_hash = *parent_hash;
+    for (idx, header) in headers.iter().enumerate() {
+        if header.height != from + idx as u64 {
+            return Err(format!("discontinuous header, expect height: {}, got: {}", from + idx as u64, header.height));
+        }
+        if header.prev_hash != parent_hash {
+            return Err(format!("header is not linked to parent, height: {}", header.height));
+        }
+        parent_hash = header.block_hash();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptocurrency_kit::crypto::EMPTY_HASH;
+
+    fn new_headers(parent_hash: Hash, from: Height, n: u64) -> Vec<Header> {
+        let mut parent_hash = parent_hash;
+        (from..from + n).map(|height| {
+            let header = Header::new_mock(parent_hash, Address::from(10), EMPTY_HASH, height, height * 10, None);
+            parent_hash = header.block_hash();
+            header
+        }).collect()
+    }
+
+    #[test]
+    fn t_link_headers() {
+        let parent_hash = CryptoHash::hash(&vec![1_u8]);
+        let headers = new_headers(parent_hash, 10, 5);
+        assert!(link_headers(&parent_hash, 10, &headers).is_ok());
+        assert!(link_headers(&EMPTY_HASH, 10, &headers).is_err());
+        assert!(link_headers(&parent_hash, 11, &headers).is_err());
+
+        let mut gap = headers.clone();
+        gap.remove(2);
+        assert!(link_headers(&parent_hash, 10, &gap).is_err());
+    }
+
+    #[test]
+    fn t_decode() {
+        let status = Status { height: 10, hash: EMPTY_HASH };
+        let decoded: Status = decode(&status.clone().into_bytes()).unwrap();
+        assert_eq!(decoded.height, 10);
+        assert!(decode::<GetHeaders>(&[1, 2, 3]).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use ::actix::prelude::*;
+use cryptocurrency_kit::crypto::Hash;
+use libp2p::{Multiaddr, PeerId};
+
+use super::config::Config as P2PConfig;
+use super::reputation::Reputation;
+use super::secure::NodeKey;
+use super::server::{AuthorFn, HandleMsgFn, HandshakePacketFn, TcpServer, ValidatorsFn};
+use super::swarm::SwarmTransport;
+use crate::subscriber::{events::ChainEvent, P2PEvent};
+
+/// The implementation of the network transport
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    // the encrypted tcp sessions of `TcpServer`
+    Tcp,
+    // the libp2p swarm, the messages are published by floodsub over the multiplexed streams
+    Libp2p,
+}
+
+impl Default for TransportKind {
+    fn default() -> Self {
+        TransportKind::Tcp
+    }
+}
+
+impl TransportKind {
+    pub fn transport(&self) -> Box<Transport> {
+        match self {
+            TransportKind::Tcp => Box::new(TcpTransport),
+            TransportKind::Libp2p => Box::new(SwarmTransport),
+        }
+    }
+}
+
+/// Everything the transport needs to connect the peers and handle their messages
+pub struct TransportContext {
+    pub node_key: NodeKey,
+    pub mul_addr: Multiaddr,
+    pub genesis: Hash,
+    pub chain_id: u64,
+    pub config: P2PConfig,
+    pub reputation: Reputation,
+    pub handshake_fn: Box<HandshakePacketFn>,
+    pub author: Box<AuthorFn>,
+    pub handles: Box<HandleMsgFn>,
+    pub validators: Box<ValidatorsFn>,
+    pub persistent_peers: Vec<(PeerId, Multiaddr)>,
+}
+
+/// The recipients of a started transport, `BroadcastEvent` is received from the broker
+pub struct TransportHandle {
+    pub p2p_event: Recipient<P2PEvent>,
+    pub chain_event: Recipient<ChainEvent>,
+}
+
+/// Transport carries the `RawMessage` between the peers, the received messages are passed to
+/// `TransportContext::handles`
+pub trait Transport {
+    /// start spawns the transport actor on the current arbiter
+    fn start(&self, context: TransportContext) -> Result<TransportHandle, String>;
+}
+
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn start(&self, context: TransportContext) -> Result<TransportHandle, String> {
+        let server = TcpServer::new(
+            context.node_key,
+            context.mul_addr,
+            context.genesis,
+            context.chain_id,
+            context.config,
+            context.reputation,
+            context.handshake_fn,
+            context.author,
+            context.handles,
+            context.validators,
+            context.persistent_peers,
+        );
+        Ok(TransportHandle {
+            p2p_event: server.clone().recipient(),
+            chain_event: server.recipient(),
+        })
+    }
+}
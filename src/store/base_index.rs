@@ -5,14 +5,10 @@ use std::{borrow::Cow, marker::PhantomData};
 use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
 use cryptocurrency_kit::storage::keys::StorageKey;
 use cryptocurrency_kit::storage::values::StorageValue;
-use kvdb::{DBTransaction, DBValue};
-use kvdb_rocksdb::{Database, DatabaseIterator};
 use serde::{Deserialize, Serialize};
 use serde_json::to_string;
 
-use super::types::Iter;
-
-const COL: Option<u32> = None;
+use super::types::{Column, Fork, Iter, View};
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub enum IndexType {
@@ -54,7 +50,8 @@ pub struct BaseIndex {
     name: String,
     index_id: Option<Vec<u8>>,
     index_type: IndexType,
-    view: Arc<Database>,
+    column: Column,
+    view: View,
 }
 
 pub struct BaseIndexIter<'a, K, V> {
@@ -74,18 +71,21 @@ impl std::fmt::Debug for BaseIndex {
             .map_or("".to_string(), |v| String::from_utf8_lossy(v).to_string());
         write!(
             f,
-            "name:{}, index_id:{}, index_type:{:?}",
-            self.name, index_id, self.index_type
+            "name:{}, index_id:{}, index_type:{:?}, column:{:?}",
+            self.name, index_id, self.index_type, self.column
         )
     }
 }
 
 impl BaseIndex {
-    pub fn new<S: AsRef<str>>(index_name: S, index_type: IndexType, view: Arc<Database>) -> Self {
+    /// new puts the index into the column of its name if the store has one
+    pub fn new<S: AsRef<str>, T: Into<View>>(index_name: S, index_type: IndexType, view: T) -> Self {
+        let view = view.into();
         Self {
             name: index_name.as_ref().to_string(),
             index_id: None,
             index_type,
+            column: view.column(index_name.as_ref()),
             view,
         }
     }
@@ -106,27 +106,28 @@ impl BaseIndex {
         prefix_key
     }
 
-    pub fn snapshot(&self) -> &Database {
+    pub fn view(&self) -> &View {
         &self.view
     }
 
+    pub fn column(&self) -> Column {
+        self.column
+    }
+
     pub fn get<K, V>(&self, key: &K) -> Option<V>
         where
             K: StorageKey + ?Sized,
             V: StorageValue,
     {
         let key = self.prefix_key(key);
-        if let Some(value) = self.view.get(COL, &key).unwrap() {
-            return Some(StorageValue::from_bytes(Cow::from(value.as_ref())));
-        }
-        None
+        self.view.get(self.column, &key).map(|value| StorageValue::from_bytes(Cow::from(value)))
     }
 
     pub fn contains<K>(&self, key: &K) -> bool
         where
             K: StorageKey + ?Sized,
     {
-        self.view.get(COL, &self.prefix_key(key)).unwrap().is_some()
+        self.view.get(self.column, &self.prefix_key(key)).is_some()
     }
 
     pub fn iter<P, K, V>(&self, subprefix: &P) -> BaseIndexIter<K, V>
@@ -137,7 +138,7 @@ impl BaseIndex {
     {
         let iter_prefix = self.prefix_key(subprefix);
         BaseIndexIter {
-            base_iter: self.view.iter_from_prefix(COL, &iter_prefix).unwrap(),
+            base_iter: self.view.iter(self.column, &iter_prefix),
             base_prefix_len: self.name.len() + self.index_id.as_ref().map_or(0, |p| p.len()),
             index_id: iter_prefix,
             ended: false,
@@ -166,7 +167,7 @@ impl BaseIndex {
         //        writeln!(io::stdout(), "iter_prefix {:?}", iter_prefix).unwrap();
 
         BaseIndexIter {
-            base_iter: self.view.iter_from_prefix(COL, &iter_prefix).unwrap(),
+            base_iter: self.view.iter(self.column, &iter_prefix),
             base_prefix_len,
             index_id: Vec::from(&iter_prefix[..base_prefix_len]),
             ended: false,
@@ -176,29 +177,14 @@ impl BaseIndex {
     }
 
     /////////////////////////////
-    pub fn fork(&mut self) -> &Database {
-        &self.view
-    }
-
-    pub fn transaction(&self) -> DBTransaction {
-        self.view.transaction()
-    }
-
-    pub fn put_transaction(&self, tx: DBTransaction) {
-        self.view.write(tx).unwrap();
-        self.view.flush().unwrap();
-    }
-
+    /// the writes go to the database directly, or are buffered if the view is a fork
     pub fn put<K, V>(&mut self, key: &K, value: V)
         where
             K: StorageKey,
             V: StorageValue,
     {
         let key = self.prefix_key(key);
-        let mut tx = self.view.transaction();
-        tx.put_vec(COL, &key, value.into_bytes());
-        self.view.write(tx).unwrap();
-        self.view.flush().unwrap();
+        self.view.put(self.column, key, value.into_bytes());
     }
 
     pub fn remove<K>(&mut self, key: &K)
@@ -206,22 +192,22 @@ impl BaseIndex {
             K: StorageKey + ?Sized,
     {
         let key = self.prefix_key(key);
-        let mut tx = self.view.transaction();
-        tx.delete(COL, &key);
-        self.view.write(tx).unwrap();
-        self.view.flush().unwrap();
+        self.view.delete(self.column, key);
     }
 
     pub fn clear(&mut self) {
         let prefix = self.prefix_key("");
-        if let Some(iter) = self.view.iter_from_prefix(COL, &prefix) {
-            let mut tx = self.view.transaction();
-            iter.for_each(|item| {
-                tx.delete(COL, &item.0);
-            });
-            self.view.write(tx).unwrap();
-            self.view.flush().unwrap();
+        let keys: Vec<Box<[u8]>> = self.view
+            .iter(self.column, &prefix)
+            .map(|item| item.0)
+            .take_while(|key| key.starts_with(&prefix))
+            .collect();
+        // delete them by one write
+        let fork = Fork::new(self.view.clone());
+        for key in keys {
+            fork.delete(self.column, key.to_vec());
         }
+        fork.merge().unwrap();
     }
 }
 
@@ -277,6 +263,8 @@ pub(crate) fn print_bytes(base_prefix_len: usize, idx: &[u8], key: &[u8], value:
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::types::Store;
+    use kvdb_rocksdb::Database;
     use cryptocurrency_kit::types::Zero;
     use std::io::{self, Write};
 
@@ -286,7 +274,7 @@ mod tests {
 
     #[test]
     fn t() {
-        let db = Arc::new(Database::open_default(&random_dir()).unwrap());
+        let db = Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap())));
         {
             let _index = BaseIndex::new("transaction", IndexType::Map, db.clone());
             let mut index = BaseIndex::new("transaction", IndexType::Map, db.clone());
@@ -305,7 +293,7 @@ mod tests {
 
     #[test]
     fn t_iter_from() {
-        let db = Arc::new(Database::open_default(&random_dir()).unwrap());
+        let db = Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap())));
         let mut index = BaseIndex::new("transaction", IndexType::List, db.clone());
         let prefix = "block_".to_string();
         (0..100).for_each(|idx| {
@@ -8,6 +8,7 @@ use cryptocurrency_kit::types::Zero;
 use kvdb_rocksdb::Database;
 
 use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
+use super::types::View;
 
 #[derive(Debug)]
 pub struct Entry<V> {
@@ -19,7 +20,7 @@ impl<V> Entry<V>
 where
     V: StorageValue,
 {
-    pub fn new<S: AsRef<str>>(index_name: S, view: Arc<Database>) -> Self {
+    pub fn new<S: AsRef<str>, T: Into<View>>(index_name: S, view: T) -> Self {
         Entry {
             base: BaseIndex::new(index_name, IndexType::Entry, view),
             _v: PhantomData,
@@ -69,6 +70,7 @@ mod tests {
     use std::io::{self, Write};
 
     use super::*;
+    use crate::store::types::Store;
     use crate::common::random_dir;
     use cryptocurrency_kit::crypto::EMPTY_HASH;
 
@@ -76,7 +78,7 @@ mod tests {
     fn entry() {
         let mut entry: Entry<i32> = Entry::new(
             "IDX_NAME",
-            Arc::new(Database::open_default(&random_dir()).unwrap()),
+            Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap()))),
         );
 
         {
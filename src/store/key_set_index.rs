@@ -0,0 +1,111 @@
+use std::{borrow::Borrow, marker::PhantomData};
+
+use cryptocurrency_kit::storage::keys::StorageKey;
+use cryptocurrency_kit::types::Zero;
+
+use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
+use super::types::View;
+
+/// data format
+/// key: IDX_NAME + key, value: Zero
+pub struct KeySetIndex<K> {
+    base: BaseIndex,
+    _k: PhantomData<K>,
+}
+
+pub struct KeySetIndexIter<'a, K> {
+    base_iter: BaseIndexIter<'a, K, Zero>,
+}
+
+impl<K> KeySetIndex<K>
+where
+    K: StorageKey,
+{
+    pub fn new<S: AsRef<str>, T: Into<View>>(index_name: S, view: T) -> Self {
+        KeySetIndex {
+            base: BaseIndex::new(index_name, IndexType::KeySet, view),
+            _k: PhantomData,
+        }
+    }
+
+    pub fn contains<Q>(&self, item: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.base.contains(item)
+    }
+
+    pub fn iter(&self) -> KeySetIndexIter<K> {
+        KeySetIndexIter {
+            base_iter: self.base.iter(&()),
+        }
+    }
+
+    pub fn iter_from<Q>(&self, from: &Q) -> KeySetIndexIter<K>
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        KeySetIndexIter {
+            base_iter: self.base.iter_from(&(), from),
+        }
+    }
+
+    //////////
+    pub fn insert(&mut self, item: K) {
+        self.base.put(&item, Zero)
+    }
+
+    pub fn remove<Q>(&mut self, item: &Q)
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.base.remove(item)
+    }
+
+    pub fn clear(&mut self) {
+        self.base.clear()
+    }
+}
+
+impl<'a, K> Iterator for KeySetIndexIter<'a, K>
+where
+    K: StorageKey,
+{
+    type Item = K::Owned;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.base_iter.next().map(|(k, ..)| k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::types::Store;
+    use std::sync::Arc;
+    use kvdb_rocksdb::Database;
+    use crate::common::random_dir;
+
+    #[test]
+    fn key_set() {
+        let db = Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap())));
+        let mut index: KeySetIndex<u64> = KeySetIndex::new("IDX_NAME", db);
+        assert!(!index.contains(&1));
+        assert_eq!(index.iter().count(), 0);
+
+        (0..10).for_each(|idx| index.insert(idx));
+        assert!(index.contains(&1));
+        assert_eq!(index.iter().collect::<Vec<_>>(), (0..10).collect::<Vec<u64>>());
+        assert_eq!(index.iter_from(&7).collect::<Vec<_>>(), vec![7, 8, 9]);
+
+        index.remove(&1);
+        assert!(!index.contains(&1));
+        assert_eq!(index.iter().count(), 9);
+
+        index.clear();
+        assert_eq!(index.iter().count(), 0);
+    }
+}
@@ -7,6 +7,7 @@ use cryptocurrency_kit::types::Zero;
 use kvdb_rocksdb::{Database, DatabaseIterator};
 
 use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
+use super::types::View;
 
 /// data format
 /// |length|l-0, l-1, l-2, l-3|
@@ -32,7 +33,7 @@ impl<V> ListIndex<V>
 where
     V: StorageValue,
 {
-    pub fn new<S: AsRef<str>>(index_name: S, view: Arc<Database>) -> Self {
+    pub fn new<S: AsRef<str>, T: Into<View>>(index_name: S, view: T) -> Self {
         Self {
             base: BaseIndex::new(index_name, IndexType::List, view),
             length: Cell::new(None),
@@ -169,6 +170,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::types::Store;
     use std::io::{self, Write};
 
     fn list_index_methods(list_index: &mut ListIndex<i32>) {
@@ -231,9 +233,9 @@ mod tests {
             Vec::<u8>::new()
         );
     }
-    fn newdb() -> Database {
+    fn newdb() -> Arc<Store> {
         use crate::common::random_dir;
-        Database::open_default(&random_dir()).unwrap()
+        Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap())))
     }
     mod rocksdb_tests {
         use super::*;
@@ -241,14 +243,14 @@ mod tests {
 
         #[test]
         fn test_list_index_methods() {
-            let db = Arc::new(newdb());
+            let db = newdb();
             let mut list_index = ListIndex::new(IDX_NAME, db.clone());
             super::list_index_methods(&mut list_index);
         }
 
         #[test]
         fn test_list_index_iter(){
-            let db = Arc::new(newdb());
+            let db = newdb();
             let mut list_index = ListIndex::new(IDX_NAME, db.clone());
             super::list_index_iter(&mut list_index);
         }
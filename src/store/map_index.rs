@@ -6,6 +6,7 @@ use cryptocurrency_kit::types::Zero;
 use kvdb_rocksdb::Database;
 
 use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
+use super::types::View;
 
 //#[derive(Debug)]
 pub struct MapIndex<K, V> {
@@ -44,7 +45,7 @@ where
     K: StorageKey,
     V: StorageValue,
 {
-    pub fn new<S: AsRef<str>>(index_name: S, view: Arc<Database>) -> Self {
+    pub fn new<S: AsRef<str>, T: Into<View>>(index_name: S, view: T) -> Self {
         Self {
             base: BaseIndex::new(index_name, IndexType::Map, view),
             _k: PhantomData,
@@ -145,18 +146,19 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::types::Store;
     use crate::common::random_dir;
     use std::io::{self, Write};
 
     const IDX_NAME: &'static str = "idx_name_";
 
-    fn newdb() -> Database {
-        Database::open_default(&random_dir()).unwrap()
+    fn newdb() -> Arc<Store> {
+        Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap())))
     }
 
     #[test]
     fn str_key() {
-        let db = Arc::new(newdb());
+        let db = newdb();
         const KEY: &str = "key_1";
         let mut index: MapIndex<String, _> = MapIndex::new(IDX_NAME, db);
         assert_eq!(false, index.contains(KEY));
@@ -166,7 +168,7 @@ mod tests {
 
     #[test]
     fn key_iter() {
-        let db = Arc::new(newdb());
+        let db = newdb();
         let mut index: MapIndex<String, String> = MapIndex::new(IDX_NAME, db.clone());
         let keys = index.keys();
         assert_eq!(keys.count(), 0);
@@ -185,7 +187,7 @@ mod tests {
 
     #[test]
     fn value_iter() {
-        let db = Arc::new(newdb());
+        let db = newdb();
         let mut index: MapIndex<String, String> = MapIndex::new(IDX_NAME, db.clone());
         assert_eq!(index.values().count(), 0);
 
@@ -202,7 +204,7 @@ mod tests {
 
     #[test]
     fn map_iter() {
-        let db = Arc::new(newdb());
+        let db = newdb();
 
         {
             let mut index: MapIndex<String, i32> = MapIndex::new(IDX_NAME, db.clone());
@@ -2,6 +2,18 @@ mod base_index;
 mod entry;
 mod list_index;
 mod map_index;
+mod key_set_index;
+mod value_set_index;
+mod sparse_list_index;
+mod proof_map_index;
 mod iter;
 pub mod schema;
-mod types;
\ No newline at end of file
+pub mod types;
+
+pub use self::entry::Entry;
+pub use self::list_index::ListIndex;
+pub use self::map_index::MapIndex;
+pub use self::key_set_index::KeySetIndex;
+pub use self::value_set_index::ValueSetIndex;
+pub use self::sparse_list_index::SparseListIndex;
+pub use self::proof_map_index::{MapProof, ProofMapIndex};
@@ -0,0 +1,319 @@
+use std::{borrow::Borrow, marker::PhantomData};
+
+use cryptocurrency_kit::crypto::{hash, Hash};
+use cryptocurrency_kit::storage::{keys::StorageKey, values::StorageValue};
+
+use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
+use super::types::View;
+
+const VALUE_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+const LEAF_HASH_PREFIX: u8 = 0x00;
+const NODE_HASH_PREFIX: u8 = 0x01;
+
+/// ProofMapIndex is a map with a sparse merkle tree over it.
+///
+/// The tree has a leaf for every possible `hash(key)`, the path from the root goes left on bit 0
+/// and right on bit 1 (from the most significant bit). The empty subtree hashes to zero, so only
+/// the nodes of the present keys are stored.
+/// leaf = hash(0x00 || hash(key) || hash(value)), node = hash(0x01 || left || right)
+///
+/// data format
+/// value: IDX_NAME + 0 + key
+/// node: IDX_NAME + 1 + depth + path (the bits below the depth are cleared)
+pub struct ProofMapIndex<K, V> {
+    base: BaseIndex,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+pub struct ProofMapIndexIter<'a, K, V> {
+    base_iter: BaseIndexIter<'a, K, V>,
+}
+
+/// MapProof proves the value of a key (or the absence of the key) against the root.
+/// The siblings are ordered from the root to the leaf, the zero siblings are skipped and marked
+/// by the unset bits of the bitmap.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapProof {
+    pub bitmap: Vec<u8>,
+    pub siblings: Vec<Hash>,
+}
+
+impl<K, V> ProofMapIndex<K, V>
+where
+    K: StorageKey,
+    V: StorageValue,
+{
+    pub fn new<S: AsRef<str>, T: Into<View>>(index_name: S, view: T) -> Self {
+        ProofMapIndex {
+            base: BaseIndex::new(index_name, IndexType::ProofMap, view),
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.base.get(&value_key(key))
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        self.base.contains(&value_key(key))
+    }
+
+    pub fn iter(&self) -> ProofMapIndexIter<K, V> {
+        ProofMapIndexIter {
+            base_iter: self.base.iter_from(&VALUE_PREFIX, &()),
+        }
+    }
+
+    /// root_hash is zero if the map is empty
+    pub fn root_hash(&self) -> Hash {
+        self.node(0, &Hash::zero())
+    }
+
+    /// get_proof returns the proof of the key, it proves the absence if the key is not found
+    pub fn get_proof<Q>(&self, key: &Q) -> MapProof
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        let key_hash = hash(&key_bytes(key));
+        let bits = bit_len(&key_hash);
+        let mut proof = MapProof {
+            bitmap: vec![0; bits / 8],
+            siblings: vec![],
+        };
+        for depth in 1..=bits {
+            let sibling = self.node(depth, &flip_bit(&path(&key_hash, depth), depth - 1));
+            if sibling != Hash::zero() {
+                proof.bitmap[(depth - 1) / 8] |= 0x80 >> ((depth - 1) % 8);
+                proof.siblings.push(sibling);
+            }
+        }
+        proof
+    }
+
+    fn node(&self, depth: usize, path: &Hash) -> Hash {
+        self.base.get(&node_key(depth, path)).unwrap_or_else(Hash::zero)
+    }
+
+    fn set_node(&mut self, depth: usize, path: &Hash, node: Hash) {
+        let key = node_key(depth, path);
+        if node == Hash::zero() {
+            self.base.remove(&key);
+        } else {
+            self.base.put(&key, node);
+        }
+    }
+
+    /// update sets the leaf and the nodes from the leaf to the root
+    fn update(&mut self, key_hash: &Hash, leaf: Hash) {
+        let bits = bit_len(key_hash);
+        let mut node = leaf;
+        for depth in (1..=bits).rev() {
+            let path = path(key_hash, depth);
+            self.set_node(depth, &path, node);
+            let sibling = self.node(depth, &flip_bit(&path, depth - 1));
+            node = if bit(key_hash, depth - 1) {
+                node_hash(&sibling, &node)
+            } else {
+                node_hash(&node, &sibling)
+            };
+        }
+        self.set_node(0, &Hash::zero(), node);
+    }
+
+    //////////
+    pub fn put(&mut self, key: &K, value: V) {
+        let bytes = value.into_bytes();
+        let key_hash = hash(&key_bytes(key));
+        self.update(&key_hash, leaf_hash(&key_hash, &hash(&bytes)));
+        self.base.put(&value_key(key), bytes);
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: StorageKey + ?Sized,
+    {
+        if !self.contains(key) {
+            return;
+        }
+        self.update(&hash(&key_bytes(key)), Hash::zero());
+        self.base.remove(&value_key(key));
+    }
+
+    pub fn clear(&mut self) {
+        self.base.clear()
+    }
+}
+
+impl<'a, K, V> Iterator for ProofMapIndexIter<'a, K, V>
+where
+    K: StorageKey,
+    V: StorageValue,
+{
+    type Item = (K::Owned, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.base_iter.next()
+    }
+}
+
+impl MapProof {
+    /// verify checks the value of the key against the root, none value checks the absence of the key
+    pub fn verify<K: StorageKey + ?Sized>(&self, key: &K, value: Option<&[u8]>, root: &Hash) -> bool {
+        let key_hash = hash(&key_bytes(key));
+        let bits = bit_len(&key_hash);
+        let present = (0..bits).filter(|idx| self.bitmap.get(idx / 8).map_or(false, |byte| byte & (0x80 >> (idx % 8)) != 0)).count();
+        if self.bitmap.len() != bits / 8 || present != self.siblings.len() {
+            return false;
+        }
+        let mut node = value.map_or_else(Hash::zero, |value| leaf_hash(&key_hash, &hash(value)));
+        let mut siblings = self.siblings.iter().rev();
+        for depth in (1..=bits).rev() {
+            let sibling = if self.bitmap[(depth - 1) / 8] & (0x80 >> ((depth - 1) % 8)) != 0 {
+                *siblings.next().unwrap()
+            } else {
+                Hash::zero()
+            };
+            node = if bit(&key_hash, depth - 1) {
+                node_hash(&sibling, &node)
+            } else {
+                node_hash(&node, &sibling)
+            };
+        }
+        node == *root
+    }
+}
+
+fn key_bytes<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut buf = vec![0; key.size()];
+    key.write(&mut buf);
+    buf
+}
+
+fn value_key<K: StorageKey + ?Sized>(key: &K) -> Vec<u8> {
+    let mut buf = vec![VALUE_PREFIX];
+    buf.extend_from_slice(&key_bytes(key));
+    buf
+}
+
+fn node_key(depth: usize, path: &Hash) -> Vec<u8> {
+    let mut buf = vec![NODE_PREFIX];
+    buf.extend_from_slice(&(depth as u16).to_be_bytes());
+    buf.extend_from_slice(path.as_ref());
+    buf
+}
+
+fn bit_len(key_hash: &Hash) -> usize {
+    key_hash.as_ref().len() * 8
+}
+
+fn bit(key_hash: &Hash, idx: usize) -> bool {
+    key_hash.as_ref()[idx / 8] & (0x80 >> (idx % 8)) != 0
+}
+
+/// path keeps the first `depth` bits of the hash
+fn path(key_hash: &Hash, depth: usize) -> Hash {
+    let mut bytes = key_hash.as_ref().to_vec();
+    for (idx, byte) in bytes.iter_mut().enumerate() {
+        if idx * 8 >= depth {
+            *byte = 0;
+        } else if idx * 8 + 8 > depth {
+            *byte &= 0xff << (idx * 8 + 8 - depth);
+        }
+    }
+    Hash::from_slice(&bytes).unwrap()
+}
+
+fn flip_bit(path: &Hash, idx: usize) -> Hash {
+    let mut bytes = path.as_ref().to_vec();
+    bytes[idx / 8] ^= 0x80 >> (idx % 8);
+    Hash::from_slice(&bytes).unwrap()
+}
+
+fn leaf_hash(key_hash: &Hash, value_hash: &Hash) -> Hash {
+    let mut buffer = vec![LEAF_HASH_PREFIX];
+    buffer.extend_from_slice(key_hash.as_ref());
+    buffer.extend_from_slice(value_hash.as_ref());
+    hash(&buffer)
+}
+
+/// the empty subtree stays zero
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    if *left == Hash::zero() && *right == Hash::zero() {
+        return Hash::zero();
+    }
+    let mut buffer = vec![NODE_HASH_PREFIX];
+    buffer.extend_from_slice(left.as_ref());
+    buffer.extend_from_slice(right.as_ref());
+    hash(&buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::types::Store;
+    use std::sync::Arc;
+    use kvdb_rocksdb::Database;
+    use crate::common::random_dir;
+
+    fn new_index(db: &Arc<Store>, name: &str) -> ProofMapIndex<u64, u64> {
+        ProofMapIndex::new(name, db.clone())
+    }
+
+    #[test]
+    fn proof_map_root() {
+        let db = Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap())));
+        let mut index = new_index(&db, "IDX_NAME_1");
+        assert_eq!(index.root_hash(), Hash::zero());
+
+        (0..10).for_each(|idx| index.put(&idx, idx * 10));
+        let root = index.root_hash();
+        assert_ne!(root, Hash::zero());
+        assert_eq!(index.get(&3), Some(30));
+        assert_eq!(index.iter().count(), 10);
+
+        // the root is independent of the insertion order
+        let mut other = new_index(&db, "IDX_NAME_2");
+        (0..10).rev().for_each(|idx| other.put(&idx, idx * 10));
+        assert_eq!(other.root_hash(), root);
+
+        other.put(&3, 31);
+        assert_ne!(other.root_hash(), root);
+        other.put(&3, 30);
+        assert_eq!(other.root_hash(), root);
+
+        (0..10).for_each(|idx| other.remove(&idx));
+        assert_eq!(other.root_hash(), Hash::zero());
+    }
+
+    #[test]
+    fn proof_map_proof() {
+        let db = Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap())));
+        let mut index = new_index(&db, "IDX_NAME");
+        (0..10).for_each(|idx| index.put(&idx, idx * 10));
+        let root = index.root_hash();
+
+        let proof = index.get_proof(&3);
+        assert!(proof.verify(&3_u64, Some(&30_u64.into_bytes()[..]), &root));
+        assert!(!proof.verify(&3_u64, Some(&31_u64.into_bytes()[..]), &root));
+        assert!(!proof.verify(&3_u64, None, &root));
+        assert!(!proof.verify(&4_u64, Some(&30_u64.into_bytes()[..]), &root));
+
+        // absence
+        let proof = index.get_proof(&100);
+        assert!(proof.verify(&100_u64, None, &root));
+        assert!(!proof.verify(&100_u64, Some(&0_u64.into_bytes()[..]), &root));
+    }
+}
@@ -1,14 +1,19 @@
 use std::sync::Arc;
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::path::Path;
 
 use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
 use cryptocurrency_kit::storage::values::StorageValue;
 use cryptocurrency_kit::ethkey::Address;
-use kvdb_rocksdb::Database;
+use kvdb_rocksdb::{CompactionProfile, Database, DatabaseConfig};
 
 use super::entry::Entry;
 use super::list_index::ListIndex;
 use super::map_index::MapIndex;
+use super::types::{Fork, Store, View};
 use crate::{
+    config::StoreConfig,
     types::block::{Block, Header},
     types::{Validator, ValidatorArray, HashesEntry, Bloom, Height, transaction::Transaction},
 };
@@ -34,40 +39,129 @@ define_name!(
     CONFIGS => "configs";
     CONSENSUS_MESSAGE_CACHE => "consensus_message_cache";
     VALIDATORS => "validators";
+    TRANSACTION_LOCATIONS => "transaction_locations";
+    VALIDATOR_TRANSITIONS => "validator_transitions";
+    PRUNED_HEIGHTS => "pruned_heights";
 );
 
-struct TxLocation {
-    block_height: Height,
-    position_in_block: u64,
+/// The index of `COLUMNS[i]` is in the column family `i`
+pub const COLUMNS: &[&str] = &[
+    TRANSACTIONS,
+    TRANSACTIONS_HASH,
+    BLOCKS,
+    HEADERS,
+    BLOCK_HASHES_BY_HEIGHT,
+    BLOCK_TRANSACTIONS,
+    PRECOMMITS,
+    CONFIGS,
+    CONSENSUS_MESSAGE_CACHE,
+    VALIDATORS,
+    TRANSACTION_LOCATIONS,
+    VALIDATOR_TRANSITIONS,
+    PRUNED_HEIGHTS,
+];
+
+/// open_database opens the database with a column family for every index of schema,
+/// the store of the old layout (all of the indexes in the default column) is refused
+pub fn open_database(path: &str, config: &StoreConfig) -> Result<Database, String> {
+    let mut db_config = DatabaseConfig::with_columns(Some(COLUMNS.len() as u32));
+    db_config.max_open_files = config.max_open_files;
+    db_config.compaction = match config.compaction.as_str() {
+        "auto" => CompactionProfile::auto(Path::new(path)),
+        "ssd" => CompactionProfile::ssd(),
+        "hdd" => CompactionProfile::hdd(),
+        other => return Err(format!("unknown compaction profile: {}", other)),
+    };
+    for idx in 0..COLUMNS.len() {
+        let col = Some(idx as u32);
+        db_config.memory_budget.insert(col, config.memory_budget);
+    }
+    for (name, budget) in &config.columns {
+        let idx = COLUMNS
+            .iter()
+            .position(|column| column.trim_start_matches("core.") == name)
+            .ok_or_else(|| format!("unknown store column: {}", name))?;
+        db_config.memory_budget.insert(Some(idx as u32), *budget);
+    }
+    let db = Database::open(&db_config, path).map_err(|err| err.to_string())?;
+    if db.iter_from_prefix(None, &[]).map_or(false, |mut iter| iter.next().is_some()) {
+        return Err(format!(
+            "store {} is in the layout without column families, migrate it or sync into an empty store",
+            path
+        ));
+    }
+    Ok(db)
+}
+
+/// The location of a committed transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxLocation {
+    pub block_hash: Hash,
+    pub block_height: Height,
+    pub position_in_block: u64,
+}
+
+implement_cryptohash_traits! {TxLocation}
+implement_storagevalue_traits! {TxLocation}
+
+impl TxLocation {
+    pub fn new(block_hash: Hash, block_height: Height, position_in_block: u64) -> Self {
+        TxLocation {
+            block_hash,
+            block_height,
+            position_in_block,
+        }
+    }
 }
 
 pub struct Schema {
-    db: Arc<Database>,
+    view: View,
 }
 
 impl Schema {
+    /// new puts the indexes into their columns, the database must be opened by `open_database`
     pub fn new(db: Arc<Database>) -> Self {
-        Schema { db }
+        Schema { view: View::from(Arc::new(Store::with_columns(db, COLUMNS))) }
+    }
+
+    pub fn from_view(view: View) -> Self {
+        Schema { view }
+    }
+
+    /// fork returns a fork over the schema, the writes through `Schema::from_view(fork.into())`
+    /// are invisible to the schema until the fork is merged
+    pub fn fork(&self) -> Arc<Fork> {
+        Arc::new(Fork::new(self.view.clone()))
+    }
+
+    /// snapshot returns the read-only schema of current state, the later commits are invisible to it.
+    /// Take a fork over it for the speculative execution, the fork can't be merged.
+    pub fn snapshot(&self) -> Schema {
+        Schema { view: self.view.snapshot() }
     }
 
     pub fn transaction(&self) -> MapIndex<Hash, Transaction> {
-        MapIndex::new(TRANSACTIONS, self.db.clone())
+        MapIndex::new(TRANSACTIONS, self.view.clone())
+    }
+
+    pub fn transaction_locations(&self) -> MapIndex<Hash, TxLocation> {
+        MapIndex::new(TRANSACTION_LOCATIONS, self.view.clone())
     }
 
     pub fn transaction_hashes(&self) -> MapIndex<Hash, HashesEntry> {
-        MapIndex::new(TRANSACTIONS_HASH, self.db.clone())
+        MapIndex::new(TRANSACTIONS_HASH, self.view.clone())
     }
 
     pub fn blocks(&self) -> MapIndex<Hash, Block> {
-        MapIndex::new(BLOCKS, self.db.clone())
+        MapIndex::new(BLOCKS, self.view.clone())
     }
 
     pub fn headers(&self) -> MapIndex<Hash, Header> {
-        MapIndex::new(HEADERS, self.db.clone())
+        MapIndex::new(HEADERS, self.view.clone())
     }
 
     pub fn block_hashes_by_height(&self) -> ListIndex<Hash> {
-        ListIndex::new(BLOCK_HASHES_BY_HEIGHT, self.db.clone())
+        ListIndex::new(BLOCK_HASHES_BY_HEIGHT, self.view.clone())
     }
 
     pub fn block_hash_by_height(&self, height: Height) -> Option<Hash> {
@@ -83,7 +177,17 @@ impl Schema {
     }
 
     pub fn validators(&self) -> Entry<ValidatorArray> {
-        Entry::new(VALIDATORS, self.db.clone())
+        Entry::new(VALIDATORS, self.view.clone())
+    }
+
+    /// validator_transitions maps the height to the validator set which takes effect from it
+    pub fn validator_transitions(&self) -> MapIndex<Height, ValidatorArray> {
+        MapIndex::new(VALIDATOR_TRANSITIONS, self.view.clone())
+    }
+
+    /// pruned_heights maps the first height skipped by a checkpoint import to the checkpoint height
+    pub fn pruned_heights(&self) -> MapIndex<Height, Height> {
+        MapIndex::new(PRUNED_HEIGHTS, self.view.clone())
     }
 
     /// Returns the height of the last committed block.
@@ -118,7 +222,7 @@ mod tests {
 
     #[test]
     fn tschema() {
-        let db = Arc::new(Database::open_default(&random_dir()).unwrap());
+        let db = Arc::new(open_database(&random_dir(), &StoreConfig::default()).unwrap());
         let schema = Schema::new(db.clone());
 
         /// block_hashes_by_height
@@ -162,4 +266,38 @@ mod tests {
             writeln!(io::stdout(), "{:#?}", zero_tx1.unwrap()).unwrap();
         }
     }
+
+    #[test]
+    fn t_open_old_layout() {
+        let path = random_dir();
+        {
+            let db = Database::open_default(&path).unwrap();
+            let mut tx = db.transaction();
+            tx.put(None, b"core.headers", b"1");
+            db.write(tx).unwrap();
+        }
+        assert!(open_database(&path, &StoreConfig::default()).is_err());
+        assert!(open_database(&random_dir(), &StoreConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn t_schema_snapshot() {
+        let db = Arc::new(open_database(&random_dir(), &StoreConfig::default()).unwrap());
+        let schema = Schema::new(db.clone());
+        let mut hashes = schema.block_hashes_by_height();
+        (0..3).for_each(|idx: u64| hashes.push(idx.hash()));
+
+        let snapshot = schema.snapshot();
+        (3..5).for_each(|idx: u64| hashes.push(idx.hash()));
+        assert_eq!(schema.height(), 4);
+        assert_eq!(snapshot.height(), 2);
+        assert_eq!(snapshot.block_hash_by_height(2), Some(2_u64.hash()));
+        assert_eq!(snapshot.block_hash_by_height(3), None);
+
+        // speculative writes over the snapshot are dropped
+        let fork = snapshot.fork();
+        Schema::from_view(fork.clone().into()).block_hashes_by_height().push(100_u64.hash());
+        assert!(fork.merge().is_err());
+        assert_eq!(schema.height(), 4);
+    }
 }
@@ -0,0 +1,219 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::io::Cursor;
+use std::marker::PhantomData;
+
+use cryptocurrency_kit::crypto::{hash, CryptoHash, Hash};
+use cryptocurrency_kit::storage::values::StorageValue;
+use cryptocurrency_kit::types::Zero;
+
+use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
+use super::types::View;
+
+/// data format
+/// |size|l-0, l-2, l-5|
+/// size: IDX_NAME, the capacity (the max index + 1) and the count of the items
+/// item: IDX_NAMEX, the removed items leave holes in the list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SparseListSize {
+    capacity: u64,
+    length: u64,
+}
+
+implement_cryptohash_traits! {SparseListSize}
+implement_storagevalue_traits! {SparseListSize}
+
+pub struct SparseListIndex<V> {
+    base: BaseIndex,
+    size: Cell<Option<SparseListSize>>,
+    _v: PhantomData<V>,
+}
+
+pub struct SparseListIndexIter<'a, V> {
+    base_iter: BaseIndexIter<'a, u64, V>,
+}
+
+impl<V> SparseListIndex<V>
+where
+    V: StorageValue,
+{
+    pub fn new<S: AsRef<str>, T: Into<View>>(index_name: S, view: T) -> Self {
+        SparseListIndex {
+            base: BaseIndex::new(index_name, IndexType::SparseList, view),
+            size: Cell::new(None),
+            _v: PhantomData,
+        }
+    }
+
+    fn size(&self) -> SparseListSize {
+        if let Some(size) = self.size.get() {
+            return size;
+        }
+        let size = self.base.get(&Zero).unwrap_or_default();
+        self.size.set(Some(size));
+        size
+    }
+
+    fn set_size(&mut self, size: SparseListSize) {
+        self.base.put(&Zero, size);
+        self.size.set(Some(size));
+    }
+
+    pub fn get(&self, index: u64) -> Option<V> {
+        self.base.get(&index)
+    }
+
+    /// capacity is the max index + 1, including the holes
+    pub fn capacity(&self) -> u64 {
+        self.size().capacity
+    }
+
+    /// len is the count of the items, excluding the holes
+    pub fn len(&self) -> u64 {
+        self.size().length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// iter returns the pairs of (index, value), the holes are skipped
+    pub fn iter(&self) -> SparseListIndexIter<V> {
+        SparseListIndexIter {
+            base_iter: self.base.iter_from(&Zero, &0_u64),
+        }
+    }
+
+    pub fn iter_from(&self, from: u64) -> SparseListIndexIter<V> {
+        SparseListIndexIter {
+            base_iter: self.base.iter_from(&Zero, &from),
+        }
+    }
+
+    pub fn indices(&self) -> impl Iterator<Item = u64> + '_ {
+        self.iter().map(|(index, ..)| index)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = V> + '_ {
+        self.iter().map(|(.., value)| value)
+    }
+
+    /// mut
+    pub fn push(&mut self, value: V) {
+        let mut size = self.size();
+        self.base.put(&size.capacity, value);
+        size.capacity += 1;
+        size.length += 1;
+        self.set_size(size);
+    }
+
+    /// pop removes the last item, the holes at the tail are dropped as well
+    pub fn pop(&mut self) -> Option<V> {
+        let mut size = self.size();
+        while size.capacity > 0 {
+            size.capacity -= 1;
+            if let Some(value) = self.base.get(&size.capacity) {
+                self.base.remove(&size.capacity);
+                size.length -= 1;
+                self.set_size(size);
+                return Some(value);
+            }
+        }
+        self.set_size(size);
+        None
+    }
+
+    pub fn set(&mut self, index: u64, value: V) {
+        let mut size = self.size();
+        if index >= size.capacity {
+            panic!(
+                "index out of bound: \
+                 the capacity is {} but the index is {}",
+                size.capacity, index,
+            );
+        }
+        if !self.base.contains(&index) {
+            size.length += 1;
+            self.set_size(size);
+        }
+        self.base.put(&index, value);
+    }
+
+    /// remove leaves a hole at the index
+    pub fn remove(&mut self, index: u64) -> Option<V> {
+        let value = self.get(index);
+        if value.is_some() {
+            let mut size = self.size();
+            size.length -= 1;
+            self.base.remove(&index);
+            self.set_size(size);
+        }
+        value
+    }
+
+    pub fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = V>,
+    {
+        for value in iter {
+            self.push(value);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.size.set(Some(SparseListSize::default()));
+        self.base.clear();
+    }
+}
+
+impl<'a, V> Iterator for SparseListIndexIter<'a, V>
+where
+    V: StorageValue,
+{
+    type Item = (u64, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.base_iter.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::types::Store;
+    use std::sync::Arc;
+    use kvdb_rocksdb::Database;
+    use crate::common::random_dir;
+
+    #[test]
+    fn sparse_list() {
+        let db = Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap())));
+        let mut index: SparseListIndex<i32> = SparseListIndex::new("IDX_NAME", db.clone());
+        assert!(index.is_empty());
+        assert_eq!(index.pop(), None);
+
+        index.extend(vec![1, 2, 3, 4, 5]);
+        assert_eq!(index.remove(1), Some(2));
+        assert_eq!(index.remove(1), None);
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.capacity(), 5);
+        assert_eq!(index.indices().collect::<Vec<_>>(), vec![0, 2, 3, 4]);
+        assert_eq!(index.iter_from(3).collect::<Vec<_>>(), vec![(3, 4), (4, 5)]);
+
+        index.set(1, 22);
+        assert_eq!(index.len(), 5);
+        assert_eq!(index.get(1), Some(22));
+
+        // the size is persisted
+        let mut index: SparseListIndex<i32> = SparseListIndex::new("IDX_NAME", db);
+        index.remove(4);
+        index.remove(3);
+        assert_eq!(index.pop(), Some(3));
+        assert_eq!(index.capacity(), 2);
+        assert_eq!(index.values().collect::<Vec<_>>(), vec![1, 22]);
+
+        index.clear();
+        assert!(index.is_empty());
+        assert_eq!(index.iter().count(), 0);
+    }
+}
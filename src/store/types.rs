@@ -1,27 +1,467 @@
-use kvdb::DBTransaction;
-use kvdb_rocksdb::DatabaseIterator;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::iter::Peekable;
+use std::mem;
+use std::sync::{Arc, Weak};
+use std::vec::IntoIter;
 
-pub type Iter<'a> = DatabaseIterator<'a>;
+use kvdb_rocksdb::Database;
+use parking_lot::{Mutex, RwLock};
 
+use crate::metrics;
 
+/// The column family of RocksDB, none is the default column
+pub type Column = Option<u32>;
+
+pub type Iter<'a> = Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
+
+// (column, key) => value, none means the key is deleted (or absent)
+type Changes = BTreeMap<(Column, Vec<u8>), Option<Vec<u8>>>;
+
+/// Snapshot is a read-only view of the store
 pub trait Snapshot: 'static {
-    fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>>;
+    fn get(&self, col: Column, key: &[u8]) -> Option<Vec<u8>>;
+
+    fn contains(&self, col: Column, key: &[u8]) -> bool {
+        self.get(col, key).is_some()
+    }
+
+    /// iter returns the key-value pairs of the column from the key, ordered by key
+    fn iter<'a>(&'a self, col: Column, from: &[u8]) -> Iter<'a>;
+}
+
+impl Snapshot for Database {
+    fn get(&self, col: Column, key: &[u8]) -> Option<Vec<u8>> {
+        Database::get(self, col, key).unwrap().map(|value| value.to_vec())
+    }
+
+    fn iter<'a>(&'a self, col: Column, from: &[u8]) -> Iter<'a> {
+        match self.iter_from_prefix(col, from) {
+            Some(iter) => Box::new(iter),
+            None => Box::new(::std::iter::empty()),
+        }
+    }
+}
+
+/// Store is the database with its live snapshots.
+///
+/// kvdb_rocksdb doesn't expose the RocksDB snapshot, so the point-in-time view is kept by
+/// saving the original values of the overwritten keys into every live snapshot before writing.
+/// It costs nothing if there is no snapshot.
+///
+/// The indexes are put into the columns by name, the others are in the default column.
+pub struct Store {
+    db: Arc<Database>,
+    columns: HashMap<String, u32>,
+    snapshots: RwLock<Vec<Weak<Mutex<Changes>>>>,
+}
 
-    fn contains(&self, name: &str, key: &[u8]) -> bool {
-        self.get(name, key).is_some()
+impl Store {
+    pub fn new(db: Arc<Database>) -> Self {
+        Self::with_columns(db, &[])
     }
 
-    fn iter<'a> (&'a self, name: &str, from: &[u8]) -> Iter<'a>;
+    /// with_columns puts the index of `names[i]` into the column `i`,
+    /// the database must be opened with `names.len()` columns at least
+    pub fn with_columns(db: Arc<Database>, names: &[&str]) -> Self {
+        Store {
+            db,
+            columns: names.iter().enumerate().map(|(idx, name)| (name.to_string(), idx as u32)).collect(),
+            snapshots: RwLock::new(vec![]),
+        }
+    }
+
+    pub fn db(&self) -> &Arc<Database> {
+        &self.db
+    }
+
+    pub fn column(&self, index_name: &str) -> Column {
+        self.columns.get(index_name).cloned()
+    }
+
+    /// snapshot returns the read-only view of current state, the later writes are invisible to it
+    pub fn snapshot(store: &Arc<Store>) -> DbSnapshot {
+        let origins = Arc::new(Mutex::new(BTreeMap::new()));
+        store.snapshots.write().push(Arc::downgrade(&origins));
+        DbSnapshot {
+            store: store.clone(),
+            origins,
+        }
+    }
+
+    /// write commits the changes by one `DBTransaction`
+    pub fn write(&self, changes: Changes) -> Result<(), String> {
+        let _timer = metrics::STORE_WRITE_LATENCY.start_timer();
+        let mut snapshots = self.snapshots.write();
+        snapshots.retain(|origins| origins.upgrade().is_some());
+        for origins in snapshots.iter().filter_map(|origins| origins.upgrade()) {
+            let mut origins = origins.lock();
+            for (col, key) in changes.keys() {
+                if !origins.contains_key(&(*col, key.clone())) {
+                    origins.insert((*col, key.clone()), Snapshot::get(&*self.db, *col, key));
+                }
+            }
+        }
+        let mut tx = self.db.transaction();
+        for ((col, key), value) in changes {
+            match value {
+                Some(value) => tx.put_vec(col, &key, value),
+                None => tx.delete(col, &key),
+            }
+        }
+        self.db.write(tx).map_err(|err| err.to_string())?;
+        self.db.flush().map_err(|err| err.to_string())
+    }
+}
+
+/// DbSnapshot is the point-in-time view of the store
+pub struct DbSnapshot {
+    store: Arc<Store>,
+    // the original values of the keys written after the snapshot is taken
+    origins: Arc<Mutex<Changes>>,
 }
 
+impl Snapshot for DbSnapshot {
+    fn get(&self, col: Column, key: &[u8]) -> Option<Vec<u8>> {
+        // block the writers, the origin and the database must be read at the same time
+        let _guard = self.store.snapshots.read();
+        if let Some(value) = self.origins.lock().get(&(col, key.to_vec())) {
+            return value.clone();
+        }
+        Snapshot::get(&*self.store.db, col, key)
+    }
+
+    fn iter<'a>(&'a self, col: Column, from: &[u8]) -> Iter<'a> {
+        // the iterator of RocksDB is consistent since it is created, the origins are copied with it
+        let _guard = self.store.snapshots.read();
+        let base = Snapshot::iter(&*self.store.db, col, from);
+        Box::new(OverlayIter::new(base, range(&self.origins.lock(), col, from)))
+    }
+}
+
+/// View is where the indexes read and write.
+/// The writes of store view go to disk directly, the writes of fork view are buffered in the fork,
+/// the snapshot view is read-only.
+#[derive(Clone)]
+pub enum View {
+    Store(Arc<Store>),
+    Snapshot(Arc<DbSnapshot>),
+    Fork(Arc<Fork>),
+}
+
+impl From<Arc<Store>> for View {
+    fn from(store: Arc<Store>) -> Self {
+        View::Store(store)
+    }
+}
+
+impl From<Arc<Fork>> for View {
+    fn from(fork: Arc<Fork>) -> Self {
+        View::Fork(fork)
+    }
+}
+
+impl View {
+    /// column returns the column of the index
+    pub fn column(&self, index_name: &str) -> Column {
+        match self {
+            View::Store(store) => store.column(index_name),
+            View::Snapshot(snapshot) => snapshot.store.column(index_name),
+            View::Fork(fork) => fork.base.column(index_name),
+        }
+    }
+
+    pub fn get(&self, col: Column, key: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            View::Store(store) => Snapshot::get(&**store.db(), col, key),
+            View::Snapshot(snapshot) => Snapshot::get(&**snapshot, col, key),
+            View::Fork(fork) => Snapshot::get(&**fork, col, key),
+        }
+    }
+
+    pub fn iter<'a>(&'a self, col: Column, from: &[u8]) -> Iter<'a> {
+        match self {
+            View::Store(store) => Snapshot::iter(&**store.db(), col, from),
+            View::Snapshot(snapshot) => Snapshot::iter(&**snapshot, col, from),
+            View::Fork(fork) => Snapshot::iter(&**fork, col, from),
+        }
+    }
+
+    pub fn put(&self, col: Column, key: Vec<u8>, value: Vec<u8>) {
+        self.write(col, key, Some(value));
+    }
+
+    pub fn delete(&self, col: Column, key: Vec<u8>) {
+        self.write(col, key, None);
+    }
+
+    fn write(&self, col: Column, key: Vec<u8>, value: Option<Vec<u8>>) {
+        match self {
+            View::Store(store) => {
+                let mut changes = BTreeMap::new();
+                changes.insert((col, key), value);
+                store.write(changes).unwrap();
+            }
+            View::Snapshot(_) => panic!("An attempt to write the read-only snapshot"),
+            View::Fork(fork) => {
+                fork.changes.lock().insert((col, key), value);
+            }
+        }
+    }
+
+    /// snapshot returns the read-only point-in-time copy of the view,
+    /// the snapshot of a fork includes its current changes.
+    pub fn snapshot(&self) -> View {
+        match self {
+            View::Store(store) => View::Snapshot(Arc::new(Store::snapshot(store))),
+            View::Snapshot(_) => self.clone(),
+            View::Fork(fork) => {
+                let frozen = Fork::new(fork.base.snapshot());
+                *frozen.changes.lock() = fork.changes.lock().clone();
+                View::Fork(Arc::new(frozen))
+            }
+        }
+    }
+}
+
+/// Fork is a writeable view over the base view, the changes are kept in memory,
+/// the reads see them first. The changes are written into the base by `merge` at once,
+/// for the store it is a single `DBTransaction`, or they are dropped by `discard`.
+/// A fork over the snapshot can't be merged, it is used for the speculative execution.
 pub struct Fork {
-    snapshot: Box<dyn Snapshot>,
-    transaction: DBTransaction,
+    base: View,
+    changes: Mutex<Changes>,
 }
 
-//
-//impl Snapshot for Fork {
-//    fn get(&self, name: &str, key: &[u8]) -> Option<Vec<u8>> {
-//
-//    }
-//}
\ No newline at end of file
+impl Fork {
+    pub fn new(base: View) -> Self {
+        Fork {
+            base,
+            changes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn put(&self, col: Column, key: Vec<u8>, value: Vec<u8>) {
+        self.changes.lock().insert((col, key), Some(value));
+    }
+
+    pub fn delete(&self, col: Column, key: Vec<u8>) {
+        self.changes.lock().insert((col, key), None);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.lock().is_empty()
+    }
+
+    /// merge moves the changes into the base view
+    pub fn merge(&self) -> Result<(), String> {
+        match self.base {
+            View::Store(ref store) => {
+                let changes = mem::replace(&mut *self.changes.lock(), BTreeMap::new());
+                store.write(changes)
+            }
+            View::Snapshot(_) => Err("the fork over snapshot can't be merged".to_string()),
+            View::Fork(ref parent) => {
+                let changes = mem::replace(&mut *self.changes.lock(), BTreeMap::new());
+                parent.changes.lock().extend(changes);
+                Ok(())
+            }
+        }
+    }
+
+    /// discard drops the changes
+    pub fn discard(&self) {
+        self.changes.lock().clear();
+    }
+}
+
+impl Snapshot for Fork {
+    fn get(&self, col: Column, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(value) = self.changes.lock().get(&(col, key.to_vec())) {
+            return value.clone();
+        }
+        self.base.get(col, key)
+    }
+
+    fn iter<'a>(&'a self, col: Column, from: &[u8]) -> Iter<'a> {
+        let changes = range(&self.changes.lock(), col, from);
+        Box::new(OverlayIter::new(self.base.iter(col, from), changes))
+    }
+}
+
+/// range copies the changes of the column from the key
+fn range(changes: &Changes, col: Column, from: &[u8]) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+    changes
+        .range((col, from.to_vec())..)
+        .take_while(|((change_col, _), _)| *change_col == col)
+        .map(|((_, key), value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// OverlayIter merges the changes into the iterator of base, both of them are ordered by key
+struct OverlayIter<'a> {
+    base: Peekable<Iter<'a>>,
+    changes: Peekable<IntoIter<(Vec<u8>, Option<Vec<u8>>)>>,
+}
+
+impl<'a> OverlayIter<'a> {
+    fn new(base: Iter<'a>, changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Self {
+        OverlayIter {
+            base: base.peekable(),
+            changes: changes.into_iter().peekable(),
+        }
+    }
+}
+
+impl<'a> Iterator for OverlayIter<'a> {
+    type Item = (Box<[u8]>, Box<[u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.base.peek(), self.changes.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((key, _)), Some((change_key, _))) => (**key).cmp(&change_key[..]),
+            };
+            match order {
+                Ordering::Less => return self.base.next(),
+                // the change overrides the base
+                Ordering::Equal => {
+                    self.base.next();
+                }
+                Ordering::Greater => {}
+            }
+            if let Some((key, Some(value))) = self.changes.next() {
+                return Some((key.into_boxed_slice(), value.into_boxed_slice()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::random_dir;
+    use kvdb_rocksdb::DatabaseConfig;
+
+    const COL: Column = None;
+
+    fn collect(iter: Iter) -> Vec<(Vec<u8>, Vec<u8>)> {
+        iter.map(|(key, value)| (key.to_vec(), value.to_vec())).collect()
+    }
+
+    fn new_view() -> View {
+        View::from(Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap()))))
+    }
+
+    #[test]
+    fn t_fork_merge() {
+        let view = new_view();
+        view.put(COL, b"a1".to_vec(), b"1".to_vec());
+        view.put(COL, b"a3".to_vec(), b"3".to_vec());
+        view.put(COL, b"a5".to_vec(), b"5".to_vec());
+
+        let fork = Arc::new(Fork::new(view.clone()));
+        fork.put(COL, b"a2".to_vec(), b"2".to_vec());
+        fork.put(COL, b"a3".to_vec(), b"33".to_vec());
+        fork.delete(COL, b"a5".to_vec());
+        assert_eq!(Snapshot::get(&*fork, COL, b"a3"), Some(b"33".to_vec()));
+        assert_eq!(Snapshot::get(&*fork, COL, b"a5"), None);
+        // the base is not touched
+        assert_eq!(view.get(COL, b"a3"), Some(b"3".to_vec()));
+        assert_eq!(view.get(COL, b"a2"), None);
+
+        let expect = vec![
+            (b"a1".to_vec(), b"1".to_vec()),
+            (b"a2".to_vec(), b"2".to_vec()),
+            (b"a3".to_vec(), b"33".to_vec()),
+        ];
+        assert_eq!(collect(Snapshot::iter(&*fork, COL, b"a")), expect);
+
+        fork.merge().unwrap();
+        assert!(fork.is_empty());
+        assert_eq!(collect(view.iter(COL, b"a")), expect);
+    }
+
+    #[test]
+    fn t_fork_discard() {
+        let view = new_view();
+        view.put(COL, b"b1".to_vec(), b"1".to_vec());
+
+        let parent = Arc::new(Fork::new(view.clone()));
+        let child = Fork::new(View::from(parent.clone()));
+        child.put(COL, b"b2".to_vec(), b"2".to_vec());
+        child.merge().unwrap();
+        assert_eq!(Snapshot::get(&*parent, COL, b"b2"), Some(b"2".to_vec()));
+
+        parent.discard();
+        parent.merge().unwrap();
+        assert_eq!(view.get(COL, b"b2"), None);
+        assert_eq!(view.get(COL, b"b1"), Some(b"1".to_vec()));
+    }
+
+    #[test]
+    fn t_snapshot() {
+        let view = new_view();
+        view.put(COL, b"c1".to_vec(), b"1".to_vec());
+        view.put(COL, b"c2".to_vec(), b"2".to_vec());
+
+        let snapshot = view.snapshot();
+        view.put(COL, b"c1".to_vec(), b"11".to_vec());
+        view.delete(COL, b"c2".to_vec());
+        view.put(COL, b"c3".to_vec(), b"3".to_vec());
+
+        assert_eq!(snapshot.get(COL, b"c1"), Some(b"1".to_vec()));
+        assert_eq!(snapshot.get(COL, b"c2"), Some(b"2".to_vec()));
+        assert_eq!(snapshot.get(COL, b"c3"), None);
+        assert_eq!(collect(snapshot.iter(COL, b"c")), vec![
+            (b"c1".to_vec(), b"1".to_vec()),
+            (b"c2".to_vec(), b"2".to_vec()),
+        ]);
+
+        // speculative changes over the snapshot
+        let fork = Fork::new(snapshot.clone());
+        fork.put(COL, b"c4".to_vec(), b"4".to_vec());
+        assert_eq!(Snapshot::get(&fork, COL, b"c4"), Some(b"4".to_vec()));
+        assert!(fork.merge().is_err());
+        assert_eq!(view.get(COL, b"c4"), None);
+    }
+
+    #[test]
+    fn t_fork_snapshot() {
+        let view = new_view();
+        let fork = Arc::new(Fork::new(view.clone()));
+        fork.put(COL, b"d1".to_vec(), b"1".to_vec());
+        let frozen = View::from(fork.clone()).snapshot();
+        fork.put(COL, b"d1".to_vec(), b"11".to_vec());
+        fork.merge().unwrap();
+        view.put(COL, b"d2".to_vec(), b"2".to_vec());
+
+        assert_eq!(frozen.get(COL, b"d1"), Some(b"1".to_vec()));
+        assert_eq!(frozen.get(COL, b"d2"), None);
+        assert_eq!(view.get(COL, b"d1"), Some(b"11".to_vec()));
+    }
+
+    #[test]
+    fn t_columns() {
+        let config = DatabaseConfig::with_columns(Some(2));
+        let db = Arc::new(Database::open(&config, &random_dir()).unwrap());
+        let store = Arc::new(Store::with_columns(db, &["idx_a", "idx_b"]));
+        let view = View::from(store);
+        assert_eq!(view.column("idx_b"), Some(1));
+        assert_eq!(view.column("idx_c"), None);
+
+        let fork = Arc::new(Fork::new(view.clone()));
+        fork.put(Some(0), b"e1".to_vec(), b"a".to_vec());
+        fork.put(Some(1), b"e1".to_vec(), b"b".to_vec());
+        fork.put(Some(1), b"e2".to_vec(), b"b".to_vec());
+        assert_eq!(collect(Snapshot::iter(&*fork, Some(0), b"e")).len(), 1);
+        fork.merge().unwrap();
+
+        assert_eq!(view.get(Some(0), b"e1"), Some(b"a".to_vec()));
+        assert_eq!(view.get(Some(1), b"e1"), Some(b"b".to_vec()));
+        assert_eq!(view.get(COL, b"e1"), None);
+        assert_eq!(collect(view.iter(Some(1), b"e")).len(), 2);
+    }
+}
@@ -0,0 +1,133 @@
+use std::marker::PhantomData;
+
+use cryptocurrency_kit::crypto::{hash, Hash};
+use cryptocurrency_kit::storage::values::StorageValue;
+
+use super::base_index::{BaseIndex, BaseIndexIter, IndexType};
+use super::types::View;
+
+/// data format
+/// key: IDX_NAME + hash(value), value: value
+pub struct ValueSetIndex<V> {
+    base: BaseIndex,
+    _v: PhantomData<V>,
+}
+
+pub struct ValueSetIndexIter<'a, V> {
+    base_iter: BaseIndexIter<'a, Hash, V>,
+}
+
+pub struct ValueSetIndexHashes<'a> {
+    base_iter: BaseIndexIter<'a, Hash, Vec<u8>>,
+}
+
+impl<V> ValueSetIndex<V>
+where
+    V: StorageValue,
+{
+    pub fn new<S: AsRef<str>, T: Into<View>>(index_name: S, view: T) -> Self {
+        ValueSetIndex {
+            base: BaseIndex::new(index_name, IndexType::ValueSet, view),
+            _v: PhantomData,
+        }
+    }
+
+    pub fn contains(&self, item: &V) -> bool
+    where
+        V: Clone,
+    {
+        self.contains_by_hash(&hash(&item.clone().into_bytes()))
+    }
+
+    pub fn contains_by_hash(&self, hash: &Hash) -> bool {
+        self.base.contains(hash)
+    }
+
+    pub fn get_by_hash(&self, hash: &Hash) -> Option<V> {
+        self.base.get(hash)
+    }
+
+    /// iter returns the pairs of (hash, value) ordered by hash
+    pub fn iter(&self) -> ValueSetIndexIter<V> {
+        ValueSetIndexIter {
+            base_iter: self.base.iter(&()),
+        }
+    }
+
+    pub fn hashes(&self) -> ValueSetIndexHashes {
+        ValueSetIndexHashes {
+            base_iter: self.base.iter(&()),
+        }
+    }
+
+    //////////
+    pub fn insert(&mut self, item: V) {
+        let bytes = item.into_bytes();
+        self.base.put(&hash(&bytes), bytes)
+    }
+
+    pub fn remove(&mut self, item: &V)
+    where
+        V: Clone,
+    {
+        self.remove_by_hash(&hash(&item.clone().into_bytes()))
+    }
+
+    pub fn remove_by_hash(&mut self, hash: &Hash) {
+        self.base.remove(hash)
+    }
+
+    pub fn clear(&mut self) {
+        self.base.clear()
+    }
+}
+
+impl<'a, V> Iterator for ValueSetIndexIter<'a, V>
+where
+    V: StorageValue,
+{
+    type Item = (Hash, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.base_iter.next()
+    }
+}
+
+impl<'a> Iterator for ValueSetIndexHashes<'a> {
+    type Item = Hash;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.base_iter.next().map(|(k, ..)| k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::types::Store;
+    use std::sync::Arc;
+    use kvdb_rocksdb::Database;
+    use crate::common::random_dir;
+
+    #[test]
+    fn value_set() {
+        let db = Arc::new(Store::new(Arc::new(Database::open_default(&random_dir()).unwrap())));
+        let mut index: ValueSetIndex<String> = ValueSetIndex::new("IDX_NAME", db);
+        assert!(!index.contains(&"a".to_string()));
+
+        index.insert("a".to_string());
+        index.insert("b".to_string());
+        index.insert("a".to_string());
+        assert!(index.contains(&"a".to_string()));
+        assert_eq!(index.iter().count(), 2);
+
+        let a_hash = hash(&"a".to_string().into_bytes());
+        assert!(index.contains_by_hash(&a_hash));
+        assert_eq!(index.get_by_hash(&a_hash), Some("a".to_string()));
+        assert!(index.hashes().any(|hash| hash == a_hash));
+
+        index.remove(&"a".to_string());
+        assert!(!index.contains_by_hash(&a_hash));
+        assert_eq!(index.iter().map(|(.., v)| v).collect::<Vec<_>>(), vec!["b".to_string()]);
+    }
+}
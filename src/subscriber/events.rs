@@ -12,7 +12,6 @@ pub enum ChainEvent {
     NewBlock(Block),
     NewHeader(Header),
     SyncBlock(Height),
-    PostBlock(Option<PeerId>, Blocks),
 }
 
 // cross thread event
@@ -71,13 +70,14 @@ impl ChainEventSubscriber {
 
 use crate::types::transaction::Transaction;
 use crate::protocol::GossipMessage;
+use crate::p2p::protocol::RawMessage;
 
 #[derive(Message, Clone, Debug)]
 pub enum BroadcastEvent {
     Transaction(Transaction),
     Blocks(Option<PeerId>, Blocks),
     Consensus(GossipMessage),
-    Sync(Height),
+    Sync(RawMessage),
 }
 
 pub struct BroadcastEventSubscriber {
@@ -3,6 +3,8 @@ use ::actix::prelude::*;
 use libp2p::Multiaddr;
 use libp2p::PeerId;
 
+use crate::p2p::reputation::Misbehavior;
+
 pub mod events;
 
 use crate::types::block::{Block, Header};
@@ -12,6 +14,8 @@ use super::*;
 pub enum P2PEvent {
     AddPeer(PeerId, Vec<Multiaddr>),
     DropPeer(PeerId, Vec<Multiaddr>),
+    // the peer misbehaved, it's reported to the reputation of server
+    Misbehave(PeerId, Misbehavior),
 }
 
 impl_subscribe_handler! {P2PEvent}
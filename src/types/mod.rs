@@ -61,6 +61,10 @@ impl ValidatorArray {
     pub fn have(&self, address: &Address) -> bool {
         self.index.contains_key(address)
     }
+
+    pub fn addresses(&self) -> &Vec<Address> {
+        &self.inner
+    }
 }
 
 impl From<Vec<Validator>> for ValidatorArray {
@@ -9,8 +9,8 @@ use serde_json::to_string;
 use std::borrow::Cow;
 use std::io::Cursor;
 
-use crate::common::merkle_tree_root;
-use super::Gas;
+use crate::common::{merkle_tree, merkle_tree_root};
+use super::{Gas, Height};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Transaction {
@@ -170,8 +170,19 @@ impl TransactionSignature {
 }
 
 
-pub fn merkle_root_transactions(transactions: Vec<Transaction>) -> Hash {
-    merkle_tree_root(transactions)
+/// merkle_upgraded returns true if the transaction root of the block at the height is built by `common::merkle`
+pub fn merkle_upgraded(height: Height, upgrade_height: Option<Height>) -> bool {
+    upgrade_height.map_or(false, |upgrade_height| height >= upgrade_height)
+}
+
+/// merkle_root_transactions returns the transaction root of the block at the height, the root algorithm
+/// is switched at the upgrade height, the older blocks keep the root of cryptocurrency-kit
+pub fn merkle_root_transactions(transactions: Vec<Transaction>, height: Height, upgrade_height: Option<Height>) -> Hash {
+    if merkle_upgraded(height, upgrade_height) {
+        merkle_tree(transactions).root()
+    } else {
+        merkle_tree_root(transactions)
+    }
 }
 
 #[cfg(test)]
@@ -8,7 +8,6 @@ use crate::protocol::{GossipMessage, MessageType};
 
 const SIGN_OP_OFFSET: usize = 0;
 const SIGN_ROUND_OFFSET: usize = 1;
-const SIGN_PACKET_SIZE: usize = 9;
 
 use std::io::Cursor;
 use std::io::Write;
@@ -65,22 +64,20 @@ impl Votes {
     }
 }
 
+/// decrypt_commit_bytes recovers the signer of the commit seal, it is the reverse of `encrypt_commit_bytes`
 pub fn decrypt_commit_bytes<T: AsRef<[u8]>>(
-    input: T,
+    digest: T,
     signture: &Signature,
 ) -> Result<Address, String> {
-    let input = input.as_ref();
-    if input.len() != SIGN_PACKET_SIZE {
-        return Err("sign bytes size not equal SIGN_PACKET_SIZE".to_string());
+    let digest = digest.as_ref();
+    if digest.len() != HASH_SIZE {
+        return Err("commit digest size not equal HASH_SIZE".to_string());
     }
-    let op_code = MessageType::Commit;
-    let digest = hash(input);
     let mut input = Cursor::new(vec![0_u8; 1 + HASH_SIZE]);
-    input.write_u8(op_code as u8).unwrap();
-    input.write_all(digest.as_ref()).unwrap();
+    input.write_u8(MessageType::Commit as u8).unwrap();
+    input.write_all(digest).unwrap();
     let buffer = input.into_inner();
-    let digest: Hash = hash(buffer);
-    match recover_bytes(signture, digest.as_ref()) {
+    match recover_bytes(signture, &buffer) {
         Ok(ref public) => {
             let address = public_to_address(public);
             return Ok(address);
@@ -108,6 +105,16 @@ mod tests{
     use cryptocurrency_kit::ethkey::Generator;
     use cryptocurrency_kit::ethkey::Random;
 
+    #[test]
+    fn t_commit_seal() {
+        let keypair = Random{}.generate().unwrap();
+        let digest = hash(vec![1_u8, 2, 3]);
+        let seal = encrypt_commit_bytes(&digest, keypair.secret());
+        assert_eq!(decrypt_commit_bytes(&digest, &seal).unwrap(), keypair.address());
+        assert_ne!(decrypt_commit_bytes(&hash(vec![1_u8]), &seal).unwrap(), keypair.address());
+        assert!(decrypt_commit_bytes(vec![1_u8, 2], &seal).is_err());
+    }
+
     #[test]
     fn t_random() {
         (0..10).for_each(|_|{